@@ -48,6 +48,24 @@ macro_rules! dbg_vs_println {
 #[doc(hidden)]
 pub fn _nothing() {}
 
+/// Prints to VGA and serial like `vs_print`, unless either is locked. Returns whether it printed.
+/// For reporting panics, where the lock holder may never get to unlock.
+pub fn try_print(fmt: core::fmt::Arguments) -> bool {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    without_interrupts(
+        || match (vga::WRITER.try_lock(), serial::SERIAL1.try_lock()) {
+            (Some(mut writer), Some(mut serial)) => {
+                let _ = writer.write_fmt(fmt);
+                let _ = serial.write_fmt(fmt);
+                true
+            }
+            _ => false,
+        },
+    )
+}
+
 #[doc(hidden)]
 pub fn _print(fmt: core::fmt::Arguments) {
     vga::_print(fmt);
@@ -1,3 +1,3 @@
 pub mod io;
 pub mod qemu;
-// pub mod rtc;
+pub mod rtc;
@@ -1,9 +1,245 @@
-const days_in_month: [u8; 12] = [
-    31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31
-];
+use core::convert::TryFrom;
+use core::fmt::Display;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+/*
+CMOS real-time clock. The RTC keeps the date and time in battery backed CMOS registers, in BCD or
+binary and with a 12 or 24 hour clock as status register B says. It updates them once a second, so
+they are read while no update is in progress, and again until two reads agree. The time is taken
+as UTC.
+ */
+
+const CMOS_SELECT: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+// CMOS registers.
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+/// Not standard, but where QEMU and most firmware keep it.
+const CENTURY: u8 = 0x32;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+
+/// Set in status register A while the RTC updates its registers.
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Set in status register B if hours count to 24.
+const HOURS_24: u8 = 1 << 1;
+/// Set in status register B if values are binary rather than BCD.
+const BINARY: u8 = 1 << 2;
+/// Set in the hours of a 12 hour clock after noon.
+const PM: u8 = 1 << 7;
+
+/// First year a `DateTime` can hold, that of the Unix epoch.
+pub const EPOCH_YEAR: u16 = 1970;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+/// Days in 400 years, after which leap years repeat.
+const DAYS_PER_CYCLE: u64 = 400 * 365 + 97;
+
+const DAYS_IN_MONTH: [u8; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
 
-const ydays: [[u16; 13]; 2] = [
-    [ 0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334, 365 ],
-    [ 0, 31, 60, 91, 121, 152, 182, 213, 244, 274, 305, 335, 366 ]
+/// Days before each month, in common and in leap years.
+const YDAYS: [[u16; 13]; 2] = [
+    [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334, 365],
+    [0, 31, 60, 91, 121, 152, 182, 213, 244, 274, 305, 335, 366],
 ];
 
+/// A date and time of day, in UTC. Only built through `new` and `from_timestamp`, so it always
+/// holds a valid date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+}
+
+impl DateTime {
+    /// Returns `None` if the date does not exist or lies before `EPOCH_YEAR`.
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<Self> {
+        let valid = year >= EPOCH_YEAR
+            && (1..=12).contains(&month)
+            && day >= 1
+            && day <= days_in_month(year, month)
+            && hour < 24
+            && minute < 60
+            && second < 60;
+        if !valid {
+            return None;
+        }
+        Some(Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    /// The date and time `timestamp` seconds after the Unix epoch. Returns `None` past the last
+    /// second of year `u16::MAX`.
+    pub fn from_timestamp(timestamp: u64) -> Option<Self> {
+        let mut days = timestamp / SECONDS_PER_DAY;
+        let time = timestamp % SECONDS_PER_DAY;
+
+        // whole 400 year cycles first, so at most 400 years are counted one by one.
+        let cycles = days / DAYS_PER_CYCLE;
+        days %= DAYS_PER_CYCLE;
+        let mut year = u16::try_from(u64::from(EPOCH_YEAR) + cycles * 400).ok()?;
+        while days >= days_in_year(year) {
+            days -= days_in_year(year);
+            year = year.checked_add(1)?;
+        }
+        let ydays = &YDAYS[usize::from(is_leap(year))];
+        // the days before a month are less than those before the next.
+        let month = (1..12)
+            .find(|&month| days < u64::from(ydays[month]))
+            .unwrap_or(12);
+
+        Some(Self {
+            year,
+            month: month as u8,
+            day: (days - u64::from(ydays[month - 1])) as u8 + 1,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        })
+    }
+
+    /// Seconds since the Unix epoch.
+    pub fn timestamp(&self) -> u64 {
+        let days = days_before(self.year) - days_before(EPOCH_YEAR)
+            + u64::from(YDAYS[usize::from(is_leap(self.year))][usize::from(self.month - 1)])
+            + u64::from(self.day - 1);
+        days * SECONDS_PER_DAY
+            + u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second)
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    /// From 1 to 12.
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    /// From 1.
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+}
+
+/// ISO 8601, e.g. `2024-02-29 13:05:09`.
+impl Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Returns true if February of `year` has 29 days.
+pub fn is_leap(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// Number of days in `month` of `year`, `month` from 1 to 12.
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap(year) => 29,
+        1..=12 => DAYS_IN_MONTH[usize::from(month - 1)],
+        _ => 0,
+    }
+}
+
+fn days_in_year(year: u16) -> u64 {
+    u64::from(YDAYS[usize::from(is_leap(year))][12])
+}
+
+/// Days from the start of year 1 to the start of `year`.
+fn days_before(year: u16) -> u64 {
+    let years = u64::from(year.saturating_sub(1));
+    years * 365 + years / 4 - years / 100 + years / 400
+}
+
+/// Reads the date and time from the RTC. Returns `None` if the registers hold no valid date.
+pub fn read() -> Option<DateTime> {
+    let mut last = read_registers();
+    loop {
+        let registers = read_registers();
+        if registers == last {
+            break;
+        }
+        last = registers;
+    }
+    let [second, minute, hour, day, month, year, century] = last;
+    let status = read_register(STATUS_B);
+
+    let decode = |value: u8| match status & BINARY {
+        0 => (value >> 4) * 10 + (value & 0x0f),
+        _ => value,
+    };
+    let hour = match status & HOURS_24 {
+        0 => decode(hour & !PM) % 12 + if hour & PM != 0 { 12 } else { 0 },
+        _ => decode(hour),
+    };
+    // without a century register the RTC is assumed to be in this one.
+    let century = match decode(century) {
+        19..=21 => u16::from(decode(century)),
+        _ => 20,
+    };
+
+    DateTime::new(
+        century * 100 + u16::from(decode(year)),
+        decode(month),
+        decode(day),
+        hour,
+        decode(minute),
+        decode(second),
+    )
+}
+
+/// The raw date and time registers, read between two updates.
+fn read_registers() -> [u8; 7] {
+    while read_register(STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    let mut registers = [SECONDS, MINUTES, HOURS, DAY, MONTH, YEAR, CENTURY];
+    for register in registers.iter_mut() {
+        *register = read_register(*register);
+    }
+    registers
+}
+
+fn read_register(register: u8) -> u8 {
+    // the select and data port must not be interleaved with another access.
+    without_interrupts(|| unsafe {
+        Port::<u8>::new(CMOS_SELECT).write(register);
+        Port::<u8>::new(CMOS_DATA).read()
+    })
+}
@@ -0,0 +1,204 @@
+use crate::kernel::mem::vmm;
+use alloc::vec::Vec;
+use x86_64::{PhysAddr, VirtAddr};
+
+/*
+ACPI tables. The RSDP is searched for where the BIOS leaves it, in the first KiB of the EBDA or in
+the BIOS ROM area, and the tables it leads to are read through the bootloader's physical memory
+mapping, which covers them as they lie in RAM. Only the tables the kernel uses are parsed, the MADT
+for the interrupt controllers and the HPET table for the clock.
+ */
+
+/// Size of the header every system description table starts with.
+const HEADER_SIZE: usize = 36;
+
+/// Where the BIOS keeps the segment of the EBDA.
+const EBDA_POINTER: u64 = 0x40e;
+/// The BIOS ROM area searched for the RSDP after the EBDA.
+const BIOS_AREA: (u64, u64) = (0xe_0000, 0x10_0000);
+
+/// Errors when reading the ACPI tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// No valid RSDP was found.
+    NoRsdp,
+    /// There is no table with the signature.
+    NotFound,
+    /// A table has a bad checksum or is too short.
+    Invalid,
+    /// Memory is not initialized yet.
+    Uninitialized,
+}
+
+/// An IO APIC as described by the MADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicInfo {
+    pub id: u8,
+    /// Physical address of its registers.
+    pub address: PhysAddr,
+    /// First global system interrupt it handles.
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ that differs from the default of arriving on the global system interrupt of the
+/// same number, edge triggered and active high.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    /// The ISA IRQ.
+    pub irq: u8,
+    pub gsi: u32,
+    /// MPS INTI flags: polarity in bits 0 and 1, trigger mode in bits 2 and 3.
+    pub flags: u16,
+}
+
+impl InterruptOverride {
+    /// Returns true if the interrupt is active low.
+    pub fn active_low(&self) -> bool {
+        self.flags & 0b11 == 0b11
+    }
+
+    /// Returns true if the interrupt is level triggered.
+    pub fn level_triggered(&self) -> bool {
+        (self.flags >> 2) & 0b11 == 0b11
+    }
+}
+
+/// The interrupt controllers listed in the MADT.
+#[derive(Debug, Clone)]
+pub struct Madt {
+    /// Physical address of the local APIC registers.
+    pub local_apic: PhysAddr,
+    /// Set if the system also has 8259 PICs, which must be masked when the APICs are used.
+    pub legacy_pics: bool,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+/// Reads the MADT.
+pub fn madt() -> Result<Madt, AcpiError> {
+    let offset = physical_offset()?;
+    let table = find_table(b"APIC")?.as_u64();
+    let length = unsafe { read::<u32>(offset, table + 4) } as u64;
+    if length < HEADER_SIZE as u64 + 8 {
+        return Err(AcpiError::Invalid);
+    }
+
+    let mut madt = Madt {
+        local_apic: PhysAddr::new(unsafe { read::<u32>(offset, table + 36) }.into()),
+        legacy_pics: unsafe { read::<u32>(offset, table + 40) } & 1 != 0,
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    let mut entry = table + HEADER_SIZE as u64 + 8;
+    let end = table + length;
+    while entry + 2 <= end {
+        let (kind, size) = unsafe { (read::<u8>(offset, entry), read::<u8>(offset, entry + 1)) };
+        if size < 2 || entry + u64::from(size) > end {
+            return Err(AcpiError::Invalid);
+        }
+        unsafe {
+            match kind {
+                1 if size >= 12 => madt.io_apics.push(IoApicInfo {
+                    id: read(offset, entry + 2),
+                    address: PhysAddr::new(read::<u32>(offset, entry + 4).into()),
+                    gsi_base: read(offset, entry + 8),
+                }),
+                2 if size >= 10 => madt.overrides.push(InterruptOverride {
+                    irq: read(offset, entry + 3),
+                    gsi: read(offset, entry + 4),
+                    flags: read(offset, entry + 8),
+                }),
+                // a 64 bit local APIC address.
+                5 if size >= 12 => madt.local_apic = PhysAddr::new(read(offset, entry + 4)),
+                _ => {}
+            }
+        }
+        entry += u64::from(size);
+    }
+
+    Ok(madt)
+}
+
+/// Physical address of the HPET registers, from the HPET table.
+pub fn hpet() -> Result<PhysAddr, AcpiError> {
+    let offset = physical_offset()?;
+    let table = find_table(b"HPET")?.as_u64();
+    let length = unsafe { read::<u32>(offset, table + 4) } as u64;
+    // the registers must lie in memory space, not port space.
+    if length < HEADER_SIZE as u64 + 20 || unsafe { read::<u8>(offset, table + 40) } != 0 {
+        return Err(AcpiError::Invalid);
+    }
+    Ok(PhysAddr::new(unsafe { read(offset, table + 44) }))
+}
+
+/// Physical address of the table with `signature`.
+pub fn find_table(signature: &[u8; 4]) -> Result<PhysAddr, AcpiError> {
+    let offset = physical_offset()?;
+    let rsdp = find_rsdp(offset).ok_or(AcpiError::NoRsdp)?;
+
+    // revision 2 and later point at the XSDT with 64 bit entries.
+    let (root, entry_size) = unsafe {
+        match read::<u8>(offset, rsdp + 15) {
+            0 => (u64::from(read::<u32>(offset, rsdp + 16)), 4),
+            _ => (read::<u64>(offset, rsdp + 24), 8),
+        }
+    };
+    let length = unsafe { checked_table(offset, root) }?;
+
+    let entries = (length - HEADER_SIZE as u64) / entry_size;
+    for i in 0..entries {
+        let entry = root + HEADER_SIZE as u64 + i * entry_size;
+        let table = unsafe {
+            match entry_size {
+                4 => u64::from(read::<u32>(offset, entry)),
+                _ => read::<u64>(offset, entry),
+            }
+        };
+        let found = unsafe { read::<[u8; 4]>(offset, table) } == *signature;
+        if found {
+            unsafe { checked_table(offset, table) }?;
+            return Ok(PhysAddr::new(table));
+        }
+    }
+
+    Err(AcpiError::NotFound)
+}
+
+/// Start of the physical memory mapping.
+fn physical_offset() -> Result<VirtAddr, AcpiError> {
+    let mut vmm = vmm::vmm().ok_or(AcpiError::Uninitialized)?;
+    Ok(vmm.page_table().phys_offset())
+}
+
+/// Physical address of the RSDP, found by its signature and checksum on a 16 byte boundary.
+fn find_rsdp(offset: VirtAddr) -> Option<u64> {
+    let ebda = u64::from(unsafe { read::<u16>(offset, EBDA_POINTER) }) << 4;
+    let areas = [(ebda, ebda + 1024), BIOS_AREA];
+    areas
+        .iter()
+        .filter(|(start, _)| *start != 0)
+        .flat_map(|&(start, end)| (start..end).step_by(16))
+        .find(|&addr| unsafe {
+            read::<[u8; 8]>(offset, addr) == *b"RSD PTR " && checksum(offset, addr, 20) == 0
+        })
+}
+
+/// Checks the table header at `table` and returns the length of the table.
+unsafe fn checked_table(offset: VirtAddr, table: u64) -> Result<u64, AcpiError> {
+    let length = u64::from(read::<u32>(offset, table + 4));
+    if length < HEADER_SIZE as u64 || checksum(offset, table, length) != 0 {
+        return Err(AcpiError::Invalid);
+    }
+    Ok(length)
+}
+
+/// Sum of the `len` bytes at `addr`, zero for valid tables.
+unsafe fn checksum(offset: VirtAddr, addr: u64, len: u64) -> u8 {
+    (addr..addr + len).fold(0, |sum, addr| sum.wrapping_add(read::<u8>(offset, addr)))
+}
+
+/// Reads a `T` from physical address `addr`, which the tables do not align.
+unsafe fn read<T: Copy>(offset: VirtAddr, addr: u64) -> T {
+    (offset + addr).as_ptr::<T>().read_unaligned()
+}
@@ -0,0 +1,191 @@
+use crate::kernel::acpi;
+use crate::kernel::interrupts::pit;
+use crate::kernel::mem::frame::FRAME_SIZE;
+use crate::kernel::mem::vmm;
+use crate::kernel::sc::SYSTEM_CLOCK;
+use conquer_once::spin::OnceCell;
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/*
+Monotonic nanosecond clock. `init` picks a free running counter and its frequency: the TSC if the
+CPU promises it runs at a constant rate, otherwise the HPET if ACPI lists one, otherwise the TSC
+anyway. The TSC frequency is measured against the PIT, and a TSC that does not count there, as
+some virtual machines have, is passed over for the HPET or, as a last resort, the PIT cycles the
+timer interrupt counts. Counter values are turned into nanoseconds with a fixed point multiplier,
+so reading the clock takes no division.
+ */
+
+/// Cycles of the PIT input clock the TSC is measured over, 50 ms.
+const CALIBRATION_COUNT: u16 = (pit::BASE_FREQUENCY / 20) as u16;
+
+// HPET registers, as offsets from its base.
+const HPET_CAPABILITIES: u64 = 0x000;
+const HPET_CONFIG: u64 = 0x010;
+const HPET_COUNTER: u64 = 0x0f0;
+/// Set in the capabilities if the main counter has 64 bits.
+const HPET_COUNT_64: u64 = 1 << 13;
+/// Set in the configuration to start the main counter.
+const HPET_ENABLE: u64 = 1 << 0;
+/// Femtoseconds per nanosecond, the HPET states its period in femtoseconds.
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+
+/// The counter the clock reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The time stamp counter of the CPU.
+    Tsc,
+    /// The main counter of the high precision event timer.
+    Hpet,
+    /// The PIT input clock cycles counted by the timer interrupt, only as fine as its ticks.
+    Pit,
+}
+
+struct Clock {
+    source: Source,
+    /// Where the HPET registers are mapped, if it is the source.
+    hpet: VirtAddr,
+    /// Counter increments per second.
+    frequency: u64,
+    /// Nanoseconds per increment, shifted left by 32.
+    multiplier: u64,
+    /// Counter value when the clock started.
+    start: u64,
+}
+
+impl Clock {
+    fn new(source: Source, hpet: VirtAddr, frequency: u64, multiplier: u64) -> Self {
+        let mut clock = Self {
+            source,
+            hpet,
+            frequency,
+            multiplier,
+            start: 0,
+        };
+        clock.start = clock.read();
+        clock
+    }
+
+    fn read(&self) -> u64 {
+        match self.source {
+            Source::Tsc => unsafe { _rdtsc() },
+            Source::Hpet => unsafe { read_hpet(self.hpet, HPET_COUNTER) },
+            Source::Pit => SYSTEM_CLOCK.cycles(),
+        }
+    }
+
+    fn nanos(&self) -> u64 {
+        let elapsed = self.read().wrapping_sub(self.start);
+        ((u128::from(elapsed) * u128::from(self.multiplier)) >> 32) as u64
+    }
+}
+
+static CLOCK: OnceCell<Clock> = OnceCell::uninit();
+
+/// Starts the clock. Needs `mem_init` to map the HPET.
+pub fn init() {
+    CLOCK.init_once(|| {
+        let hpet_first = !invariant_tsc();
+        if hpet_first {
+            if let Some(clock) = hpet() {
+                return clock;
+            }
+        }
+        match tsc_frequency() {
+            0 if hpet_first => pit_clock(),
+            0 => hpet().unwrap_or_else(pit_clock),
+            frequency => Clock::new(
+                Source::Tsc,
+                VirtAddr::zero(),
+                frequency,
+                multiplier(frequency),
+            ),
+        }
+    });
+}
+
+/// Nanoseconds since `init`, zero before.
+pub fn nanos() -> u64 {
+    CLOCK.get().map_or(0, Clock::nanos)
+}
+
+/// The counter the clock reads, `None` before `init`.
+pub fn source() -> Option<Source> {
+    CLOCK.get().map(|clock| clock.source)
+}
+
+/// Increments per second of the counter the clock reads, `None` before `init`.
+pub fn frequency() -> Option<u64> {
+    CLOCK.get().map(|clock| clock.frequency)
+}
+
+/// Returns true if the TSC runs at the same rate in every power state.
+// `__cpuid` is only safe to call on newer compilers.
+#[allow(unused_unsafe)]
+fn invariant_tsc() -> bool {
+    unsafe { __cpuid(0x8000_0000).eax >= 0x8000_0007 && __cpuid(0x8000_0007).edx & 1 << 8 != 0 }
+}
+
+/// Measures the TSC frequency in Hz against the PIT.
+fn tsc_frequency() -> u64 {
+    let cycles = without_interrupts(|| {
+        let start = unsafe { _rdtsc() };
+        pit::wait(CALIBRATION_COUNT);
+        unsafe { _rdtsc() }.wrapping_sub(start)
+    });
+    cycles * u64::from(pit::BASE_FREQUENCY) / u64::from(CALIBRATION_COUNT)
+}
+
+/// Nanoseconds per increment of a counter running at `frequency` Hz, shifted left by 32.
+fn multiplier(frequency: u64) -> u64 {
+    ((1_000_000_000u128 << 32) / u128::from(frequency)) as u64
+}
+
+/// A clock on the PIT cycles the timer interrupt counts.
+fn pit_clock() -> Clock {
+    let frequency = u64::from(pit::BASE_FREQUENCY);
+    Clock::new(
+        Source::Pit,
+        VirtAddr::zero(),
+        frequency,
+        multiplier(frequency),
+    )
+}
+
+/// Maps and starts the HPET, if there is one with a 64 bit counter.
+fn hpet() -> Option<Clock> {
+    let address = acpi::hpet().ok()?;
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::NO_EXECUTE;
+    let base = vmm::vmm()?
+        .map_physical("hpet", address, 0x400, flags)
+        .ok()?;
+
+    let capabilities = unsafe { read_hpet(base, HPET_CAPABILITIES) };
+    let period = capabilities >> 32;
+    // a 32 bit counter wraps within minutes.
+    if capabilities & HPET_COUNT_64 == 0 || period == 0 {
+        let _ = vmm::vmm()?.unmap(base.align_down(FRAME_SIZE));
+        return None;
+    }
+    unsafe {
+        let config = read_hpet(base, HPET_CONFIG);
+        write_hpet(base, HPET_CONFIG, config | HPET_ENABLE);
+    }
+
+    let frequency = FEMTOS_PER_NANO * 1_000_000_000 / period;
+    let multiplier = ((u128::from(period) << 32) / u128::from(FEMTOS_PER_NANO)) as u64;
+    Some(Clock::new(Source::Hpet, base, frequency, multiplier))
+}
+
+unsafe fn read_hpet(base: VirtAddr, register: u64) -> u64 {
+    (base + register).as_ptr::<u64>().read_volatile()
+}
+
+unsafe fn write_hpet(base: VirtAddr, register: u64, value: u64) {
+    (base + register).as_mut_ptr::<u64>().write_volatile(value)
+}
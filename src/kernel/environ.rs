@@ -11,7 +11,7 @@ mod public {
 
     use crate::kernel::status::Status;
 
-    use super::{ENVIRON, Key};
+    use super::{Environment, ENVIRON, Key};
 
     pub struct EnvironmentRef;
 
@@ -47,6 +47,11 @@ mod public {
         pub fn keys(&self) -> Vec<Key> {
             ENVIRON.lock().keys().clone()
         }
+
+        /// A copy of the whole environment, e.g. for a new process to start from.
+        pub fn snapshot(&self) -> Environment {
+            ENVIRON.lock().clone()
+        }
     }
 
     pub fn environmentref() -> EnvironmentRef {
@@ -82,8 +87,8 @@ impl Display for Key {
 }
 
 #[repr(transparent)]
-#[derive(Debug, Clone)]
-struct Environment(Vec<Key>);
+#[derive(Debug, Clone, Default)]
+pub struct Environment(Vec<Key>);
 
 impl Environment {
     pub fn new() -> Environment {
@@ -54,9 +54,13 @@ impl FileSystem for BTFS {
 
     fn map(&self) -> Option<ImapRef> {
         let cwd = EnvironmentRef::new().cwd();
+        self.list(&cwd)
+    }
+
+    fn list(&self, dir: &str) -> Option<ImapRef> {
         let mut map: BTreeMap<u16, FileDescriptor<NodeIdent>> = BTreeMap::new();
 
-        if cwd == "/" {
+        if dir == "/" {
             let root_node = &self.imap[&0];
             if let Node::Directory(_, ref children) = root_node {
                 for (child_id, child_fd) in children {
@@ -64,13 +68,9 @@ impl FileSystem for BTFS {
                 }
             } 
         } else {
-            let path = if cwd.ends_with("/") {
-                let mut tmp = cwd;
-                tmp.remove(tmp.len()-1);
-                tmp
-            } else { cwd };
+            let path = dir.strip_suffix('/').unwrap_or(dir);
 
-            let id = self.find_name(&path)?;
+            let id = self.find_name(path)?;
             let node = &self.imap[&id];
             if let Node::Directory(_, ref children) = node {
                 for (child_id, child_fd) in children {
@@ -152,4 +152,18 @@ impl FileSystem for BTFS {
             }
         }
     }
+
+    fn read(&self, ident: u16) -> Option<Vec<u8>> {
+        match self.imap.get(&ident)? {
+            Node::File(_, data) => Some(data.clone()),
+            Node::Directory(_, _) => None,
+        }
+    }
+
+    fn write(&mut self, ident: u16, data: &[u8]) -> Option<usize> {
+        let file = self.imap.get_mut(&ident)?.data()?;
+        file.clear();
+        file.extend_from_slice(data);
+        Some(data.len())
+    }
 }
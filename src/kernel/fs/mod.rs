@@ -3,7 +3,7 @@ mod btfs;
 pub mod public;
 pub use public::*;
 
-use alloc::{string::String, collections::BTreeMap};
+use alloc::{string::String, collections::BTreeMap, vec::Vec};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
@@ -58,6 +58,8 @@ pub trait FileSystem {
 
     fn map(&self) -> Option<ImapRef>;
 
+    fn list(&self, dir: &str) -> Option<ImapRef>;
+
     fn create_file(&mut self, name: &str) -> Option<FileDescriptor<Identifier>>;
 
     fn create_dir(&mut self, name: &str) -> Option<FileDescriptor<Identifier>>;
@@ -65,6 +67,10 @@ pub trait FileSystem {
     fn open(&mut self, ident: Identifier) -> Option<FileDescriptor<Identifier>>;
 
     fn size(&self, ident: u16) -> Option<usize>;
+
+    fn read(&self, ident: u16) -> Option<Vec<u8>>;
+
+    fn write(&mut self, ident: u16, data: &[u8]) -> Option<usize>;
 }
 
 pub trait Inode {
@@ -1,4 +1,5 @@
-use super::{FileSystem, Identifier, ImapRef, FILESYSTEM, FileDescriptor};
+use super::{FileDescriptor, FileSystem, Identifier, ImapRef, FILESYSTEM};
+use alloc::vec::Vec;
 
 pub struct FileSyetemRef;
 
@@ -11,6 +12,11 @@ impl FileSyetemRef {
         super::FILESYSTEM.lock().map()
     }
 
+    /// The entries of the directory `dir`, by full path.
+    pub fn list(&self, dir: &str) -> Option<ImapRef> {
+        FILESYSTEM.lock().list(dir)
+    }
+
     pub fn create_file(&self, name: &str) -> Option<FileDescriptor<Identifier>> {
         FILESYSTEM.lock().create_file(name)
     }
@@ -26,6 +32,16 @@ impl FileSyetemRef {
     pub fn size(&self, ident: u16) -> Option<usize> {
         FILESYSTEM.lock().size(ident)
     }
+
+    /// Returns a copy of the contents of a file.
+    pub fn read(&self, ident: u16) -> Option<Vec<u8>> {
+        FILESYSTEM.lock().read(ident)
+    }
+
+    /// Replaces the contents of a file with `data`, returns the number of bytes written.
+    pub fn write(&self, ident: u16, data: &[u8]) -> Option<usize> {
+        FILESYSTEM.lock().write(ident, data)
+    }
 }
 
 pub fn filesystemref() -> FileSyetemRef {
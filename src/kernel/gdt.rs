@@ -1,5 +1,8 @@
+use crate::kernel::mem::stack::{KernelStack, DEFAULT_STACK_SIZE};
+use core::ptr::{addr_of, addr_of_mut};
 use lazy_static::lazy_static;
-use x86_64::instructions::segmentation::{Segment, CS};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
 use x86_64::{
     instructions::tables::load_tss,
     structures::{
@@ -11,33 +14,45 @@ use x86_64::{
 
 /*
 Defines the General Descriptor Table.
+
+The interrupt stacks start out on small static stacks so exceptions work before memory is
+initialized. `init_stacks` moves them to guard paged kernel stacks once the VMM is up.
  */
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
 
-lazy_static! {
-    static ref TTS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            stack_start + STACK_SIZE // stack end
-        };
-        tss
-    };
-}
+/// Interrupt stacks in use, indexed by IST index.
+const IST_STACKS: [(u16, &str); 3] = [
+    (DOUBLE_FAULT_IST_INDEX, "double fault stack"),
+    (NMI_IST_INDEX, "nmi stack"),
+    (MACHINE_CHECK_IST_INDEX, "machine check stack"),
+];
+
+const BOOT_STACK_SIZE: usize = 4096 * 5;
+static mut BOOT_STACKS: [[u8; BOOT_STACK_SIZE]; IST_STACKS.len()] =
+    [[0; BOOT_STACK_SIZE]; IST_STACKS.len()];
+
+/// The CPU reads interrupt stacks from here on every IST switch, so entries can be replaced after
+/// the TSS is loaded.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TTS));
+        let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*addr_of!(TSS) }));
         (
             gdt,
             Selectors {
                 code_selector,
+                data_selector,
+                user_code_selector,
+                user_data_selector,
                 tss_selector,
             },
         )
@@ -46,15 +61,64 @@ lazy_static! {
 
 struct Selectors {
     code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
     tss_selector: SegmentSelector,
 }
 
 /// initiates GDT, setting it in CPU register.
 pub fn init() {
+    for (index, _) in IST_STACKS {
+        let stack = unsafe { addr_of!(BOOT_STACKS[index as usize]) };
+        set_interrupt_stack(index, VirtAddr::from_ptr(stack) + BOOT_STACK_SIZE);
+    }
+
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
         // set_cs(GDT.1.code_selector);
+        SS::set_reg(GDT.1.data_selector);
+        DS::set_reg(GDT.1.data_selector);
+        ES::set_reg(GDT.1.data_selector);
         load_tss(GDT.1.tss_selector);
     }
 }
+
+/// Replaces the boot interrupt stacks with guard paged kernel stacks. Needs `mem_init`.
+pub fn init_stacks() {
+    for (index, name) in IST_STACKS {
+        let stack = KernelStack::allocate(name, DEFAULT_STACK_SIZE)
+            .expect("failed to allocate interrupt stack");
+        set_interrupt_stack(index, stack.top());
+    }
+}
+
+/// Points interrupt stack table entry `index` at the stack ending at `top`.
+pub fn set_interrupt_stack(index: u16, top: VirtAddr) {
+    without_interrupts(|| unsafe {
+        (*addr_of_mut!(TSS)).interrupt_stack_table[index as usize] = top;
+    });
+}
+
+/// Points the stack the CPU switches to when an interrupt arrives in user mode at `top`.
+pub fn set_kernel_stack(top: VirtAddr) {
+    without_interrupts(|| unsafe {
+        (*addr_of_mut!(TSS)).privilege_stack_table[0] = top;
+    });
+}
+
+/// Stack the CPU switches to when an interrupt arrives in user mode.
+pub fn kernel_stack() -> VirtAddr {
+    unsafe { (*addr_of!(TSS)).privilege_stack_table[0] }
+}
+
+/// Kernel code and data segment selectors.
+pub fn kernel_selectors() -> (SegmentSelector, SegmentSelector) {
+    (GDT.1.code_selector, GDT.1.data_selector)
+}
+
+/// User code and data segment selectors, with requested privilege level 3.
+pub fn user_selectors() -> (SegmentSelector, SegmentSelector) {
+    (GDT.1.user_code_selector, GDT.1.user_data_selector)
+}
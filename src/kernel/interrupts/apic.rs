@@ -0,0 +1,260 @@
+use super::{irq, pic};
+use crate::kernel::acpi::{self, AcpiError, InterruptOverride};
+use crate::kernel::mem::vmm::{self, VmError};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/*
+Local APIC and IO APIC. When the MADT lists them, `init` routes the legacy IRQs through the IO APIC
+handling them to the vectors the PICs used, so the handlers stay the same, and masks the PICs. From
+then on interrupts end at the local APIC instead. Without an APIC the PICs stay in charge.
+ */
+
+/// Vector of spurious interrupts from the local APIC, which take no end of interrupt.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// The local APIC base MSR.
+const IA32_APIC_BASE: u32 = 0x1b;
+/// Set in `IA32_APIC_BASE` to enable the local APIC.
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+
+// local APIC registers, as offsets from its base.
+const LAPIC_ID: u64 = 0x20;
+const LAPIC_TASK_PRIORITY: u64 = 0x80;
+const LAPIC_EOI: u64 = 0xb0;
+const LAPIC_SPURIOUS: u64 = 0xf0;
+const LAPIC_LVT_TIMER: u64 = 0x320;
+/// Set in the spurious interrupt register to enable the local APIC.
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+
+// IO APIC registers, selected through `IOREGSEL` and accessed through `IOWIN`.
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION: u32 = 0x10;
+
+/// Set in LVT and redirection entries to mask the interrupt.
+const MASKED: u32 = 1 << 16;
+/// Set in redirection entries of active low interrupts.
+const ACTIVE_LOW: u32 = 1 << 13;
+/// Set in redirection entries of level triggered interrupts.
+const LEVEL_TRIGGERED: u32 = 1 << 15;
+
+/// Errors when switching to the APICs.
+#[derive(Debug)]
+pub enum ApicError {
+    /// The MADT could not be read.
+    Acpi(AcpiError),
+    /// No IO APIC handles the legacy IRQs.
+    NoIoApic,
+    /// Mapping the registers failed.
+    Memory(VmError),
+}
+
+impl From<VmError> for ApicError {
+    fn from(error: VmError) -> Self {
+        ApicError::Memory(error)
+    }
+}
+
+/// Virtual address of the local APIC registers, zero while the PICs are in use.
+static LOCAL_APIC: AtomicU64 = AtomicU64::new(0);
+/// The IO APIC the legacy IRQs are routed through, set by `init`.
+static IO_APIC: Mutex<Option<IoApic>> = Mutex::new(None);
+
+/// An IO APIC and the overrides of the legacy IRQs it handles.
+#[derive(Debug)]
+struct IoApic {
+    base: VirtAddr,
+    /// The local APIC interrupts are sent to.
+    apic_id: u8,
+    /// First global system interrupt it handles.
+    gsi_base: u32,
+    /// Number of redirection entries.
+    entries: u32,
+    overrides: Vec<InterruptOverride>,
+}
+
+impl IoApic {
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            (self.base + IOREGSEL)
+                .as_mut_ptr::<u32>()
+                .write_volatile(register);
+            (self.base + IOWIN).as_ptr::<u32>().read_volatile()
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        unsafe {
+            (self.base + IOREGSEL)
+                .as_mut_ptr::<u32>()
+                .write_volatile(register);
+            (self.base + IOWIN)
+                .as_mut_ptr::<u32>()
+                .write_volatile(value);
+        }
+    }
+
+    /// The redirection entry of `gsi`, `None` if it is not handled here.
+    fn redirection(&self, gsi: u32) -> Option<u64> {
+        let entry = gsi
+            .checked_sub(self.gsi_base)
+            .filter(|e| *e < self.entries)?;
+        let register = IOAPIC_REDIRECTION + entry * 2;
+        let low = u64::from(self.read(register));
+        let high = u64::from(self.read(register + 1));
+        Some(high << 32 | low)
+    }
+
+    /// Sets the redirection entry of `gsi`. Returns false if it is not handled here.
+    fn set_redirection(&mut self, gsi: u32, value: u64) -> bool {
+        let entry = match gsi.checked_sub(self.gsi_base).filter(|e| *e < self.entries) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let register = IOAPIC_REDIRECTION + entry * 2;
+        // masked while the entry is half written.
+        self.write(register, MASKED);
+        self.write(register + 1, (value >> 32) as u32);
+        self.write(register, value as u32);
+        true
+    }
+
+    /// Sends the legacy IRQ `irq` to its vector, or masks it.
+    fn route(&mut self, irq: u8, masked: bool) -> bool {
+        let (gsi, over) = gsi(&self.overrides, irq);
+
+        let mut low = u32::from(irq::vector(irq));
+        if masked {
+            low |= MASKED;
+        }
+        if matches!(over, Some(o) if o.active_low()) {
+            low |= ACTIVE_LOW;
+        }
+        if matches!(over, Some(o) if o.level_triggered()) {
+            low |= LEVEL_TRIGGERED;
+        }
+        self.set_redirection(gsi, u64::from(self.apic_id) << 56 | u64::from(low))
+    }
+}
+
+/// Switches interrupt delivery from the PICs to the APICs listed in the MADT, routing the timer and
+/// every IRQ with a handler through the IO APIC. Needs `mem_init`. On error the PICs stay in use.
+pub fn init() -> Result<(), ApicError> {
+    let madt = acpi::madt().map_err(ApicError::Acpi)?;
+    // the legacy IRQs are the first global system interrupts, or their overrides.
+    let (gsi, _) = gsi(&madt.overrides, 0);
+    let info = *madt
+        .io_apics
+        .iter()
+        .filter(|io_apic| io_apic.gsi_base <= gsi)
+        .max_by_key(|io_apic| io_apic.gsi_base)
+        .ok_or(ApicError::NoIoApic)?;
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::NO_EXECUTE;
+    let mut vmm = vmm::vmm().ok_or(VmError::Uninitialized)?;
+    let local_apic = vmm.map_physical("local apic", madt.local_apic, 0x400, flags)?;
+    let io_apic_base = vmm.map_physical("io apic", info.address, 0x20, flags)?;
+    drop(vmm);
+
+    let mut io_apic = IoApic {
+        base: io_apic_base,
+        apic_id: 0,
+        gsi_base: info.gsi_base,
+        entries: 0,
+        overrides: madt.overrides,
+    };
+    io_apic.entries = ((io_apic.read(IOAPIC_VERSION) >> 16) & 0xff) + 1;
+
+    without_interrupts(|| {
+        unsafe {
+            let mut base = Msr::new(IA32_APIC_BASE);
+            base.write(base.read() | APIC_GLOBAL_ENABLE);
+            write_local(local_apic, LAPIC_TASK_PRIORITY, 0);
+            write_local(local_apic, LAPIC_LVT_TIMER, MASKED);
+            write_local(
+                local_apic,
+                LAPIC_SPURIOUS,
+                SPURIOUS_ENABLE | u32::from(SPURIOUS_VECTOR),
+            );
+        }
+        io_apic.apic_id = (unsafe { read_local(local_apic, LAPIC_ID) } >> 24) as u8;
+
+        for entry in 0..io_apic.entries {
+            io_apic.set_redirection(io_apic.gsi_base + entry, u64::from(MASKED));
+        }
+        for line in 0..irq::IRQ_LINES {
+            if line == irq::TIMER || irq::is_claimed(line) {
+                io_apic.route(line, false);
+            }
+        }
+
+        pic::disable();
+        *IO_APIC.lock() = Some(io_apic);
+        LOCAL_APIC.store(local_apic.as_u64(), Ordering::SeqCst);
+    });
+
+    Ok(())
+}
+
+/// Returns true once `init` switched to the APICs.
+pub fn is_enabled() -> bool {
+    LOCAL_APIC.load(Ordering::Relaxed) != 0
+}
+
+/// Vector the IO APIC delivers the legacy IRQ `irq` to, `None` if it is masked or the APICs are
+/// not in use.
+pub fn irq_vector(irq: u8) -> Option<u8> {
+    without_interrupts(|| {
+        let io_apic = IO_APIC.lock();
+        let io_apic = io_apic.as_ref()?;
+        let entry = io_apic.redirection(gsi(&io_apic.overrides, irq).0)?;
+        match entry & u64::from(MASKED) {
+            0 => Some(entry as u8),
+            _ => None,
+        }
+    })
+}
+
+/// Masks or unmasks the legacy IRQ `irq` at the IO APIC.
+pub fn set_masked(irq: u8, masked: bool) {
+    without_interrupts(|| {
+        if let Some(io_apic) = IO_APIC.lock().as_mut() {
+            io_apic.route(irq, masked);
+        }
+    });
+}
+
+/// Signals the end of the interrupt being handled to the local APIC.
+pub fn end_of_interrupt() {
+    let base = LOCAL_APIC.load(Ordering::Relaxed);
+    if base != 0 {
+        unsafe { write_local(VirtAddr::new(base), LAPIC_EOI, 0) };
+    }
+}
+
+/// The global system interrupt the legacy IRQ `irq` arrives on, along with its override if it has
+/// one.
+fn gsi(overrides: &[InterruptOverride], irq: u8) -> (u32, Option<InterruptOverride>) {
+    match overrides.iter().find(|o| o.irq == irq) {
+        Some(over) => (over.gsi, Some(*over)),
+        None => (u32::from(irq), None),
+    }
+}
+
+unsafe fn read_local(base: VirtAddr, register: u64) -> u32 {
+    (base + register).as_ptr::<u32>().read_volatile()
+}
+
+unsafe fn write_local(base: VirtAddr, register: u64, value: u32) {
+    (base + register).as_mut_ptr::<u32>().write_volatile(value)
+}
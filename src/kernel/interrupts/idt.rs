@@ -1,15 +1,19 @@
-use super::{pic, InterruptIndex};
+use super::{apic, irq};
 use crate::{halt, kernel, vga_println};
 use lazy_static::lazy_static;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::PrivilegeLevel;
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.divide_error.set_handler_fn(divide_error_handler);
         idt.debug.set_handler_fn(debug_handler);
-        idt.non_maskable_interrupt
-            .set_handler_fn(non_maskable_interrupt_handler);
+        unsafe {
+            idt.non_maskable_interrupt
+                .set_handler_fn(non_maskable_interrupt_handler)
+                .set_stack_index(kernel::gdt::NMI_IST_INDEX);
+        }
         idt.breakpoint.set_handler_fn(breakpoint_handler);
         idt.overflow.set_handler_fn(overflow_handler);
         idt.bound_range_exceeded.set_handler_fn(bound_range_handler);
@@ -28,16 +32,32 @@ lazy_static! {
             .set_handler_fn(stack_seg_fault_handler);
         idt.general_protection_fault
             .set_handler_fn(gen_protect_fault_handler);
+        // on the current stack, an IST stack would be reset by page faults the handler raises.
         idt.page_fault.set_handler_fn(page_fault_handler);
         idt.x87_floating_point.set_handler_fn(x87_floatp_handler);
         idt.alignment_check.set_handler_fn(alignment_check_handler);
-        idt.machine_check.set_handler_fn(machine_check_handler);
+        unsafe {
+            idt.machine_check
+                .set_handler_fn(machine_check_handler)
+                .set_stack_index(kernel::gdt::MACHINE_CHECK_IST_INDEX);
+        }
         idt.simd_floating_point.set_handler_fn(simd_floatp_handler);
         idt.virtualization.set_handler_fn(virtualization_handler);
         idt.security_exception.set_handler_fn(security_handler);
-        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
-        idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
+        // the timer switches threads, so its handler saves the full register state.
+        let timer_vector = usize::from(irq::vector(irq::TIMER));
+        unsafe {
+            idt[timer_vector].set_handler_addr(kernel::thread::switch::timer_entry());
+            idt[kernel::thread::switch::YIELD_VECTOR as usize]
+                .set_handler_addr(kernel::thread::switch::yield_entry());
+            idt[kernel::user::syscall::SYSCALL_VECTOR as usize]
+                .set_handler_addr(kernel::user::syscall::entry())
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
+        for (line, entry) in IRQ_ENTRIES.iter().enumerate() {
+            idt[usize::from(irq::vector(line as u8 + 1))].set_handler_fn(*entry);
+        }
+        idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(spurious_interrupt_handler);
         idt
     };
 }
@@ -46,10 +66,40 @@ pub fn init() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn mouse_interrupt_handler(_: InterruptStackFrame) {
-    pic::end_of_interrupt(InterruptIndex::Mouse)
+/// Defines `$name`, the IDT entry of IRQ `$irq`, which runs the handlers registered for it.
+macro_rules! irq_entry {
+    ($name:ident, $irq:literal) => {
+        extern "x86-interrupt" fn $name(_: InterruptStackFrame) {
+            irq::dispatch($irq);
+        }
+    };
 }
 
+irq_entry!(irq_1, 1);
+irq_entry!(irq_2, 2);
+irq_entry!(irq_3, 3);
+irq_entry!(irq_4, 4);
+irq_entry!(irq_5, 5);
+irq_entry!(irq_6, 6);
+irq_entry!(irq_7, 7);
+irq_entry!(irq_8, 8);
+irq_entry!(irq_9, 9);
+irq_entry!(irq_10, 10);
+irq_entry!(irq_11, 11);
+irq_entry!(irq_12, 12);
+irq_entry!(irq_13, 13);
+irq_entry!(irq_14, 14);
+irq_entry!(irq_15, 15);
+
+/// IDT entries of IRQ 1 onwards, the timer enters through the thread switch.
+const IRQ_ENTRIES: [extern "x86-interrupt" fn(InterruptStackFrame); irq::IRQ_LINES as usize - 1] = [
+    irq_1, irq_2, irq_3, irq_4, irq_5, irq_6, irq_7, irq_8, irq_9, irq_10, irq_11, irq_12, irq_13,
+    irq_14, irq_15,
+];
+
+// spurious interrupts are not in service, so they take no end of interrupt.
+extern "x86-interrupt" fn spurious_interrupt_handler(_: InterruptStackFrame) {}
+
 extern "x86-interrupt" fn security_handler(stack_frame: InterruptStackFrame, _: u64) {
     panic!("EXCEPTION: SECURITY:\n{:#?}", stack_frame);
 }
@@ -74,11 +124,17 @@ extern "x86-interrupt" fn x87_floatp_handler(stack_frame: InterruptStackFrame) {
     panic!("EXCEPTION: X86 FLOATING POINT:\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn gen_protect_fault_handler(stack_frame: InterruptStackFrame, _: u64) {
+extern "x86-interrupt" fn gen_protect_fault_handler(mut stack_frame: InterruptStackFrame, _: u64) {
+    if kernel::user::kill_on_fault(&mut stack_frame, "general protection fault") {
+        return;
+    }
     panic!("EXCEPTION: GENERAL PROTECTION:\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn stack_seg_fault_handler(stack_frame: InterruptStackFrame, _: u64) {
+extern "x86-interrupt" fn stack_seg_fault_handler(mut stack_frame: InterruptStackFrame, _: u64) {
+    if kernel::user::kill_on_fault(&mut stack_frame, "stack segment fault") {
+        return;
+    }
     panic!("EXCEPTION: STACK SEGMENT FAULT:\n{:#?}", stack_frame);
 }
 
@@ -94,7 +150,10 @@ extern "x86-interrupt" fn device_not_avail_handler(stack_frame: InterruptStackFr
     panic!("EXCEPTION: DEVICE NOT AVAILABLE:\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn invalid_op_handler(stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn invalid_op_handler(mut stack_frame: InterruptStackFrame) {
+    if kernel::user::kill_on_fault(&mut stack_frame, "invalid opcode") {
+        return;
+    }
     panic!("EXCEPTION: INVALID OPERATION:\n{:#?}", stack_frame);
 }
 
@@ -118,39 +177,85 @@ extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
     vga_println!("EXCEPTION: BREAKPOINT:\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn divide_error_handler(mut stack_frame: InterruptStackFrame) {
+    if kernel::user::kill_on_fault(&mut stack_frame, "divide error") {
+        return;
+    }
     panic!("EXCEPTION: DIVIDE ERROR:\n{:#?}", stack_frame);
 }
 
 extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _: u64) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
-}
+    use x86_64::registers::control::Cr2;
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    pic::end_of_interrupt(InterruptIndex::Timer);
-    let mut sc = crate::kernel::sc::SYSTEM_CLOCK.lock();
-    sc.tick();
-    drop(sc);
+    // an overflow faults on the guard page and then again pushing the page fault frame.
+    match kernel::mem::stack::overflowed(Cr2::read()) {
+        Some(stack) => panic!(
+            "EXCEPTION: DOUBLE FAULT\nStack overflow: {}\n{:#?}",
+            stack, stack_frame
+        ),
+        None => panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame),
+    }
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use x86_64::instructions::port::Port;
-
-    let mut port = Port::new(0x60);
-    let scancode: u8 = unsafe { port.read() };
-    crate::kernel::task::keyboard::add_scancode(scancode);
-
-    pic::end_of_interrupt(InterruptIndex::Keyboard)
+/// Timer interrupt work, called by the thread switch entry before it picks the next thread.
+pub(crate) fn timer_tick() {
+    crate::kernel::sc::SYSTEM_CLOCK.tick();
+    irq::dispatch(irq::TIMER);
 }
 
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     use x86_64::registers::control::Cr2;
 
+    let addr = Cr2::read();
+    let region = kernel::mem::region::find(addr);
+
+    // a kernel access through a kernel entry created after the active space: share it and retry.
+    let missing = !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION);
+    if missing
+        && !error_code.contains(PageFaultErrorCode::USER_MODE)
+        && matches!(kernel::mem::space::share_kernel_entry(addr), Ok(true))
+    {
+        return;
+    }
+
+    // a write to a user page shared by fork, from either ring: copy the page and retry.
+    let shared_write = error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+    if shared_write
+        && kernel::mem::space::is_user(addr)
+        && matches!(kernel::mem::space::copy_on_write(addr), Ok(true))
+    {
+        return;
+    }
+
+    // a kernel access to a missing page of a demand paged region: back it and retry.
+    if let Some(region) = region {
+        let fixable = !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+            && !error_code.contains(PageFaultErrorCode::USER_MODE);
+        if region.is_demand()
+            && fixable
+            && kernel::mem::vmm::map_demand_page(addr, region.flags).is_ok()
+        {
+            return;
+        }
+    }
+
+    if kernel::user::kill_on_fault(&mut stack_frame, "page fault") {
+        return;
+    }
+
     vga_println!("EXCEPTION: PAGE FAULT");
-    vga_println!("Accessed Address: {:?}", Cr2::read());
+    vga_println!("Accessed Address: {:?}", addr);
+    match region {
+        Some(region) if region.guard_contains(addr) => {
+            vga_println!("Stack overflow: {}", region.name)
+        }
+        Some(region) => vga_println!("Inside region: {} ({:?})", region.name, region.flags),
+        None => vga_println!("Outside of any reserved region"),
+    }
     vga_println!("Error Code: {:?}", error_code);
     vga_println!("{:#?}", stack_frame);
     halt();
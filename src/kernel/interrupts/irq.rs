@@ -0,0 +1,160 @@
+use super::{end_of_interrupt, set_masked};
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/*
+IRQ handler registration. Each of the 16 legacy IRQ lines has a few slots for handlers, which the
+IDT entry of the line calls in turn before it ends the interrupt, so handlers never send the end of
+interrupt themselves. A line is unmasked at the interrupt controller while it has handlers, except
+the timer, which always runs to drive the scheduler. Handlers run with interrupts disabled and the
+table locked, so they must be short and may not register or unregister handlers.
+ */
+
+/// Number of legacy IRQ lines.
+pub const IRQ_LINES: u8 = 16;
+/// Vector of IRQ 0, the others follow it.
+pub const IRQ_BASE: u8 = super::pic::PIC_1_OFFSET;
+/// Maximum number of handlers sharing a line.
+pub const MAX_SHARED: usize = 4;
+
+/// IRQ of the programmable interval timer.
+pub const TIMER: u8 = 0;
+/// IRQ of the PS/2 keyboard.
+pub const KEYBOARD: u8 = 1;
+/// IRQ of the PS/2 mouse.
+pub const MOUSE: u8 = 12;
+
+/// Errors when registering a handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// There is no such IRQ line.
+    InvalidLine,
+    /// Every slot of the line is taken.
+    LineFull,
+}
+
+/// Names a registered handler, pass it to `unregister` to remove the handler.
+#[derive(Debug, PartialEq, Eq)]
+#[must_use = "the handle is needed to unregister the handler"]
+pub struct IrqHandle {
+    irq: u8,
+    id: u64,
+}
+
+impl IrqHandle {
+    /// The line the handler is registered on.
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+}
+
+/// What a slot calls.
+enum Handler {
+    Function(fn()),
+    Closure(Box<dyn Fn() + Send + Sync>),
+}
+
+struct Slot {
+    id: u64,
+    handler: Handler,
+}
+
+const EMPTY_SLOT: Option<Slot> = None;
+const EMPTY_LINE: [Option<Slot>; MAX_SHARED] = [EMPTY_SLOT; MAX_SHARED];
+
+/// Handlers of each line. Only locked with interrupts disabled.
+static LINES: Mutex<[[Option<Slot>; MAX_SHARED]; IRQ_LINES as usize]> =
+    Mutex::new([EMPTY_LINE; IRQ_LINES as usize]);
+
+/// Number of IRQ handlers running, nested ones included.
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Vector IRQ `irq` is delivered to.
+pub fn vector(irq: u8) -> u8 {
+    IRQ_BASE + irq
+}
+
+/// Calls `handler` on every interrupt of `irq`. Needs no heap, so drivers can register during
+/// `init`.
+pub fn register_fn(irq: u8, handler: fn()) -> Result<IrqHandle, IrqError> {
+    insert(irq, Handler::Function(handler))
+}
+
+/// Calls `handler` on every interrupt of `irq`.
+pub fn register(
+    irq: u8,
+    handler: impl Fn() + Send + Sync + 'static,
+) -> Result<IrqHandle, IrqError> {
+    insert(irq, Handler::Closure(Box::new(handler)))
+}
+
+/// Removes the handler registered as `handle`, masking its line if it was the last one there.
+pub fn unregister(handle: IrqHandle) {
+    let slot = without_interrupts(|| {
+        let mut lines = LINES.lock();
+        let line = &mut lines[usize::from(handle.irq)];
+        let slot = line
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(slot) if slot.id == handle.id))
+            .and_then(Option::take);
+        if line.iter().all(Option::is_none) && handle.irq != TIMER {
+            set_masked(handle.irq, true);
+        }
+        slot
+    });
+    // the heap may be locked by a preempted thread, so closures are freed with interrupts enabled.
+    drop(slot);
+}
+
+/// Returns true if `irq` has a registered handler.
+pub fn is_claimed(irq: u8) -> bool {
+    irq < IRQ_LINES
+        && without_interrupts(|| LINES.lock()[usize::from(irq)].iter().any(Option::is_some))
+}
+
+/// Returns true while IRQ handlers run.
+pub fn in_irq() -> bool {
+    DEPTH.load(Ordering::Relaxed) != 0
+}
+
+/// Runs the handlers of `irq` and ends the interrupt. Called by the IDT entries.
+pub(crate) fn dispatch(irq: u8) {
+    DEPTH.fetch_add(1, Ordering::Relaxed);
+    if let Some(line) = LINES.lock().get(usize::from(irq)) {
+        for slot in line.iter().flatten() {
+            match &slot.handler {
+                Handler::Function(handler) => handler(),
+                Handler::Closure(handler) => handler(),
+            }
+        }
+    }
+    DEPTH.fetch_sub(1, Ordering::Relaxed);
+    end_of_interrupt(irq);
+}
+
+fn insert(irq: u8, handler: Handler) -> Result<IrqHandle, IrqError> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    if irq >= IRQ_LINES {
+        return Err(IrqError::InvalidLine);
+    }
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let result = without_interrupts(|| {
+        let mut lines = LINES.lock();
+        match lines[usize::from(irq)]
+            .iter_mut()
+            .find(|slot| slot.is_none())
+        {
+            Some(slot) => {
+                *slot = Some(Slot { id, handler });
+                set_masked(irq, false);
+                Ok(IrqHandle { irq, id })
+            }
+            None => Err(handler),
+        }
+    });
+    // like in `unregister`, a rejected closure is freed with interrupts enabled.
+    result.map_err(|_| IrqError::LineFull)
+}
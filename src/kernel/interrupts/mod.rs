@@ -1,19 +1,29 @@
+pub mod apic;
 pub mod idt;
+pub mod irq;
 pub mod pic;
+pub mod pit;
 
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-pub enum InterruptIndex {
-    Timer = pic::PIC_1_OFFSET,
-    Keyboard,
-    Mouse,
-}
-impl InterruptIndex {
-    fn as_u8(self) -> u8 {
-        self as u8
+/*
+Hardware interrupts arrive through the 8259 PICs until `apic::init` switches to the APICs. Either
+way the IRQs keep their vectors and are dispatched to the handlers registered in `irq`, only the
+end of interrupt and masking go to a different controller.
+ */
+
+/// Signals the end of IRQ `irq` to the controller that delivered it.
+pub fn end_of_interrupt(irq: u8) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+    } else {
+        pic::end_of_interrupt(irq);
     }
+}
 
-    fn as_usize(self) -> usize {
-        usize::from(self.as_u8())
+/// Masks or unmasks IRQ `irq` at the controller in use.
+fn set_masked(irq: u8, masked: bool) {
+    if apic::is_enabled() {
+        apic::set_masked(irq, masked);
+    } else {
+        pic::set_masked(irq, masked);
     }
 }
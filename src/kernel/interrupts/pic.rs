@@ -1,6 +1,7 @@
-use crate::kernel::interrupts::InterruptIndex;
+use super::irq;
 use pic8259::ChainedPics;
 use spin::Mutex;
+use x86_64::instructions::port::Port;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -13,8 +14,34 @@ pub fn init() {
     x86_64::instructions::interrupts::enable();
 }
 
-pub fn end_of_interrupt(i: InterruptIndex) {
+/// Masks every line of both PICs, once the APICs took over.
+pub fn disable() {
     unsafe {
-        PICS.lock().notify_end_of_interrupt(i.as_u8());
+        Port::<u8>::new(0x21).write(0xff);
+        Port::<u8>::new(0xa1).write(0xff);
+    }
+}
+
+/// Masks or unmasks IRQ `irq`. Lines of the second PIC also need the cascade line unmasked.
+pub fn set_masked(irq: u8, masked: bool) {
+    let (mut port, line) = match irq {
+        0..=7 => (Port::<u8>::new(0x21), irq),
+        _ => (Port::<u8>::new(0xa1), irq - 8),
+    };
+    unsafe {
+        let mask = port.read();
+        match masked {
+            true => port.write(mask | 1 << line),
+            false => port.write(mask & !(1 << line)),
+        }
+    }
+    if irq >= 8 && !masked {
+        set_masked(2, false);
+    }
+}
+
+pub fn end_of_interrupt(irq: u8) {
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(irq::vector(irq));
     }
 }
@@ -0,0 +1,99 @@
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU16, Ordering};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+/*
+Programmable interval timer. Channel 0 counts down from a divisor of its fixed input clock and
+raises IRQ 0 each time it reaches zero, which drives the system clock and the scheduler. The
+divisor is kept here so the tick rate is known exactly, rather than assumed. Channel 2 is not wired
+to an IRQ and serves as a stopwatch to calibrate other clocks against.
+ */
+
+/// Input clock of the PIT in Hz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+/// Timer frequency programmed by `init`.
+pub const DEFAULT_FREQUENCY: u32 = 100;
+/// Lowest frequency, the divisor has 16 bits.
+pub const MIN_FREQUENCY: u32 = 19;
+/// Highest frequency, faster ticks would leave little time between interrupts.
+pub const MAX_FREQUENCY: u32 = 1000;
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Gates channel 2 in bit 0 and reads its output in bit 5, bit 1 drives the speaker.
+const CHANNEL_2_CONTROL: u16 = 0x61;
+/// Channel 0, low then high byte of the divisor, mode 2 (rate generator), binary counting.
+const RATE_GENERATOR: u8 = 0b0011_0100;
+/// Channel 2, low then high byte of the count, mode 0 (interrupt on terminal count), binary.
+const ONE_SHOT: u8 = 0b1011_0000;
+
+/// Divisor channel 0 counts down from, see `set_frequency`.
+static DIVISOR: AtomicU16 = AtomicU16::new(divisor_for(DEFAULT_FREQUENCY));
+
+/// Programs channel 0 to `DEFAULT_FREQUENCY`.
+pub fn init() {
+    set_frequency(DEFAULT_FREQUENCY);
+}
+
+/// Timer interrupts per second, rounded.
+pub fn frequency() -> u32 {
+    let divisor = u32::from(DIVISOR.load(Ordering::Relaxed));
+    (BASE_FREQUENCY + divisor / 2) / divisor
+}
+
+/// Cycles of the input clock between timer interrupts.
+pub fn divisor() -> u16 {
+    DIVISOR.load(Ordering::Relaxed)
+}
+
+/// Exact timer interrupts per second.
+pub fn rate() -> f32 {
+    BASE_FREQUENCY as f32 / f32::from(DIVISOR.load(Ordering::Relaxed))
+}
+
+/// Sets the timer frequency in Hz, between `MIN_FREQUENCY` and `MAX_FREQUENCY`, and returns the
+/// frequency the divisor gives. Ticks counted before are converted at the new rate.
+pub fn set_frequency(hz: u32) -> u32 {
+    let divisor = divisor_for(hz);
+    without_interrupts(|| unsafe {
+        Port::<u8>::new(COMMAND).write(RATE_GENERATOR);
+        let mut channel = Port::<u8>::new(CHANNEL_0);
+        channel.write(divisor as u8);
+        channel.write((divisor >> 8) as u8);
+        DIVISOR.store(divisor, Ordering::Relaxed);
+    });
+    frequency()
+}
+
+/// Divisor closest to `hz`, once clamped.
+const fn divisor_for(hz: u32) -> u16 {
+    let hz = if hz < MIN_FREQUENCY {
+        MIN_FREQUENCY
+    } else if hz > MAX_FREQUENCY {
+        MAX_FREQUENCY
+    } else {
+        hz
+    };
+    ((BASE_FREQUENCY + hz / 2) / hz) as u16
+}
+
+/// Busy waits for `count` cycles of the input clock, measured by channel 2.
+pub fn wait(count: u16) {
+    without_interrupts(|| unsafe {
+        let mut control = Port::<u8>::new(CHANNEL_2_CONTROL);
+        // gate on, speaker off.
+        let value = control.read();
+        control.write(value & !0b10 | 0b1);
+
+        Port::<u8>::new(COMMAND).write(ONE_SHOT);
+        let mut channel = Port::<u8>::new(CHANNEL_2);
+        channel.write(count as u8);
+        channel.write((count >> 8) as u8);
+        // the output goes high once the count reaches zero.
+        while control.read() & 0b10_0000 == 0 {
+            spin_loop();
+        }
+    });
+}
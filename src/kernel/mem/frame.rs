@@ -1,6 +1,21 @@
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
-use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
-use x86_64::PhysAddr;
+use core::fmt::{Debug, Formatter};
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+/*
+Physical memory manager. Every 4 KiB frame below the highest usable address has one bit in a
+bitmap, set when the frame is in use, and a reference count, so a frame mapped in several address
+spaces is only freed once the last of them lets go of it. Both live in the first usable region
+large enough to hold them and are reached through the bootloader's physical memory mapping.
+ */
+
+/// Size of a physical frame in bytes.
+pub const FRAME_SIZE: u64 = 4096;
+
+/// Number of frames tracked by one bitmap word.
+const WORD_BITS: usize = u64::BITS as usize;
 
 pub struct EmptyFrameAllocator;
 
@@ -10,37 +25,250 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
     }
 }
 
-#[derive(Debug)]
+/// Bitmap frame allocator built from the bootloader `MemoryMap`. Supports freeing frames and
+/// allocating physically contiguous runs of frames.
 pub struct BootInfoFrameAllocator {
-    // memory_map: &'static MemoryMap,
-    memory_reagions: &'static MemoryMap,
+    /// One bit per frame, set when the frame is used or not usable RAM.
+    bitmap: &'static mut [u64],
+    /// References to each allocated frame. Frames that are free or not usable RAM have none.
+    refs: &'static mut [u16],
+    /// Number of frames covered by `bitmap`.
+    frames: usize,
+    /// Frames the memory map reports as usable.
+    usable: usize,
+    /// Usable frames currently free.
+    free: usize,
+    /// Word index to start the next search from.
     next: usize,
 }
 
 impl BootInfoFrameAllocator {
-    pub unsafe fn init(memory_reagions: &'static MemoryMap) -> Self {
-        Self {
-            // memory_map,
-            memory_reagions,
+    /// Builds the bitmap from the memory map. Frames holding the bitmap and reference counts are
+    /// marked used.
+    ///
+    /// The caller must guarantee that the memory map is valid and that all physical memory is
+    /// mapped at `physical_memory_offset`.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+
+        let frames = usable_regions()
+            .map(|r| r.range.end_frame_number)
+            .max()
+            .unwrap_or(0) as usize;
+        let words = (frames + WORD_BITS - 1) / WORD_BITS;
+        let bitmap_frames = ((words * 8 + frames * 2) as u64 + FRAME_SIZE - 1) / FRAME_SIZE;
+
+        // place the bitmap, followed by the reference counts, at the start of the first usable
+        // region that can hold them.
+        let bitmap_region = usable_regions()
+            .find(|r| r.range.end_frame_number - r.range.start_frame_number >= bitmap_frames)
+            .expect("no usable memory region can hold the frame bitmap");
+        let bitmap_start = bitmap_region.range.start_frame_number as usize;
+        let bitmap_ptr: *mut u64 =
+            (physical_memory_offset + bitmap_region.range.start_addr()).as_mut_ptr();
+        let bitmap = core::slice::from_raw_parts_mut(bitmap_ptr, words);
+        let refs = core::slice::from_raw_parts_mut(bitmap_ptr.add(words) as *mut u16, frames);
+
+        // everything is used until the memory map says otherwise.
+        bitmap.fill(u64::MAX);
+        refs.fill(0);
+
+        let mut allocator = Self {
+            bitmap,
+            refs,
+            frames,
+            usable: 0,
+            free: 0,
             next: 0,
+        };
+
+        for region in usable_regions() {
+            for index in region.range.start_frame_number..region.range.end_frame_number {
+                allocator.clear(index as usize);
+                allocator.usable += 1;
+                allocator.free += 1;
+            }
+        }
+
+        for index in bitmap_start..bitmap_start + bitmap_frames as usize {
+            allocator.set(index);
+            allocator.free -= 1;
+        }
+
+        allocator
+    }
+
+    /// Total number of usable frames reported by the boot memory map.
+    pub fn total_frames(&self) -> usize {
+        self.usable
+    }
+
+    /// Number of frames currently free.
+    pub fn free_frames(&self) -> usize {
+        self.free
+    }
+
+    /// Number of usable frames currently allocated, including the bitmap itself.
+    pub fn used_frames(&self) -> usize {
+        self.usable - self.free
+    }
+
+    /// Returns true if `frame` is allocated or is not usable RAM.
+    pub fn is_used(&self, frame: PhysFrame) -> bool {
+        let index = frame_index(frame);
+        index >= self.frames || self.test(index)
+    }
+
+    /// Number of references to `frame`, zero if it is free.
+    pub fn references(&self, frame: PhysFrame) -> usize {
+        self.refs.get(frame_index(frame)).copied().unwrap_or(0) as usize
+    }
+
+    /// Adds a reference to the allocated `frame`. It then takes one more `deallocate_frame` to
+    /// free it.
+    pub fn share(&mut self, frame: PhysFrame) {
+        let index = frame_index(frame);
+        assert!(
+            index < self.frames && self.test(index),
+            "sharing a free frame: {:?}",
+            frame
+        );
+        self.refs[index] = self.refs[index]
+            .checked_add(1)
+            .expect("too many references to a frame");
+    }
+
+    /// Allocates `count` physically contiguous frames.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrameRange> {
+        if count == 0 || count > self.free {
+            return None;
+        }
+
+        let mut run_start = 0;
+        let mut run_len = 0;
+        for index in 0..self.frames {
+            if self.test(index) {
+                run_len = 0;
+                continue;
+            }
+
+            if run_len == 0 {
+                run_start = index;
+            }
+            run_len += 1;
+
+            if run_len == count {
+                for i in run_start..run_start + count {
+                    self.set(i);
+                    self.refs[i] = 1;
+                }
+                self.free -= count;
+                let start = index_frame(run_start);
+                return Some(PhysFrame::range(start, start + count as u64));
+            }
+        }
+
+        None
+    }
+
+    /// Frees a range of frames returned by `allocate_contiguous`.
+    ///
+    /// The caller must guarantee that no frame in `range` is still in use.
+    pub unsafe fn deallocate_contiguous(&mut self, range: PhysFrameRange) {
+        for frame in range {
+            self.deallocate_frame(frame);
         }
     }
 
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        let regions = self.memory_reagions;
-        let usable_regions = regions
-            .iter()
-            .filter(|r| r.region_type == MemoryRegionType::Usable);
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    /// Returns true if the bit for frame `index` is set.
+    fn test(&self, index: usize) -> bool {
+        self.bitmap[index / WORD_BITS] & (1 << (index % WORD_BITS)) != 0
+    }
+
+    /// Marks frame `index` used.
+    fn set(&mut self, index: usize) {
+        self.bitmap[index / WORD_BITS] |= 1 << (index % WORD_BITS);
     }
+
+    /// Marks frame `index` free.
+    fn clear(&mut self, index: usize) {
+        self.bitmap[index / WORD_BITS] &= !(1 << (index % WORD_BITS));
+    }
+}
+
+/// Bitmap index of a frame.
+fn frame_index(frame: PhysFrame) -> usize {
+    (frame.start_address().as_u64() / FRAME_SIZE) as usize
+}
+
+/// Frame at a bitmap index.
+fn index_frame(index: usize) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        if self.free == 0 {
+            return None;
+        }
+
+        let words = self.bitmap.len();
+        // search from the last word that had a free frame, wrapping around once.
+        for offset in 0..words {
+            let word = (self.next + offset) % words;
+            let bits = self.bitmap[word];
+            if bits == u64::MAX {
+                continue;
+            }
+
+            let index = word * WORD_BITS + (!bits).trailing_zeros() as usize;
+            if index >= self.frames {
+                continue;
+            }
+
+            self.set(index);
+            self.refs[index] = 1;
+            self.free -= 1;
+            self.next = word;
+            return Some(index_frame(index));
+        }
+
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let index = frame_index(frame);
+        assert!(
+            index < self.frames,
+            "freeing frame outside of usable memory: {:?}",
+            frame
+        );
+        assert!(self.test(index), "double free of frame {:?}", frame);
+
+        // shared frames only lose a reference.
+        if self.refs[index] > 1 {
+            self.refs[index] -= 1;
+            return;
+        }
+        self.refs[index] = 0;
+        self.clear(index);
+        self.free += 1;
+        self.next = self.next.min(index / WORD_BITS);
+    }
+}
+
+impl Debug for BootInfoFrameAllocator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BootInfoFrameAllocator")
+            .field("frames", &self.frames)
+            .field("usable", &self.usable)
+            .field("free", &self.free)
+            .finish()
     }
 }
@@ -0,0 +1,180 @@
+use crate::drivers::io;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use spin::Mutex;
+
+/*
+Debug allocator, enabled with the `debug-alloc` feature. Every allocation is surrounded by red
+zones and preceded by a header recording its layout:
+
+    | padding | Header | front red zone | user data | back red zone |
+
+Freed blocks are poisoned and held in a quarantine before they reach the real allocator, so
+double frees and writes after free are caught while the block is still quarantined.
+ */
+
+/// Bytes of guard on each side of an allocation.
+const RED_ZONE: usize = 16;
+/// Byte the red zones are filled with.
+const GUARD_BYTE: u8 = 0xfd;
+/// Byte fresh allocations are filled with.
+const ALLOC_POISON: u8 = 0xcd;
+/// Byte freed allocations are filled with.
+const FREE_POISON: u8 = 0xdd;
+/// Header magic of a live allocation.
+const LIVE_MAGIC: u64 = 0x_a110_c8ed_a110_c8ed;
+/// Header magic of a freed allocation.
+const FREED_MAGIC: u64 = 0x_dead_f4ee_dead_f4ee;
+/// Number of freed blocks held back from the real allocator.
+const QUARANTINE_SIZE: usize = 64;
+
+/// Bookkeeping stored in front of every allocation.
+#[repr(C)]
+struct Header {
+    magic: u64,
+    size: usize,
+    align: usize,
+}
+
+/// CONSTANT size of the Header type
+const HEADER_SIZE: usize = core::mem::size_of::<Header>();
+
+/// Recently freed blocks, oldest first once the ring wraps.
+struct Quarantine {
+    blocks: [Option<(*mut u8, Layout)>; QUARANTINE_SIZE],
+    next: usize,
+}
+
+// The pointers only refer to heap blocks owned by the quarantine.
+unsafe impl Send for Quarantine {}
+
+/// Wraps an allocator with red zones, poisoning and double free detection.
+pub struct DebugAllocator<A: 'static> {
+    inner: &'static A,
+    quarantine: Mutex<Quarantine>,
+}
+
+impl<A: GlobalAlloc> DebugAllocator<A> {
+    /// Creates a debug allocator around `inner`.
+    pub const fn new(inner: &'static A) -> Self {
+        const EMPTY: Option<(*mut u8, Layout)> = None;
+        DebugAllocator {
+            inner,
+            quarantine: Mutex::new(Quarantine {
+                blocks: [EMPTY; QUARANTINE_SIZE],
+                next: 0,
+            }),
+        }
+    }
+}
+
+impl<A> DebugAllocator<A> {
+    /// Returns true while the quarantine is locked, as during the checks of a free.
+    pub fn is_locked(&self) -> bool {
+        self.quarantine.is_locked()
+    }
+}
+
+/// Offset of the user data from the start of the real allocation.
+fn front_size(align: usize) -> usize {
+    super::align_up(HEADER_SIZE + RED_ZONE, align)
+}
+
+/// Layout of the real allocation backing `layout`.
+fn outer_layout(layout: Layout) -> Layout {
+    let align = layout.align().max(core::mem::align_of::<Header>());
+    Layout::from_size_align(front_size(align) + layout.size() + RED_ZONE, align)
+        .expect("debug allocation too large")
+}
+
+/// Reports heap corruption at `ptr` and panics. The report is skipped if the output is locked, as
+/// the corruption may be found freeing a string in the middle of a print.
+fn report(ptr: *mut u8, what: &str, layout: Layout) -> ! {
+    io::try_print(format_args!(
+        "HEAP CORRUPTION: {} at {:p} (size {}, align {})\n",
+        what,
+        ptr,
+        layout.size(),
+        layout.align()
+    ));
+    panic!("heap corruption: {} at {:p}", what, ptr);
+}
+
+/// Returns true if every byte of `len` bytes at `ptr` equals `byte`.
+unsafe fn filled_with(ptr: *const u8, len: usize, byte: u8) -> bool {
+    core::slice::from_raw_parts(ptr, len)
+        .iter()
+        .all(|b| *b == byte)
+}
+
+/// Header of the user allocation at `ptr`.
+unsafe fn header(ptr: *mut u8) -> *mut Header {
+    ptr.sub(RED_ZONE + HEADER_SIZE) as *mut Header
+}
+
+/// Checks the header and red zones of the user allocation at `ptr` freed with `layout`.
+unsafe fn check(ptr: *mut u8, layout: Layout) {
+    let header = &*header(ptr);
+    match header.magic {
+        LIVE_MAGIC => {}
+        FREED_MAGIC => report(ptr, "double free", layout),
+        _ => report(ptr, "free of unknown pointer or corrupted header", layout),
+    }
+
+    if header.size != layout.size() || header.align != layout.align() {
+        io::try_print(format_args!(
+            "allocated with size {}, align {}\n",
+            header.size, header.align
+        ));
+        report(ptr, "dealloc with mismatched layout", layout);
+    }
+
+    if !filled_with(ptr.sub(RED_ZONE), RED_ZONE, GUARD_BYTE) {
+        report(ptr, "buffer underflow into front red zone", layout);
+    }
+
+    if !filled_with(ptr.add(layout.size()), RED_ZONE, GUARD_BYTE) {
+        report(ptr, "buffer overflow into back red zone", layout);
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let outer = outer_layout(layout);
+        let base = self.inner.alloc(outer);
+        if base.is_null() {
+            return base;
+        }
+
+        let ptr = base.add(front_size(outer.align()));
+        header(ptr).write(Header {
+            magic: LIVE_MAGIC,
+            size: layout.size(),
+            align: layout.align(),
+        });
+        ptr::write_bytes(ptr.sub(RED_ZONE), GUARD_BYTE, RED_ZONE);
+        ptr::write_bytes(ptr, ALLOC_POISON, layout.size());
+        ptr::write_bytes(ptr.add(layout.size()), GUARD_BYTE, RED_ZONE);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut quarantine = self.quarantine.lock();
+        check(ptr, layout);
+
+        (*header(ptr)).magic = FREED_MAGIC;
+        ptr::write_bytes(ptr, FREE_POISON, layout.size());
+
+        // hold the block back, releasing the oldest one once the quarantine is full.
+        let next = quarantine.next;
+        quarantine.next = (next + 1) % QUARANTINE_SIZE;
+        if let Some((old, old_layout)) = quarantine.blocks[next].replace((ptr, layout)) {
+            if !filled_with(old, old_layout.size(), FREE_POISON) {
+                report(old, "write after free", old_layout);
+            }
+            let outer = outer_layout(old_layout);
+            self.inner
+                .dealloc(old.sub(front_size(outer.align())), outer);
+        }
+    }
+}
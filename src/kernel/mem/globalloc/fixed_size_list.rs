@@ -1,9 +1,18 @@
-use crate::kernel::mem::globalloc::Locked;
+use crate::kernel::mem::globalloc::heap::{heap_limit, HEAP_GROW_STEP, HEAP_START};
+use crate::kernel::mem::globalloc::{align_up, Locked};
 use alloc::alloc::Layout;
 use core::alloc::GlobalAlloc;
 use core::ptr;
 use core::ptr::NonNull;
 use linked_list_allocator::Heap;
+use x86_64::structures::paging::{PageSize, Size4KiB};
+
+/*
+Size classes are served from slabs: aligned chunks of the fallback heap holding a `Slab` header
+followed by blocks of one size. A slab lives on its class's `partial` list while it has free
+blocks and on `full` otherwise. Fully free slabs beyond `MAX_EMPTY_SLABS` go straight back to the
+fallback heap, the rest are released by `reclaim` when the heap runs out.
+ */
 
 /// Fixed size node for memory allocation
 struct ListNode {
@@ -11,26 +20,204 @@ struct ListNode {
 }
 
 /// Possible sizes for each node.
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
 /// CONSTANT size of ListNode type
 const NODE_SIZE: usize = core::mem::size_of::<ListNode>();
 /// CONSTANT alignment of ListNode type
 const NODE_ALLIGN: usize = core::mem::align_of::<ListNode>();
 
-/// The Fixed sized allocator. contains list of Nodes
+/// Smallest slab size, one page.
+const MIN_SLAB_SIZE: usize = Size4KiB::SIZE as usize;
+/// Minimum number of blocks in a slab of the larger classes.
+const MIN_SLAB_BLOCKS: usize = 8;
+/// Number of fully free slabs a class keeps before returning them to the fallback heap.
+const MAX_EMPTY_SLABS: usize = 1;
+
+/// Header at the start of every slab.
+struct Slab {
+    prev: *mut Slab,
+    next: *mut Slab,
+    free: Option<&'static mut ListNode>,
+    live: usize,
+    capacity: usize,
+}
+
+/// CONSTANT size of the Slab header
+const SLAB_HEADER_SIZE: usize = core::mem::size_of::<Slab>();
+
+/// Size (and alignment) of a slab of class `index`.
+fn slab_size(index: usize) -> usize {
+    (BLOCK_SIZES[index] * MIN_SLAB_BLOCKS).max(MIN_SLAB_SIZE)
+}
+
+/// Layout of a slab of class `index`, as requested from the fallback heap.
+fn slab_layout(index: usize) -> Layout {
+    let size = slab_size(index);
+    Layout::from_size_align(size, size).unwrap()
+}
+
+/// Slab containing the block at `ptr` of class `index`.
+fn slab_of(ptr: *mut u8, index: usize) -> *mut Slab {
+    (ptr as usize & !(slab_size(index) - 1)) as *mut Slab
+}
+
+/// Pushes `slab` at the front of the list at `head`.
+unsafe fn push(head: &mut *mut Slab, slab: *mut Slab) {
+    (*slab).prev = ptr::null_mut();
+    (*slab).next = *head;
+    if !head.is_null() {
+        (**head).prev = slab;
+    }
+    *head = slab;
+}
+
+/// Removes `slab` from the list at `head`.
+unsafe fn unlink(head: &mut *mut Slab, slab: *mut Slab) {
+    let prev = (*slab).prev;
+    let next = (*slab).next;
+    if prev.is_null() {
+        *head = next;
+    } else {
+        (*prev).next = next;
+    }
+    if !next.is_null() {
+        (*next).prev = prev;
+    }
+    (*slab).prev = ptr::null_mut();
+    (*slab).next = ptr::null_mut();
+}
+
+/// Counters for one size class of `BLOCK_SIZES`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SizeClassStats {
+    /// Size of a block in this class.
+    pub block_size: usize,
+    /// Number of allocations served.
+    pub allocs: usize,
+    /// Number of deallocations.
+    pub frees: usize,
+    /// Free blocks in this class's slabs.
+    pub free_blocks: usize,
+    /// Slabs currently owned by this class.
+    pub slabs: usize,
+    /// Owned slabs with no live blocks.
+    pub empty_slabs: usize,
+    /// Slabs returned to the fallback heap so far.
+    pub reclaimed: usize,
+}
+
+impl SizeClassStats {
+    const fn new(block_size: usize) -> Self {
+        SizeClassStats {
+            block_size,
+            allocs: 0,
+            frees: 0,
+            free_blocks: 0,
+            slabs: 0,
+            empty_slabs: 0,
+            reclaimed: 0,
+        }
+    }
+
+    /// Blocks currently handed out.
+    pub fn live(&self) -> usize {
+        self.allocs - self.frees
+    }
+
+    /// Bytes held in free blocks.
+    pub fn free_bytes(&self) -> usize {
+        self.free_blocks * self.block_size
+    }
+}
+
+/// Slab lists and counters of one size class.
+struct SizeClass {
+    /// Slabs with at least one free block.
+    partial: *mut Slab,
+    /// Slabs with no free block.
+    full: *mut Slab,
+    stats: SizeClassStats,
+}
+
+impl SizeClass {
+    const fn new(block_size: usize) -> Self {
+        SizeClass {
+            partial: ptr::null_mut(),
+            full: ptr::null_mut(),
+            stats: SizeClassStats::new(block_size),
+        }
+    }
+}
+
+/// Snapshot of the allocator's counters.
+#[derive(Debug, Clone, Copy)]
+pub struct AllocatorStats {
+    /// Counters of every size class, in `BLOCK_SIZES` order.
+    pub classes: [SizeClassStats; BLOCK_SIZES.len()],
+    /// Allocations too large for any size class.
+    pub large_allocs: usize,
+    /// Deallocations of large allocations.
+    pub large_frees: usize,
+    /// Bytes currently held by large allocations.
+    pub large_bytes: usize,
+    /// Bytes mapped for the fallback heap.
+    pub heap_size: usize,
+    /// Bytes of the fallback heap in use, including slabs.
+    pub heap_used: usize,
+    /// Bytes of the fallback heap still available.
+    pub heap_free: usize,
+}
+
+/// The Fixed sized allocator. Serves size classes from slabs, everything else from the fallback.
 pub struct FixedSizeAllocator {
-    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    classes: [SizeClass; BLOCK_SIZES.len()],
     fallback_allocator: Heap,
+    large_allocs: usize,
+    large_frees: usize,
+    large_bytes: usize,
 }
 
+// The slab pointers only point into the fallback heap owned by the allocator.
+unsafe impl Send for FixedSizeAllocator {}
+
 impl FixedSizeAllocator {
     /// Creates a new allocator
     pub const fn new() -> Self {
-        const EMPTY: Option<&'static mut ListNode> = None;
         FixedSizeAllocator {
-            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            classes: [
+                SizeClass::new(BLOCK_SIZES[0]),
+                SizeClass::new(BLOCK_SIZES[1]),
+                SizeClass::new(BLOCK_SIZES[2]),
+                SizeClass::new(BLOCK_SIZES[3]),
+                SizeClass::new(BLOCK_SIZES[4]),
+                SizeClass::new(BLOCK_SIZES[5]),
+                SizeClass::new(BLOCK_SIZES[6]),
+                SizeClass::new(BLOCK_SIZES[7]),
+                SizeClass::new(BLOCK_SIZES[8]),
+            ],
             fallback_allocator: Heap::empty(),
+            large_allocs: 0,
+            large_frees: 0,
+            large_bytes: 0,
+        }
+    }
+
+    /// Returns a snapshot of the allocator's counters.
+    pub fn stats(&self) -> AllocatorStats {
+        let mut classes = [SizeClassStats::default(); BLOCK_SIZES.len()];
+        for (stats, class) in classes.iter_mut().zip(self.classes.iter()) {
+            *stats = class.stats;
+        }
+
+        AllocatorStats {
+            classes,
+            large_allocs: self.large_allocs,
+            large_frees: self.large_frees,
+            large_bytes: self.large_bytes,
+            heap_size: self.fallback_allocator.size(),
+            heap_used: self.fallback_allocator.used(),
+            heap_free: self.fallback_allocator.free(),
         }
     }
 
@@ -39,14 +226,175 @@ impl FixedSizeAllocator {
         self.fallback_allocator.init(heap_start, heap_size)
     }
 
-    /// allocate using fallback
+    /// Returns every fully free slab to the fallback heap. Returns the number of bytes released.
+    pub fn reclaim(&mut self) -> usize {
+        let mut released = 0;
+        for index in 0..BLOCK_SIZES.len() {
+            // empty slabs always have free blocks, so they are all on the partial list.
+            let mut slab = self.classes[index].partial;
+            while !slab.is_null() {
+                let next = unsafe { (*slab).next };
+                if unsafe { (*slab).live } == 0 {
+                    unsafe { self.release_slab(index, slab) };
+                    released += slab_size(index);
+                }
+                slab = next;
+            }
+        }
+        released
+    }
+
+    /// Takes a block of class `index`, creating a new slab if none has a free block.
+    fn alloc_block(&mut self, index: usize) -> *mut u8 {
+        if self.classes[index].partial.is_null() {
+            let slab = self.new_slab(index);
+            if slab.is_null() {
+                return ptr::null_mut();
+            }
+            unsafe { push(&mut self.classes[index].partial, slab) };
+        }
+
+        let class = &mut self.classes[index];
+        let slab = class.partial;
+        unsafe {
+            let node = (*slab)
+                .free
+                .take()
+                .expect("slab on partial list has no free block");
+            (*slab).free = node.next.take();
+
+            if (*slab).live == 0 {
+                class.stats.empty_slabs -= 1;
+            }
+            (*slab).live += 1;
+            class.stats.free_blocks -= 1;
+            class.stats.allocs += 1;
+
+            if (*slab).free.is_none() {
+                unlink(&mut class.partial, slab);
+                push(&mut class.full, slab);
+            }
+
+            node as *mut ListNode as *mut u8
+        }
+    }
+
+    /// Returns the block at `ptr` to its slab, releasing the slab if it is no longer needed.
+    unsafe fn dealloc_block(&mut self, ptr: *mut u8, index: usize) {
+        assert!(NODE_SIZE <= BLOCK_SIZES[index]);
+        assert!(NODE_ALLIGN <= BLOCK_SIZES[index]);
+
+        let slab = slab_of(ptr, index);
+        let class = &mut self.classes[index];
+        let was_full = (*slab).free.is_none();
+
+        let new_node_ptr = ptr as *mut ListNode;
+        new_node_ptr.write(ListNode {
+            next: (*slab).free.take(),
+        });
+        (*slab).free = Some(&mut *new_node_ptr);
+        (*slab).live -= 1;
+        class.stats.free_blocks += 1;
+        class.stats.frees += 1;
+
+        if was_full {
+            unlink(&mut class.full, slab);
+            push(&mut class.partial, slab);
+        }
+
+        if (*slab).live == 0 {
+            class.stats.empty_slabs += 1;
+            if class.stats.empty_slabs > MAX_EMPTY_SLABS {
+                self.release_slab(index, slab);
+            }
+        }
+    }
+
+    /// Carves a new slab for class `index` out of the fallback heap.
+    fn new_slab(&mut self, index: usize) -> *mut Slab {
+        let slab = self.fallback_alloc(slab_layout(index)) as *mut Slab;
+        if slab.is_null() {
+            return slab;
+        }
+
+        let block_size = BLOCK_SIZES[index];
+        let first = align_up(SLAB_HEADER_SIZE, block_size);
+        let capacity = (slab_size(index) - first) / block_size;
+
+        // thread every block onto the slab's free list, lowest address first.
+        let mut free: Option<&'static mut ListNode> = None;
+        for i in (0..capacity).rev() {
+            let node = (slab as usize + first + i * block_size) as *mut ListNode;
+            unsafe {
+                node.write(ListNode { next: free.take() });
+                free = Some(&mut *node);
+            }
+        }
+
+        unsafe {
+            slab.write(Slab {
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+                free,
+                live: 0,
+                capacity,
+            });
+        }
+
+        let stats = &mut self.classes[index].stats;
+        stats.slabs += 1;
+        stats.empty_slabs += 1;
+        stats.free_blocks += capacity;
+        slab
+    }
+
+    /// Returns an empty slab of class `index` to the fallback heap.
+    unsafe fn release_slab(&mut self, index: usize, slab: *mut Slab) {
+        let class = &mut self.classes[index];
+        unlink(&mut class.partial, slab);
+        class.stats.slabs -= 1;
+        class.stats.empty_slabs -= 1;
+        class.stats.free_blocks -= (*slab).capacity;
+        class.stats.reclaimed += 1;
+
+        self.fallback_dealloc(NonNull::new_unchecked(slab as *mut u8), slab_layout(index));
+    }
+
+    /// allocate using fallback, reclaiming empty slabs and then growing the heap when it is
+    /// exhausted
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
-            Err(_) => ptr::null_mut(),
+        loop {
+            match self.fallback_allocator.allocate_first_fit(layout) {
+                Ok(ptr) => return ptr.as_ptr(),
+                Err(_) => {
+                    if self.reclaim() > 0 {
+                        continue;
+                    }
+                    if !self.grow(layout) {
+                        return ptr::null_mut();
+                    }
+                }
+            }
         }
     }
 
+    /// Extends the fallback heap by enough for at least `layout`. The new pages are demand
+    /// paged. Returns false if the heap limit is reached.
+    fn grow(&mut self, layout: Layout) -> bool {
+        let top = self.fallback_allocator.top();
+        let size = align_up(
+            (layout.size() + layout.align()).max(HEAP_GROW_STEP),
+            Size4KiB::SIZE as usize,
+        );
+
+        if top + size - HEAP_START > heap_limit() {
+            return false;
+        }
+
+        unsafe { self.fallback_allocator.extend(size) };
+        true
+    }
+
     /// deallocate using fallback
     fn fallback_dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
         unsafe { self.fallback_allocator.deallocate(ptr, layout) }
@@ -65,22 +413,17 @@ unsafe impl GlobalAlloc for Locked<FixedSizeAllocator> {
         // lock our allocator into the thread
         let mut allocator = self.lock();
         match list_index(&layout) {
-            // if space is available, allocate and return its position (pointer)
-            Some(index) => match allocator.list_heads[index].take() {
-                Some(node) => {
-                    allocator.list_heads[index] = node.next.take();
-                    node as *mut ListNode as *mut u8
-                }
-                // if allocation fails, fallback
-                None => {
-                    let block_size = BLOCK_SIZES[index];
-                    let block_align = block_size;
-                    let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                    allocator.fallback_alloc(layout)
+            // take a block from the size class's slabs
+            Some(index) => allocator.alloc_block(index),
+            // too large for any size class, fallback
+            None => {
+                let ptr = allocator.fallback_alloc(layout);
+                if !ptr.is_null() {
+                    allocator.large_allocs += 1;
+                    allocator.large_bytes += layout.size();
                 }
-            },
-            // if space is unavailable, fallback
-            None => allocator.fallback_alloc(layout),
+                ptr
+            }
         }
     }
 
@@ -88,19 +431,12 @@ unsafe impl GlobalAlloc for Locked<FixedSizeAllocator> {
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
         match list_index(&layout) {
-            Some(index) => {
-                let new_node = ListNode {
-                    next: allocator.list_heads[index].take(),
-                };
-                assert!(NODE_SIZE <= BLOCK_SIZES[index]);
-                assert!(NODE_ALLIGN <= BLOCK_SIZES[index]);
-                let new_node_ptr = ptr as *mut ListNode;
-                new_node_ptr.write(new_node);
-                allocator.list_heads[index] = Some(&mut *new_node_ptr);
-            }
+            Some(index) => allocator.dealloc_block(ptr, index),
             None => {
                 let ptr = NonNull::new(ptr).unwrap();
-                allocator.fallback_dealloc(ptr, layout)
+                allocator.fallback_dealloc(ptr, layout);
+                allocator.large_frees += 1;
+                allocator.large_bytes -= layout.size();
             }
         }
     }
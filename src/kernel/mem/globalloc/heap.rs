@@ -1,35 +1,47 @@
 use crate::kernel::mem::globalloc::ALLOCATOR;
+use crate::kernel::mem::region::Region;
+use crate::kernel::mem::vmm::{VirtualMemoryManager, VmError};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::ptr::null_mut;
-use x86_64::{
-    structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
-    },
-    VirtAddr,
-};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
+/// Size of the heap mapped at boot.
 pub const HEAP_SIZE: usize = 100 * 1024;
+/// Default upper bound the heap may grow to.
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024;
+/// Virtual address space reserved for the heap, the limit can not exceed this.
+pub const HEAP_RESERVED: usize = 1024 * 1024 * 1024;
+/// Smallest amount the heap grows by at once.
+pub const HEAP_GROW_STEP: usize = 64 * 1024;
 
-pub fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
-
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
-    }
+/// Current upper bound of the heap, see `set_heap_limit`.
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
+
+/// Returns the size in bytes the heap is allowed to grow to.
+pub fn heap_limit() -> usize {
+    HEAP_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Sets the size in bytes the heap is allowed to grow to, between `HEAP_SIZE` and
+/// `HEAP_RESERVED`. Memory already mapped is kept.
+pub fn set_heap_limit(limit: usize) {
+    HEAP_LIMIT.store(limit.clamp(HEAP_SIZE, HEAP_RESERVED), Ordering::Relaxed)
+}
+
+/// Reserves the heap region, maps its first `HEAP_SIZE` bytes and hands them to the allocator.
+pub fn init_heap(vmm: &mut VirtualMemoryManager) -> Result<(), VmError> {
+    // pages past the initial heap are mapped by the page fault handler as the heap grows.
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let heap = Region::demand(
+        "heap",
+        VirtAddr::new(HEAP_START as u64),
+        HEAP_RESERVED as u64,
+        flags,
+    );
+    vmm.map_at(heap)?;
+    vmm.populate(heap.start, HEAP_SIZE as u64)?;
 
     unsafe {
         ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
@@ -52,5 +64,9 @@ unsafe impl GlobalAlloc for Dummy {
 
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
-    panic!("allocation error: {:?}", layout);
+    panic!(
+        "allocation error: {:?} (heap limit {} bytes)",
+        layout,
+        heap_limit()
+    );
 }
@@ -1,5 +1,7 @@
 use spin::{Mutex, MutexGuard};
 
+#[cfg(feature = "debug-alloc")]
+pub mod debug;
 pub mod fixed_size_list;
 pub mod heap;
 pub mod linked_list;
@@ -15,10 +17,37 @@ Defines multiplee global allocators
 // static ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
 
 /// Current used global allocator, uses FixedSize allocation, fallbacks to linklist.
-#[global_allocator]
+#[cfg_attr(not(feature = "debug-alloc"), global_allocator)]
 static ALLOCATOR: Locked<fixed_size_list::FixedSizeAllocator> =
     Locked::new(fixed_size_list::FixedSizeAllocator::new());
 
+/// Global allocator with the `debug-alloc` feature, checks every allocation before `ALLOCATOR`.
+#[cfg(feature = "debug-alloc")]
+#[global_allocator]
+static DEBUG_ALLOCATOR: debug::DebugAllocator<Locked<fixed_size_list::FixedSizeAllocator>> =
+    debug::DebugAllocator::new(&ALLOCATOR);
+
+/// Returns a snapshot of the global allocator's counters.
+pub fn allocator_stats() -> fixed_size_list::AllocatorStats {
+    ALLOCATOR.lock().stats()
+}
+
+/// Returns true while the global allocator is locked. A panic while it is held leaves it locked
+/// for good, as there is no unwinding.
+pub fn heap_locked() -> bool {
+    ALLOCATOR.inner.is_locked() || debug_locked()
+}
+
+#[cfg(feature = "debug-alloc")]
+fn debug_locked() -> bool {
+    DEBUG_ALLOCATOR.is_locked()
+}
+
+#[cfg(not(feature = "debug-alloc"))]
+fn debug_locked() -> bool {
+    false
+}
+
 /// Wrapper around a Mutex type to implement global allocation trait
 pub struct Locked<A> {
     inner: Mutex<A>,
@@ -1,30 +1,68 @@
 pub mod frame;
 pub mod globalloc;
 pub mod page;
+pub mod region;
+pub mod space;
+pub mod stack;
+pub mod vmm;
 
-use crate::kernel::mem::frame::BootInfoFrameAllocator;
+use crate::kernel::mem::frame::{BootInfoFrameAllocator, FRAME_SIZE};
+use crate::kernel::mem::globalloc::fixed_size_list::AllocatorStats;
 use bootloader::BootInfo;
-use x86_64::structures::paging::OffsetPageTable;
 use x86_64::VirtAddr;
 
-/// Memory items structure. Contains offset_page_table (mapper) and frame_allocator
-#[derive(Debug)]
-pub struct MemoryItems {
-    pub offset_page_table: OffsetPageTable<'static>,
-    pub frame_allocator: BootInfoFrameAllocator,
-}
+/// low-level memory initialization function, initates the offset_page_table, frame_allocator and
+/// global allocator, hands the first two to the virtual memory manager and prepares the kernel page
+/// table for sharing with user address spaces
+pub fn mem_init(boot_info: &'static BootInfo) {
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let offset_page_table = unsafe { page::init(physical_memory_offset) };
+    let frame_allocator =
+        unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map, physical_memory_offset) };
+    vmm::init(offset_page_table, frame_allocator);
 
-/// low-level memory initialization function, initates the offset_page_table, frame_allocator and global allocator
-pub fn mem_init(boot_info: &'static BootInfo) -> MemoryItems {
-    let mut offset_page_table =
-        unsafe { page::init(VirtAddr::new(boot_info.physical_memory_offset)) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let mut vmm = vmm::vmm().expect("vmm just initialized");
+    globalloc::heap::init_heap(&mut vmm).expect("heap initialization failed");
+    space::init(&mut vmm).expect("kernel page table initialization failed");
+}
 
-    globalloc::heap::init_heap(&mut offset_page_table, &mut frame_allocator)
-        .expect("heap initialization failed");
+/// Physical frame totals from the boot memory map.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    /// Usable frames reported by the memory map.
+    pub total: usize,
+    /// Frames currently allocated.
+    pub used: usize,
+    /// Frames currently free.
+    pub free: usize,
+}
 
-    MemoryItems {
-        offset_page_table,
-        frame_allocator,
+impl FrameStats {
+    /// Size of `frames` frames in KiB.
+    pub fn kib(frames: usize) -> usize {
+        frames * FRAME_SIZE as usize / 1024
     }
 }
+
+/// Snapshot of heap and physical memory accounting.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryStats {
+    pub heap: AllocatorStats,
+    /// `None` before `mem_init`.
+    pub frames: Option<FrameStats>,
+}
+
+/// Collects allocator and frame allocator counters.
+pub fn stats() -> MemoryStats {
+    let heap = globalloc::allocator_stats();
+    let frames = vmm::vmm().map(|mut vmm| {
+        let frame_allocator = vmm.frame_allocator();
+        FrameStats {
+            total: frame_allocator.total_frames(),
+            used: frame_allocator.used_frames(),
+            free: frame_allocator.free_frames(),
+        }
+    });
+
+    MemoryStats { heap, frames }
+}
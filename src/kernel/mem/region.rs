@@ -0,0 +1,220 @@
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/*
+Table of reserved kernel virtual memory regions. The page fault handler consults it, so it is a
+fixed size array that never touches the heap and is only locked with interrupts disabled.
+ */
+
+/// Maximum number of regions in the table.
+const MAX_REGIONS: usize = 256;
+
+/// Size of the unmapped guard at the bottom of a stack region.
+pub const GUARD_SIZE: u64 = 4096;
+
+static REGIONS: Mutex<RegionTable> = Mutex::new(RegionTable::new());
+
+/// A reserved range of kernel virtual memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    /// Name shown in diagnostics.
+    pub name: &'static str,
+    /// First address of the region.
+    pub start: VirtAddr,
+    /// First address past the region.
+    pub end: VirtAddr,
+    /// Flags pages of the region are mapped with.
+    pub flags: PageTableFlags,
+    /// Where the frames behind the region come from.
+    pub backing: Backing,
+}
+
+/// How the pages of a region are backed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// Frames are allocated and mapped when the region is created.
+    Allocated,
+    /// Frames are allocated by the page fault handler on first access.
+    Demand,
+    /// Pages map a fixed physical range the region does not own, e.g. device memory.
+    Physical,
+    /// Like `Allocated`, except the lowest `GUARD_SIZE` bytes are never mapped, so running off
+    /// the bottom of a stack faults instead of corrupting whatever lies below.
+    Stack,
+}
+
+impl Region {
+    /// Creates a region whose pages are mapped by its owner.
+    pub fn new(name: &'static str, start: VirtAddr, size: u64, flags: PageTableFlags) -> Self {
+        Region {
+            name,
+            start,
+            end: start + size,
+            flags,
+            backing: Backing::Allocated,
+        }
+    }
+
+    /// Creates a region whose pages are mapped by the page fault handler on first access.
+    pub fn demand(name: &'static str, start: VirtAddr, size: u64, flags: PageTableFlags) -> Self {
+        Region {
+            backing: Backing::Demand,
+            ..Region::new(name, start, size, flags)
+        }
+    }
+
+    /// Returns true if the page fault handler backs the region.
+    pub fn is_demand(&self) -> bool {
+        self.backing == Backing::Demand
+    }
+
+    /// Returns true if `addr` lies in the guard page of a stack region.
+    pub fn guard_contains(&self, addr: VirtAddr) -> bool {
+        self.backing == Backing::Stack && self.start <= addr && addr < self.start + GUARD_SIZE
+    }
+
+    /// Size of the region in bytes.
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Returns true if `addr` lies inside the region.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Returns true if the two regions share an address.
+    pub fn overlaps(&self, other: &Region) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+/// Errors when reserving a region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionError {
+    /// The region overlaps an existing one.
+    Overlap,
+    /// No free slot in the region table.
+    TableFull,
+    /// No region starts at the given address.
+    NotFound,
+    /// No gap large enough in the requested window.
+    NoSpace,
+}
+
+struct RegionTable {
+    regions: [Option<Region>; MAX_REGIONS],
+}
+
+impl RegionTable {
+    const fn new() -> Self {
+        const EMPTY: Option<Region> = None;
+        RegionTable {
+            regions: [EMPTY; MAX_REGIONS],
+        }
+    }
+
+    fn reserve(&mut self, region: Region) -> Result<(), RegionError> {
+        if self.iter().any(|r| r.overlaps(&region)) {
+            return Err(RegionError::Overlap);
+        }
+
+        let slot = self
+            .regions
+            .iter_mut()
+            .find(|r| r.is_none())
+            .ok_or(RegionError::TableFull)?;
+        *slot = Some(region);
+        Ok(())
+    }
+
+    /// Reserves `region` at the lowest address in `window` where it fits, keeping its size.
+    fn reserve_in(
+        &mut self,
+        window: (VirtAddr, VirtAddr),
+        mut region: Region,
+    ) -> Result<Region, RegionError> {
+        let size = region.size();
+        let (mut start, end) = (window.0.as_u64(), window.1.as_u64());
+        // every region skipped moves `start` past it, so this ends after at most one pass.
+        loop {
+            if start.checked_add(size).map_or(true, |last| last > end) {
+                return Err(RegionError::NoSpace);
+            }
+            match self
+                .iter()
+                .find(|r| r.start.as_u64() < start + size && start < r.end.as_u64())
+            {
+                Some(blocker) => start = blocker.end.align_up(4096u64).as_u64(),
+                None => break,
+            }
+        }
+
+        let start = VirtAddr::new(start);
+        region.start = start;
+        region.end = start + size;
+        self.reserve(region)?;
+        Ok(region)
+    }
+
+    fn set_flags(&mut self, start: VirtAddr, flags: PageTableFlags) -> Result<(), RegionError> {
+        let region = self
+            .regions
+            .iter_mut()
+            .flatten()
+            .find(|r| r.start == start)
+            .ok_or(RegionError::NotFound)?;
+        region.flags = flags;
+        Ok(())
+    }
+
+    fn release(&mut self, start: VirtAddr) -> Result<Region, RegionError> {
+        self.regions
+            .iter_mut()
+            .find(|r| matches!(r, Some(r) if r.start == start))
+            .and_then(|r| r.take())
+            .ok_or(RegionError::NotFound)
+    }
+
+    fn find(&self, addr: VirtAddr) -> Option<Region> {
+        self.iter().find(|r| r.contains(addr)).copied()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.regions.iter().flatten()
+    }
+}
+
+/// Reserves `region`. Fails if it overlaps an already reserved region.
+pub fn reserve(region: Region) -> Result<(), RegionError> {
+    without_interrupts(|| REGIONS.lock().reserve(region))
+}
+
+/// Reserves `region` at the lowest free address inside `window`, ignoring its start address.
+/// Returns the region as placed.
+pub fn reserve_in(window: (VirtAddr, VirtAddr), region: Region) -> Result<Region, RegionError> {
+    without_interrupts(|| REGIONS.lock().reserve_in(window, region))
+}
+
+/// Changes the flags recorded for the region starting at `start`.
+pub fn set_flags(start: VirtAddr, flags: PageTableFlags) -> Result<(), RegionError> {
+    without_interrupts(|| REGIONS.lock().set_flags(start, flags))
+}
+
+/// Removes the region starting at `start` from the table. Mapped pages are left alone.
+pub fn release(start: VirtAddr) -> Result<Region, RegionError> {
+    without_interrupts(|| REGIONS.lock().release(start))
+}
+
+/// Returns the region containing `addr`.
+pub fn find(addr: VirtAddr) -> Option<Region> {
+    without_interrupts(|| REGIONS.lock().find(addr))
+}
+
+/// Like `find`, but gives up instead of spinning if the table is locked. For exception handlers
+/// that may have interrupted a holder of the lock.
+pub fn try_find(addr: VirtAddr) -> Option<Region> {
+    without_interrupts(|| REGIONS.try_lock()?.find(addr))
+}
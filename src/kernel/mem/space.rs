@@ -0,0 +1,407 @@
+use crate::kernel::mem::frame::FRAME_SIZE;
+use crate::kernel::mem::globalloc::heap::{HEAP_RESERVED, HEAP_START};
+use crate::kernel::mem::region::{Backing, Region, RegionError, GUARD_SIZE};
+use crate::kernel::mem::vmm::{self, VirtualMemoryManager, VmError, VMALLOC_END, VMALLOC_START};
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::ops::{Range, RangeInclusive};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
+use x86_64::structures::paging::mapper::{MapToError, MappedFrame, Translate, TranslateResult};
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame,
+};
+use x86_64::VirtAddr;
+
+/*
+Address spaces of user programs. Each space has its own level 4 table. The entries covering the
+user window are private to the space, every other entry points at the same lower level tables as
+the kernel's own level 4 table, so kernel mappings are shared by all spaces. `init` creates the
+entries of the heap and the vmalloc window up front, as kernel stacks live there and a stack must
+be mapped before a fault on it can be handled. Creating all lower half entries would cost a frame
+each, so any other entry the kernel creates later reaches spaces created before it lazily: the
+first kernel access through it faults, and `share_kernel_entry` copies it over.
+
+`fork` shares the frames of a space with its copy instead of copying them. Writable pages turn read
+only and copy on write in both spaces, and the first write to one faults into `copy_on_write`,
+which gives the writing space its own copy. The frame allocator counts the references, so a
+shared frame is freed with the last space using it.
+ */
+
+/// Start of the window user memory is placed in.
+pub const USER_START: u64 = 0x_6000_0000_0000;
+/// First address past the user window.
+pub const USER_END: u64 = 0x_6100_0000_0000;
+
+/// Bytes covered by one level 4 entry.
+const P4_ENTRY_SIZE: u64 = 1 << 39;
+/// Level 4 entries covering the user window.
+const USER_ENTRIES: Range<usize> =
+    (USER_START / P4_ENTRY_SIZE) as usize..(USER_END / P4_ENTRY_SIZE) as usize;
+/// Level 4 entries of the lower half.
+const LOWER_HALF: Range<usize> = 0..256;
+/// Windows whose level 4 entries `init` creates, from start to end.
+const KERNEL_WINDOWS: [(u64, u64); 2] = [
+    (HEAP_START as u64, (HEAP_START + HEAP_RESERVED) as u64),
+    (VMALLOC_START, VMALLOC_END),
+];
+
+/// Marks read only pages that are writable once copied, one of the bits left to the OS.
+const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+/// Flags of the tables above user pages, the pages themselves restrict access.
+const TABLE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
+/// The level 4 table the kernel booted with, used by kernel threads.
+static KERNEL_PAGE_TABLE: OnceCell<PhysFrame> = OnceCell::uninit();
+
+/// Creates the entries of the kernel windows in the active level 4 table and remembers it as the
+/// kernel page table. Called once by `mem_init`.
+pub(crate) fn init(vmm: &mut VirtualMemoryManager) -> Result<(), VmError> {
+    // kernel writes to user memory must fault on copy on write pages too.
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
+
+    let (p4, _) = Cr3::read();
+    let p4_table = unsafe { table(vmm, p4) };
+    for index in KERNEL_WINDOWS
+        .iter()
+        .flat_map(|&(start, end)| entries(start, end))
+    {
+        if p4_table[index].is_unused() {
+            let frame = vmm.allocate_zeroed()?;
+            p4_table[index].set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
+    }
+
+    KERNEL_PAGE_TABLE
+        .try_init_once(|| p4)
+        .expect("space::init should only be called once");
+    Ok(())
+}
+
+/// The level 4 table of kernel threads.
+pub fn kernel_page_table() -> PhysFrame {
+    *KERNEL_PAGE_TABLE
+        .get()
+        .expect("kernel page table not initialized")
+}
+
+/// Copies the kernel's level 4 entry covering `addr` into the active space, if the kernel created
+/// it after the space was. Returns true if it did. Used by the page fault handler, so this must not
+/// allocate on the heap.
+pub fn share_kernel_entry(addr: VirtAddr) -> Result<bool, VmError> {
+    let index = usize::from(addr.p4_index());
+    let kernel = match KERNEL_PAGE_TABLE.get() {
+        Some(&kernel) => kernel,
+        None => return Ok(false),
+    };
+    let (active, _) = Cr3::read();
+    if !LOWER_HALF.contains(&index) || USER_ENTRIES.contains(&index) || active == kernel {
+        return Ok(false);
+    }
+
+    let mut vmm = vmm::fault_vmm(addr)?;
+    let kernel_table = unsafe { table(&mut vmm, kernel) };
+    let active_table = unsafe { table(&mut vmm, active) };
+    if kernel_table[index].is_unused() || !active_table[index].is_unused() {
+        return Ok(false);
+    }
+    // entries that are not present are never cached, so nothing needs flushing.
+    active_table[index] = kernel_table[index].clone();
+    Ok(true)
+}
+
+/// Level 4 entries covering the addresses from `start` to `end`.
+fn entries(start: u64, end: u64) -> RangeInclusive<usize> {
+    (start / P4_ENTRY_SIZE) as usize..=((end - 1) / P4_ENTRY_SIZE) as usize
+}
+
+/// Returns true if `addr` lies in the user window.
+pub fn is_user(addr: VirtAddr) -> bool {
+    (USER_START..USER_END).contains(&addr.as_u64())
+}
+
+/// Gives the active space its own writable copy of the copy on write page containing `addr`.
+/// Returns false if that page is not copy on write. Used by the page fault handler, so this must
+/// not allocate on the heap, and panics if the faulting code holds the manager, see `fault_vmm`.
+pub fn copy_on_write(addr: VirtAddr) -> Result<bool, VmError> {
+    let mut vmm = vmm::fault_vmm(addr)?;
+    let (p4, _) = Cr3::read();
+    let mut mapper = unsafe { mapper(&mut vmm, p4) };
+    let page = Page::containing_address(addr);
+    let (frame, flags) = match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags,
+            ..
+        } if flags.contains(COPY_ON_WRITE) => (frame, flags),
+        _ => return Ok(false),
+    };
+    let flags = (flags | PageTableFlags::WRITABLE) & !COPY_ON_WRITE;
+
+    // the last space using the frame can keep it.
+    if vmm.frame_allocator().references(frame) == 1 {
+        unsafe { mapper.update_flags(page, flags) }
+            .map_err(|_| VmError::HugePage)?
+            .flush();
+        return Ok(true);
+    }
+
+    let copy = vmm
+        .frame_allocator()
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let offset = vmm.page_table().phys_offset();
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            (offset + frame.start_address().as_u64()).as_ptr::<u8>(),
+            (offset + copy.start_address().as_u64()).as_mut_ptr::<u8>(),
+            FRAME_SIZE as usize,
+        );
+        let (_, flush) = mapper.unmap(page).map_err(|_| VmError::HugePage)?;
+        flush.flush();
+        // the tables are still there, so this allocates nothing.
+        mapper
+            .map_to_with_table_flags(page, copy, flags, TABLE_FLAGS, vmm.frame_allocator())?
+            .flush();
+        vmm.frame_allocator().deallocate_frame(frame);
+    }
+    Ok(true)
+}
+
+/// A level 4 table with private user memory. Its user frames and tables are freed on drop.
+#[derive(Debug)]
+pub struct AddressSpace {
+    p4: PhysFrame,
+    /// Regions of the user window mapped in this space.
+    regions: Vec<Region>,
+}
+
+impl AddressSpace {
+    /// Creates a space with the kernel mappings and no user memory.
+    pub fn new() -> Result<Self, VmError> {
+        let kernel = kernel_page_table();
+        let mut vmm = vmm::vmm().ok_or(VmError::Uninitialized)?;
+        let p4 = vmm.allocate_zeroed()?;
+
+        let kernel_table = unsafe { table(&mut vmm, kernel) };
+        let p4_table = unsafe { table(&mut vmm, p4) };
+        for (index, entry) in kernel_table.iter().enumerate() {
+            if !USER_ENTRIES.contains(&index) {
+                p4_table[index] = entry.clone();
+            }
+        }
+
+        Ok(AddressSpace {
+            p4,
+            regions: Vec::new(),
+        })
+    }
+
+    /// Frame of the level 4 table, for loading into CR3.
+    pub fn page_table(&self) -> PhysFrame {
+        self.p4
+    }
+
+    /// Regions mapped in this space.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Returns the region containing `addr`.
+    pub fn find(&self, addr: VirtAddr) -> Option<Region> {
+        self.regions.iter().find(|r| r.contains(addr)).copied()
+    }
+
+    /// Maps fresh zeroed frames behind `region`, which must lie in the user window and may not
+    /// overlap another region of the space. Regions need not be page aligned, a page shared with
+    /// a neighbouring region gets the flags of both.
+    pub fn map(&mut self, region: Region) -> Result<Region, VmError> {
+        if region.start >= region.end || !is_user(region.start) || !is_user(region.end - 1u64) {
+            return Err(RegionError::NoSpace.into());
+        }
+        if self.regions.iter().any(|r| r.overlaps(&region)) {
+            return Err(RegionError::Overlap.into());
+        }
+
+        let start = match region.backing {
+            Backing::Allocated => region.start,
+            Backing::Stack => region.start + GUARD_SIZE,
+            Backing::Demand | Backing::Physical => return Err(RegionError::NotFound.into()),
+        };
+        let first = Page::containing_address(start);
+        let last = Page::containing_address(region.end - 1u64);
+
+        let mut vmm = vmm::vmm().ok_or(VmError::Uninitialized)?;
+        let mut mapper = unsafe { mapper(&mut vmm, self.p4) };
+        for page in Page::range_inclusive(first, last) {
+            match mapper.translate_page(page) {
+                Ok(_) => {
+                    let flags = shared_flags(page, &self.regions, region.flags);
+                    unsafe { mapper.update_flags(page, flags) }
+                        .map_err(|_| VmError::HugePage)?
+                        .flush();
+                }
+                // pages mapped before a failure are freed with the space.
+                Err(_) => {
+                    let frame = vmm.allocate_zeroed()?;
+                    let result =
+                        unsafe { mapper.map_to(page, frame, region.flags, vmm.frame_allocator()) };
+                    match result {
+                        Ok(flush) => flush.flush(),
+                        Err(error) => {
+                            unsafe { vmm.frame_allocator().deallocate_frame(frame) };
+                            return Err(error.into());
+                        }
+                    }
+                }
+            }
+        }
+        drop(vmm);
+
+        self.regions.push(region);
+        Ok(region)
+    }
+
+    /// Creates a space with the same user memory, sharing every mapped frame with this one.
+    /// Writable pages become copy on write in both spaces. The pages of this space are flushed from
+    /// the TLB, so it should be the active space or not be loaded at all.
+    pub fn fork(&mut self) -> Result<AddressSpace, VmError> {
+        let mut child = AddressSpace::new()?;
+        child.regions = self.regions.clone();
+
+        let mut vmm = vmm::vmm().ok_or(VmError::Uninitialized)?;
+        let mut parent = unsafe { mapper(&mut vmm, self.p4) };
+        let mut copy = unsafe { mapper(&mut vmm, child.p4) };
+        for region in &self.regions {
+            let first = Page::containing_address(region.start);
+            let last = Page::containing_address(region.end - 1u64);
+            for page in Page::range_inclusive(first, last) {
+                let (frame, mut flags) = match parent.translate(page.start_address()) {
+                    TranslateResult::Mapped {
+                        frame: MappedFrame::Size4KiB(frame),
+                        flags,
+                        ..
+                    } => (frame, flags),
+                    // guard pages.
+                    _ => continue,
+                };
+                // pages shared with a neighbouring region come up twice.
+                if copy.translate_page(page).is_ok() {
+                    continue;
+                }
+
+                if flags.contains(PageTableFlags::WRITABLE) {
+                    flags = flags & !PageTableFlags::WRITABLE | COPY_ON_WRITE;
+                    unsafe { parent.update_flags(page, flags) }
+                        .map_err(|_| VmError::HugePage)?
+                        .flush();
+                }
+                // pages mapped before a failure are released with the child.
+                unsafe {
+                    copy.map_to_with_table_flags(
+                        page,
+                        frame,
+                        flags,
+                        TABLE_FLAGS,
+                        vmm.frame_allocator(),
+                    )?
+                    .flush();
+                }
+                vmm.frame_allocator().share(frame);
+            }
+        }
+        drop(vmm);
+
+        Ok(child)
+    }
+
+    /// Copies `data` to `addr` through the physical memory mapping, so read only pages can be
+    /// filled too. Every page written must be mapped and not shared by `fork`.
+    pub fn write(&mut self, addr: VirtAddr, data: &[u8]) -> Result<(), VmError> {
+        let mut vmm = vmm::vmm().ok_or(VmError::Uninitialized)?;
+        let offset = vmm.page_table().phys_offset();
+        let mapper = unsafe { mapper(&mut vmm, self.p4) };
+
+        let mut written = 0;
+        while written < data.len() {
+            let addr = addr + written as u64;
+            let phys = mapper.translate_addr(addr).ok_or(RegionError::NotFound)?;
+            let count =
+                (FRAME_SIZE - addr.as_u64() % FRAME_SIZE).min((data.len() - written) as u64);
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data[written..].as_ptr(),
+                    (offset + phys.as_u64()).as_mut_ptr::<u8>(),
+                    count as usize,
+                );
+            }
+            written += count as usize;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        debug_assert_ne!(Cr3::read().0, self.p4, "dropped the active address space");
+        let mut vmm = vmm::vmm().expect("address space outlived the vmm");
+        unsafe {
+            let p4_table = table(&mut vmm, self.p4);
+            for index in USER_ENTRIES {
+                free_table(&mut vmm, &p4_table[index], 3);
+            }
+            vmm.frame_allocator().deallocate_frame(self.p4);
+        }
+    }
+}
+
+/// Flags for `page` when it is shared between a new region mapped with `flags` and the regions
+/// already in `regions`: writable if any of them is, executable if any of them is.
+fn shared_flags(page: Page, regions: &[Region], flags: PageTableFlags) -> PageTableFlags {
+    let page_end = page.start_address() + FRAME_SIZE;
+    regions
+        .iter()
+        .filter(|r| r.start < page_end && page.start_address() < r.end)
+        .fold(flags, |flags, r| {
+            let no_execute = flags & r.flags & PageTableFlags::NO_EXECUTE;
+            (flags | r.flags) & !PageTableFlags::NO_EXECUTE | no_execute
+        })
+}
+
+/// Frees the table `entry` points to, `level` being the level of that table, along with every
+/// table and frame below it.
+unsafe fn free_table(vmm: &mut VirtualMemoryManager, entry: &PageTableEntry, level: u8) {
+    let frame = match entry.frame() {
+        Ok(frame) => frame,
+        Err(_) => return,
+    };
+    if level > 1 {
+        let next = table(vmm, frame);
+        for entry in next.iter() {
+            free_table(vmm, entry, level - 1);
+        }
+    } else {
+        for entry in table(vmm, frame).iter() {
+            if let Ok(frame) = entry.frame() {
+                vmm.frame_allocator().deallocate_frame(frame);
+            }
+        }
+    }
+    vmm.frame_allocator().deallocate_frame(frame);
+}
+
+/// The page table in `frame`, through the physical memory mapping.
+unsafe fn table(vmm: &mut VirtualMemoryManager, frame: PhysFrame) -> &'static mut PageTable {
+    let offset = vmm.page_table().phys_offset();
+    &mut *(offset + frame.start_address().as_u64()).as_mut_ptr::<PageTable>()
+}
+
+/// A mapper over the level 4 table in `p4`.
+unsafe fn mapper(vmm: &mut VirtualMemoryManager, p4: PhysFrame) -> OffsetPageTable<'static> {
+    let offset = vmm.page_table().phys_offset();
+    OffsetPageTable::new(table(vmm, p4), offset)
+}
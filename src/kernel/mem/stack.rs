@@ -0,0 +1,71 @@
+use crate::kernel::mem::region::{self, Backing, Region, GUARD_SIZE};
+use crate::kernel::mem::vmm::{self, VmError};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/*
+Kernel stacks. Each stack is a region of the vmalloc window with an unmapped guard page below it,
+so an overflow faults on the guard page and can be traced back to the stack by name.
+ */
+
+/// Default size of a kernel stack, excluding the guard page.
+pub const DEFAULT_STACK_SIZE: u64 = 16 * 4096;
+
+/// A mapped kernel stack. Stacks are not freed on drop, see `free`.
+#[derive(Debug, Clone, Copy)]
+pub struct KernelStack {
+    region: Region,
+}
+
+impl KernelStack {
+    /// Maps a stack of `size` bytes, rounded up to whole pages, above a guard page.
+    pub fn allocate(name: &'static str, size: u64) -> Result<Self, VmError> {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let region = vmm::vmm().ok_or(VmError::Uninitialized)?.allocate(
+            name,
+            size,
+            flags,
+            Backing::Stack,
+        )?;
+        Ok(KernelStack { region })
+    }
+
+    /// Name the stack was allocated with.
+    pub fn name(&self) -> &'static str {
+        self.region.name
+    }
+
+    /// Initial stack pointer, the first address past the stack.
+    pub fn top(&self) -> VirtAddr {
+        self.region.end
+    }
+
+    /// Lowest usable address of the stack.
+    pub fn bottom(&self) -> VirtAddr {
+        self.region.start + GUARD_SIZE
+    }
+
+    /// Start of the guard page below the stack.
+    pub fn guard(&self) -> VirtAddr {
+        self.region.start
+    }
+
+    /// Unmaps the stack and returns its frames.
+    ///
+    /// # Safety
+    /// Nothing may run on the stack or point into it anymore.
+    pub unsafe fn free(self) -> Result<(), VmError> {
+        vmm::vmm()
+            .ok_or(VmError::Uninitialized)?
+            .unmap(self.region.start)?;
+        Ok(())
+    }
+}
+
+/// Returns the name of the stack whose guard page contains `addr`. Safe to call from exception
+/// handlers, it gives up if the region table is locked.
+pub fn overflowed(addr: VirtAddr) -> Option<&'static str> {
+    region::try_find(addr)
+        .filter(|region| region.guard_contains(addr))
+        .map(|region| region.name)
+}
@@ -0,0 +1,389 @@
+use crate::kernel::mem::frame::{BootInfoFrameAllocator, FRAME_SIZE};
+use crate::kernel::mem::region::{self, Backing, Region, RegionError, GUARD_SIZE};
+use conquer_once::spin::OnceCell;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::mapper::{FlagUpdateError, MapToError, Translate, UnmapError};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame,
+    Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/*
+Kernel virtual memory manager. It owns the page table and the frame allocator and hands out named
+ranges of kernel address space. Every range is recorded in the region table, which is what the page
+fault handler and `query` look at. Ranges without a fixed address are placed in the vmalloc window.
+ */
+
+/// Start of the window `allocate` and `map_physical` place regions in.
+pub const VMALLOC_START: u64 = 0x_5000_0000_0000;
+/// First address past the vmalloc window.
+pub const VMALLOC_END: u64 = 0x_5100_0000_0000;
+
+/// The manager, set once by `mem_init`. The page fault handler locks it to back demand paged
+/// regions and panics if it is already held, so nothing may allocate on the heap while holding it,
+/// and it is only held with interrupts disabled, see `VmmGuard`.
+static VMM: OnceCell<Mutex<VirtualMemoryManager>> = OnceCell::uninit();
+
+/// Errors of virtual memory operations.
+#[derive(Debug)]
+pub enum VmError {
+    /// The region table refused the range.
+    Region(RegionError),
+    /// Mapping a page failed.
+    Map(MapToError<Size4KiB>),
+    /// A page is part of a huge page and can not be changed on its own.
+    HugePage,
+    /// The address or size is not page aligned, or the size is zero.
+    Unaligned,
+    /// The manager is not initialized yet.
+    Uninitialized,
+}
+
+impl From<RegionError> for VmError {
+    fn from(error: RegionError) -> Self {
+        VmError::Region(error)
+    }
+}
+
+impl From<MapToError<Size4KiB>> for VmError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        VmError::Map(error)
+    }
+}
+
+/// Owner of the kernel page table and physical frames.
+#[derive(Debug)]
+pub struct VirtualMemoryManager {
+    page_table: OffsetPageTable<'static>,
+    frame_allocator: BootInfoFrameAllocator,
+}
+
+impl VirtualMemoryManager {
+    /// The physical frame allocator.
+    pub fn frame_allocator(&mut self) -> &mut BootInfoFrameAllocator {
+        &mut self.frame_allocator
+    }
+
+    /// The kernel page table, for callers that need raw `Mapper` access.
+    pub fn page_table(&mut self) -> &mut OffsetPageTable<'static> {
+        &mut self.page_table
+    }
+
+    /// Reserves `region` at its own address. Allocated regions are mapped to fresh zeroed frames
+    /// right away, demand regions on first access.
+    pub fn map_at(&mut self, region: Region) -> Result<Region, VmError> {
+        check_aligned(region.start, region.size())?;
+        region::reserve(region)?;
+        self.populate_region(region)
+    }
+
+    /// Reserves `size` bytes, rounded up to whole pages, somewhere in the vmalloc window. Stack
+    /// regions get an extra guard page below the `size` usable bytes.
+    pub fn allocate(
+        &mut self,
+        name: &'static str,
+        size: u64,
+        flags: PageTableFlags,
+        backing: Backing,
+    ) -> Result<Region, VmError> {
+        self.allocate_in(vmalloc_window(), name, size, flags, backing)
+    }
+
+    /// Like `allocate`, but places the region in `window` instead of the vmalloc window.
+    pub fn allocate_in(
+        &mut self,
+        window: (VirtAddr, VirtAddr),
+        name: &'static str,
+        size: u64,
+        flags: PageTableFlags,
+        backing: Backing,
+    ) -> Result<Region, VmError> {
+        let mut size = page_align(size)?;
+        if backing == Backing::Stack {
+            size += GUARD_SIZE;
+        }
+        let region = Region {
+            backing,
+            ..Region::new(name, window.0, size, flags)
+        };
+        let region = region::reserve_in(window, region)?;
+        self.populate_region(region)
+    }
+
+    /// Maps `size` bytes of physical memory at `phys`, e.g. device registers, into the vmalloc
+    /// window. Returns the virtual address of `phys`.
+    pub fn map_physical(
+        &mut self,
+        name: &'static str,
+        phys: PhysAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<VirtAddr, VmError> {
+        let offset = phys.as_u64() % FRAME_SIZE;
+        let size = page_align(offset + size)?;
+        let region = Region {
+            backing: Backing::Physical,
+            ..Region::new(name, VirtAddr::new(VMALLOC_START), size, flags)
+        };
+        let region = region::reserve_in(vmalloc_window(), region)?;
+
+        let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+        for (i, page) in pages(region.start, region.size()).enumerate() {
+            let frame = first_frame + i as u64;
+            if let Err(error) = self.map_page(page, frame, flags) {
+                self.unmap(region.start)?;
+                return Err(error);
+            }
+        }
+
+        Ok(region.start + offset)
+    }
+
+    /// Maps fresh zeroed frames behind the `size` bytes at `start`, which must lie inside a demand
+    /// paged region. Pages already mapped are left alone.
+    pub fn populate(&mut self, start: VirtAddr, size: u64) -> Result<(), VmError> {
+        check_aligned(start, size)?;
+        let region = region::find(start).ok_or(RegionError::NotFound)?;
+        if !region.is_demand() || start + size > region.end {
+            return Err(RegionError::NotFound.into());
+        }
+
+        for page in pages(start, size) {
+            if self.page_table.translate_page(page).is_err() {
+                self.map_fresh_page(page, region.flags)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unmaps the region starting at `start` and releases it. Frames of allocated and demand
+    /// paged regions are returned to the frame allocator.
+    pub fn unmap(&mut self, start: VirtAddr) -> Result<Region, VmError> {
+        let region = region::find(start)
+            .filter(|region| region.start == start)
+            .ok_or(RegionError::NotFound)?;
+
+        for page in pages(region.start, region.size()) {
+            match self.page_table.unmap(page) {
+                Ok((frame, flush)) => {
+                    flush.flush();
+                    if region.backing != Backing::Physical {
+                        unsafe { self.frame_allocator.deallocate_frame(frame) };
+                    }
+                }
+                // demand paged regions have holes.
+                Err(UnmapError::PageNotMapped) => {}
+                Err(_) => return Err(VmError::HugePage),
+            }
+        }
+
+        Ok(region::release(start)?)
+    }
+
+    /// Changes the flags of every mapped page of the region starting at `start`. Pages mapped
+    /// later use the new flags as well.
+    pub fn protect(&mut self, start: VirtAddr, flags: PageTableFlags) -> Result<(), VmError> {
+        let region = region::find(start)
+            .filter(|region| region.start == start)
+            .ok_or(RegionError::NotFound)?;
+
+        region::set_flags(start, flags)?;
+        for page in pages(region.start, region.size()) {
+            match unsafe { self.page_table.update_flags(page, flags) } {
+                Ok(flush) => flush.flush(),
+                Err(FlagUpdateError::PageNotMapped) => {}
+                Err(_) => return Err(VmError::HugePage),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Physical address `addr` is mapped to.
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        self.page_table.translate_addr(addr)
+    }
+
+    /// Maps the pages of a freshly reserved region according to its backing. The region is
+    /// released again if that fails.
+    fn populate_region(&mut self, region: Region) -> Result<Region, VmError> {
+        let (start, size) = match region.backing {
+            Backing::Allocated => (region.start, region.size()),
+            Backing::Stack => (region.start + GUARD_SIZE, region.size() - GUARD_SIZE),
+            Backing::Demand | Backing::Physical => return Ok(region),
+        };
+
+        for page in pages(start, size) {
+            if let Err(error) = self.map_fresh_page(page, region.flags) {
+                self.unmap(region.start)?;
+                return Err(error);
+            }
+        }
+
+        Ok(region)
+    }
+
+    /// Allocates a frame and zeroes it through the physical memory mapping, so it can be handed
+    /// out with any flags.
+    pub fn allocate_zeroed(&mut self) -> Result<PhysFrame, VmError> {
+        let frame = self
+            .frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let frame_virt = self.page_table.phys_offset() + frame.start_address().as_u64();
+        unsafe { core::ptr::write_bytes(frame_virt.as_mut_ptr::<u8>(), 0, FRAME_SIZE as usize) };
+        Ok(frame)
+    }
+
+    /// Maps `page` to a newly allocated frame and zeroes it.
+    fn map_fresh_page(&mut self, page: Page, flags: PageTableFlags) -> Result<(), VmError> {
+        let frame = self.allocate_zeroed()?;
+        if let Err(error) = self.map_page(page, frame, flags) {
+            unsafe { self.frame_allocator.deallocate_frame(frame) };
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn map_page(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), VmError> {
+        unsafe {
+            self.page_table
+                .map_to(page, frame, flags, &mut self.frame_allocator)?
+                .flush();
+        }
+        Ok(())
+    }
+}
+
+/// Takes ownership of the page table and frame allocator. Called once by `mem_init`.
+pub(crate) fn init(page_table: OffsetPageTable<'static>, frame_allocator: BootInfoFrameAllocator) {
+    VMM.try_init_once(|| {
+        Mutex::new(VirtualMemoryManager {
+            page_table,
+            frame_allocator,
+        })
+    })
+    .expect("vmm::init should only be called once");
+}
+
+/// Lock on the manager. Interrupts stay disabled while it is held, so the holder can not be
+/// preempted by another thread that then page faults on a demand paged region.
+pub struct VmmGuard {
+    guard: ManuallyDrop<MutexGuard<'static, VirtualMemoryManager>>,
+    interrupts_enabled: bool,
+}
+
+impl Deref for VmmGuard {
+    type Target = VirtualMemoryManager;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl DerefMut for VmmGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl Drop for VmmGuard {
+    fn drop(&mut self) {
+        // unlock before interrupts can come in again.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Locks the manager. Returns `None` before `mem_init`.
+pub fn vmm() -> Option<VmmGuard> {
+    let vmm = VMM.try_get().ok()?;
+    let interrupts_enabled = interrupts::are_enabled();
+    interrupts::disable();
+    Some(VmmGuard {
+        guard: ManuallyDrop::new(vmm.lock()),
+        interrupts_enabled,
+    })
+}
+
+/// Locks the manager if it is free, for code that must not wait. Errs before `mem_init`.
+fn try_vmm() -> Result<Option<VmmGuard>, VmError> {
+    let vmm = VMM.try_get().map_err(|_| VmError::Uninitialized)?;
+    let interrupts_enabled = interrupts::are_enabled();
+    interrupts::disable();
+    match vmm.try_lock() {
+        Some(guard) => Ok(Some(VmmGuard {
+            guard: ManuallyDrop::new(guard),
+            interrupts_enabled,
+        })),
+        None => {
+            if interrupts_enabled {
+                interrupts::enable();
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Returns true while the manager is locked.
+pub fn is_locked() -> bool {
+    VMM.try_get().map_or(false, |vmm| vmm.is_locked())
+}
+
+/// Returns the region containing `addr`.
+pub fn query(addr: VirtAddr) -> Option<Region> {
+    region::find(addr)
+}
+
+/// Locks the manager for the page fault handler, which faulted at `addr`. Panics if the faulting
+/// code holds the manager, as waiting would spin forever with interrupts disabled.
+pub(crate) fn fault_vmm(addr: VirtAddr) -> Result<VmmGuard, VmError> {
+    Ok(try_vmm()?.unwrap_or_else(|| panic!("page fault while holding the VMM lock at {:?}", addr)))
+}
+
+/// Backs the page containing `addr` with a fresh zeroed frame. Used by the page fault handler for
+/// demand paged regions, so this must not allocate. Panics if the faulting code holds the manager,
+/// see `fault_vmm`.
+pub fn map_demand_page(addr: VirtAddr, flags: PageTableFlags) -> Result<Page, VmError> {
+    let mut vmm = fault_vmm(addr)?;
+    let page = Page::containing_address(addr);
+    vmm.map_fresh_page(page, flags)?;
+    Ok(page)
+}
+
+fn vmalloc_window() -> (VirtAddr, VirtAddr) {
+    (VirtAddr::new(VMALLOC_START), VirtAddr::new(VMALLOC_END))
+}
+
+/// Rounds `size` up to whole pages.
+fn page_align(size: u64) -> Result<u64, VmError> {
+    match size {
+        0 => Err(VmError::Unaligned),
+        size => Ok((size + FRAME_SIZE - 1) & !(FRAME_SIZE - 1)),
+    }
+}
+
+fn check_aligned(start: VirtAddr, size: u64) -> Result<(), VmError> {
+    if size == 0 || !start.is_aligned(FRAME_SIZE) || size % FRAME_SIZE != 0 {
+        return Err(VmError::Unaligned);
+    }
+    Ok(())
+}
+
+/// The pages covering `size` bytes at page aligned `start`.
+fn pages(start: VirtAddr, size: u64) -> impl Iterator<Item = Page> {
+    let first = Page::containing_address(start);
+    (0..size / FRAME_SIZE).map(move |i| first + i)
+}
@@ -1,8 +1,13 @@
+pub mod acpi;
+pub mod clock;
 pub mod environ;
 pub mod fs;
 pub mod gdt;
 pub mod interrupts;
 pub mod mem;
+pub mod process;
 pub mod sc;
 pub mod status;
 pub mod task;
+pub mod thread;
+pub mod user;
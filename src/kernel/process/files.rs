@@ -0,0 +1,71 @@
+/*
+File descriptor tables. A descriptor is an index into the table of its process and names an open
+file of the filesystem along with the position the next read starts at.
+ */
+
+/// Maximum number of files a process can have open at once.
+pub const MAX_FILES: usize = 16;
+
+/// A file opened by a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFile {
+    /// Filesystem id of the file.
+    pub id: u16,
+    /// Offset the next read starts at.
+    pub offset: usize,
+}
+
+/// Open files of a process, indexed by descriptor.
+#[derive(Debug, Clone)]
+pub struct FileTable {
+    files: [Option<OpenFile>; MAX_FILES],
+}
+
+impl FileTable {
+    /// A table without open files.
+    pub const fn new() -> Self {
+        FileTable {
+            files: [None; MAX_FILES],
+        }
+    }
+
+    /// Opens the file `id` at offset zero. Returns its descriptor, the lowest one free, or `None`
+    /// if the table is full.
+    pub fn open(&mut self, id: u16) -> Option<usize> {
+        let fd = self.files.iter().position(Option::is_none)?;
+        self.files[fd] = Some(OpenFile { id, offset: 0 });
+        Some(fd)
+    }
+
+    /// The file open as `fd`.
+    pub fn get(&self, fd: usize) -> Option<OpenFile> {
+        *self.files.get(fd)?
+    }
+
+    /// Moves the read position of `fd` to `offset`. Returns false if `fd` is not open.
+    pub fn seek(&mut self, fd: usize, offset: usize) -> bool {
+        match self.files.get_mut(fd) {
+            Some(Some(file)) => {
+                file.offset = offset;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Closes `fd`, returning the file it named.
+    pub fn close(&mut self, fd: usize) -> Option<OpenFile> {
+        self.files.get_mut(fd)?.take()
+    }
+
+    /// Number of open files.
+    pub fn count(&self) -> usize {
+        self.files.iter().flatten().count()
+    }
+}
+
+impl Default for FileTable {
+    fn default() -> Self {
+        FileTable::new()
+    }
+}
@@ -0,0 +1,322 @@
+use crate::kernel::environ::{environmentref, Environment};
+use crate::kernel::mem::space::{self, AddressSpace};
+use crate::kernel::status::Status;
+use crate::kernel::thread::switch::SwitchFrame;
+use crate::kernel::thread::{self, JoinHandle, ThreadError, ThreadId};
+use crate::kernel::user::image::Image;
+use crate::kernel::user::{self, UserError};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use files::FileTable;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::structures::paging::PhysFrame;
+
+pub mod files;
+
+/*
+Processes. A process is a user program together with everything it owns: its address space, its
+open files, and its environment, which holds the working directory under `cwd`. Processes start
+with a copy of the environment of whoever created them, so changes made by a program stay inside
+it. A process frees its memory and files when it exits but stays in the table, exited, until
+`wait` collects its status, or `wait_async` for tasks, which must not block the executor's thread.
+`fork` clones the calling process, sharing its memory copy on write.
+ */
+
+/// Structure for process IDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pid(u64);
+
+impl Pid {
+    /// Create an ID, on higher than the last still in existence.
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Pid(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl core::fmt::Display for Pid {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Where a process is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    /// The process ended with the status and waits to be collected.
+    Exited(Status),
+}
+
+/// A process in the process table.
+#[derive(Debug)]
+pub struct Process {
+    pid: Pid,
+    /// The process that created this one, `None` if it was created by the kernel.
+    parent: Option<Pid>,
+    name: String,
+    /// The thread running the program.
+    thread: ThreadId,
+    /// `None` once the process exited.
+    space: Option<AddressSpace>,
+    files: FileTable,
+    env: Environment,
+    state: State,
+    /// Where the status goes for the thread's `JoinHandle`.
+    result: Arc<Mutex<Option<Status>>>,
+    /// Taken by `wait`.
+    handle: Option<JoinHandle<Status>>,
+}
+
+impl Process {
+    /// A process about to run on a new thread.
+    fn new(
+        name: &str,
+        parent: Option<Pid>,
+        space: AddressSpace,
+        files: FileTable,
+        env: Environment,
+    ) -> Self {
+        Process {
+            pid: Pid::new(),
+            parent,
+            name: String::from(name),
+            thread: ThreadId::new(),
+            space: Some(space),
+            files,
+            env,
+            state: State::Running,
+            result: Arc::new(Mutex::new(None)),
+            handle: None,
+        }
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn parent(&self) -> Option<Pid> {
+        self.parent
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Address space of the program, `None` once the process exited.
+    pub fn space(&self) -> Option<&AddressSpace> {
+        self.space.as_ref()
+    }
+
+    pub fn files(&mut self) -> &mut FileTable {
+        &mut self.files
+    }
+
+    pub fn env(&mut self) -> &mut Environment {
+        &mut self.env
+    }
+
+    /// Working directory of the process.
+    pub fn cwd(&self) -> String {
+        self.env.cwd()
+    }
+
+    /// `path` relative to the working directory of the process.
+    pub fn path(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            return String::from(path);
+        }
+        let cwd = self.cwd();
+        match cwd.ends_with('/') {
+            true => format!("{}{}", cwd, path),
+            false => format!("{}/{}", cwd, path),
+        }
+    }
+}
+
+/// Snapshot of a process for listings.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub parent: Option<Pid>,
+    pub name: String,
+    pub state: State,
+    /// Number of open files.
+    pub files: usize,
+}
+
+lazy_static! {
+    static ref PROCESSES: Mutex<BTreeMap<Pid, Process>> = Mutex::new(BTreeMap::new());
+}
+
+/// Loads `image` into a new address space and starts it as a process running `args`. The process
+/// inherits the environment of the current process, or the global one when called from the
+/// kernel, and gets it as `name=value` strings after `args`.
+pub fn create(name: &str, image: &Image, args: &[&str]) -> Result<Pid, UserError> {
+    let env =
+        with_current(|process| process.env.clone()).unwrap_or_else(|| environmentref().snapshot());
+    let strings: Vec<String> = env
+        .keys()
+        .iter()
+        .map(|key| format!("{}={}", key.name, key.value))
+        .collect();
+    let strings: Vec<&str> = strings.iter().map(String::as_str).collect();
+
+    let mut space = AddressSpace::new()?;
+    let start = user::load(&mut space, image, args, &strings)?;
+    let registers = [start.argc, start.argv.as_u64(), start.envp.as_u64()];
+    let entry = image.entry;
+
+    let process = Process::new(name, current(), space, FileTable::new(), env);
+    insert(process, |thread, page_table, result| {
+        thread::spawn_user(
+            thread, "process", page_table, entry, start.rsp, registers, result,
+        )
+    })
+}
+
+/// Creates a child of the current process, running from a copy of the user registers in `frame`
+/// with rax cleared. The child gets a copy on write copy of the address space and copies of the
+/// open files and the environment.
+pub(crate) fn fork(frame: &SwitchFrame) -> Result<Pid, UserError> {
+    let child = with_current(|process| -> Result<Process, UserError> {
+        let space = process.space.as_mut().ok_or(UserError::NotFound)?.fork()?;
+        Ok(Process::new(
+            &process.name,
+            Some(process.pid),
+            space,
+            process.files.clone(),
+            process.env.clone(),
+        ))
+    })
+    .ok_or(UserError::NotFound)??;
+
+    let frame = SwitchFrame {
+        rax: 0,
+        ..frame.clone()
+    };
+    insert(child, |thread, page_table, result| {
+        thread::spawn_user_frame(thread, "process", page_table, frame, result)
+    })
+}
+
+/// Adds `process` to the table and starts its thread with `spawn`, which gets the thread ID, the
+/// page table and where the status goes.
+fn insert(
+    process: Process,
+    spawn: impl FnOnce(
+        ThreadId,
+        PhysFrame,
+        Arc<Mutex<Option<Status>>>,
+    ) -> Result<JoinHandle<Status>, ThreadError>,
+) -> Result<Pid, UserError> {
+    let pid = process.pid;
+    let thread = process.thread;
+    let result = process.result.clone();
+    let page_table = process
+        .space
+        .as_ref()
+        .map(AddressSpace::page_table)
+        .ok_or(UserError::NotFound)?;
+    // registered before the thread exists, so it can exit at any point.
+    PROCESSES.lock().insert(pid, process);
+
+    match spawn(thread, page_table, result) {
+        Ok(handle) => {
+            if let Some(process) = PROCESSES.lock().get_mut(&pid) {
+                process.handle = Some(handle);
+            }
+            Ok(pid)
+        }
+        Err(error) => {
+            let process = PROCESSES.lock().remove(&pid);
+            drop(process);
+            Err(UserError::Thread(error))
+        }
+    }
+}
+
+/// Blocks until process `pid` exits, removes it from the table and returns its status. Returns
+/// `None` if there is no such process or another thread is already waiting for it.
+pub fn wait(pid: Pid) -> Option<Status> {
+    let handle = PROCESSES.lock().get_mut(&pid)?.handle.take()?;
+    let status = handle.join();
+    let process = PROCESSES.lock().remove(&pid);
+    drop(process);
+    Some(status)
+}
+
+/// Waits for process `pid` to exit like `wait`, but as a task, without blocking the thread.
+pub async fn wait_async(pid: Pid) -> Option<Status> {
+    let handle = PROCESSES.lock().get_mut(&pid)?.handle.take()?;
+    handle.finished().await;
+    let status = handle.join();
+    let process = PROCESSES.lock().remove(&pid);
+    drop(process);
+    Some(status)
+}
+
+/// Ends the current process with `status`, freeing its memory and closing its files.
+pub(crate) fn exit(status: Status) -> ! {
+    let current = thread::current();
+    let exited = PROCESSES
+        .lock()
+        .values_mut()
+        .find(|process| process.thread == current)
+        .map(|process| {
+            process.state = State::Exited(status);
+            process.files = FileTable::new();
+            (process.space.take(), process.result.clone())
+        });
+
+    if let Some((space, result)) = exited {
+        // the space can not be freed while it is loaded.
+        thread::set_page_table(space::kernel_page_table());
+        drop(space);
+        *result.lock() = Some(status);
+    }
+    thread::exit()
+}
+
+/// ID of the process running on the current thread, `None` on kernel threads.
+pub fn current() -> Option<Pid> {
+    with_current(|process| process.pid)
+}
+
+/// Runs `f` on the process of the current thread. The process table stays locked meanwhile.
+pub fn with_current<R>(f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    let current = thread::current();
+    let mut processes = PROCESSES.lock();
+    let process = processes
+        .values_mut()
+        .find(|process| process.thread == current)?;
+    Some(f(process))
+}
+
+/// The processes in the table, ordered by ID.
+pub fn list() -> Vec<ProcessInfo> {
+    PROCESSES
+        .lock()
+        .values()
+        .map(|process| ProcessInfo {
+            pid: process.pid,
+            parent: process.parent,
+            name: process.name.clone(),
+            state: process.state,
+            files: process.files.count(),
+        })
+        .collect()
+}
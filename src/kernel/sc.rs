@@ -1,119 +1,145 @@
-use core::{fmt::Display, ops::Add};
+use core::{
+    convert::TryFrom,
+    fmt::Display,
+    ops::{Add, AddAssign, Sub, SubAssign},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
-use lazy_static::lazy_static;
-use spin::Mutex;
+use crate::kernel::{clock, interrupts::pit};
 
-lazy_static! {
-    pub static ref SYSTEM_CLOCK: Mutex<SystemClock> = Mutex::new(SystemClock::new(10.0));
-}
+/// Counts the timer interrupts since boot, ticked by the timer interrupt handler.
+pub static SYSTEM_CLOCK: SystemClock = SystemClock::new();
 
+/// A point in time, in nanoseconds since the clock started, see `clock`.
 #[repr(transparent)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Instant(usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
 
 impl Instant {
     pub fn now() -> Self {
-        SYSTEM_CLOCK.lock().now()
+        Self(clock::nanos())
     }
 
     pub fn zero() -> Self {
         Self(0)
     }
 
-    pub fn checked_add(&self, rhs: usize) -> Option<Self> {
-        self.0.checked_add(rhs).map(|i| Instant(i))
+    /// Time passed since `earlier`, zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
     }
 
-    pub fn seconds(&self) -> usize {
-        let tick = self.0;
-        let seconds = tick as f32 / SYSTEM_CLOCK.lock().rate;
-        seconds as usize
+    /// Time passed since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
     }
 
-    pub fn minutes(&self) -> (usize, usize) {
-        let seconds = self.seconds();
-        let minutes = seconds / 60;
-        (seconds - (60 * minutes), minutes)
+    pub fn checked_add(&self, rhs: Duration) -> Option<Self> {
+        let nanos = u64::try_from(rhs.as_nanos()).ok()?;
+        self.0.checked_add(nanos).map(Instant)
     }
 
-    pub fn hours(&self) -> (usize, usize, usize) {
-        let (seconds, minutes) = self.minutes();
-        let hours = minutes / 60;
-        (seconds, minutes - (60 * hours), hours)
+    pub fn checked_sub(&self, rhs: Duration) -> Option<Self> {
+        let nanos = u64::try_from(rhs.as_nanos()).ok()?;
+        self.0.checked_sub(nanos).map(Instant)
     }
 }
 
-impl Add<usize> for Instant {
+impl Add<Duration> for Instant {
     type Output = Instant;
 
-    fn add(self, rhs: usize) -> Self::Output {
-        Instant(self.0 + rhs)
+    fn add(self, rhs: Duration) -> Self::Output {
+        self.checked_add(rhs)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
     }
 }
 
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Self::Output {
+        self.checked_sub(rhs)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
+
+/// Seconds since the clock started, to the microsecond.
 impl Display for Instant {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        Display::fmt(&self.0, f)
+        let since = self.duration_since(Instant::zero());
+        write!(f, "{}.{:06}", since.as_secs(), since.subsec_micros())
     }
 }
 
+/// Counts timer interrupts, at the rate the PIT is programmed to. Reads take no lock, so they never
+/// contend with the timer interrupt.
 pub struct SystemClock {
-    tick: Instant,
-    loops: usize,
-    rate: f32,
+    ticks: AtomicU64,
+    /// PIT input clock cycles the ticks took, which stays right when the timer frequency changes.
+    cycles: AtomicU64,
 }
 
 impl SystemClock {
-    pub fn new(rate: f32) -> Self {
+    pub const fn new() -> Self {
         Self {
-            tick: Instant::zero(),
-            loops: 0,
-            rate,
+            ticks: AtomicU64::new(0),
+            cycles: AtomicU64::new(0),
         }
     }
 
-    pub fn tick(&mut self) {
-        use core::intrinsics::likely;
-        let now = self.tick.checked_add(1);
-
-        if likely(now.is_some()) {
-            let now = now.unwrap();
-            self.tick = now;
-        } else {
-            self.tick = Instant::zero();
-            self.loops += 1;
-        }
+    /// Ticks per second.
+    pub fn rate(&self) -> f32 {
+        pit::rate()
     }
 
-    pub fn now(&self) -> Instant {
-        self.tick
+    /// Counts a timer interrupt.
+    pub fn tick(&self) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+        self.cycles
+            .fetch_add(u64::from(pit::divisor()), Ordering::Relaxed);
     }
 
-    pub fn total(&self) -> f32 {
-        use core::intrinsics::likely;
-
-        if likely(self.loops < 1) {
-            self.tick.0 as f32
-        } else {
-            self.tick.0 as f32 * self.loops as f32
-        }
+    /// Ticks counted since boot.
+    pub fn ticks(&self) -> u64 {
+        self.ticks.load(Ordering::Relaxed)
     }
 
-    pub fn seconds(&self) -> usize {
-        let tick = self.total();
-        let seconds = tick / self.rate;
-        seconds as usize
+    /// PIT input clock cycles the ticks took.
+    pub fn cycles(&self) -> u64 {
+        self.cycles.load(Ordering::Relaxed)
     }
 
-    pub fn minutes(&self) -> (usize, usize) {
-        let seconds = self.seconds();
-        let minutes = seconds / 60;
-        (seconds - (60 * minutes), minutes)
+    /// Time since the first tick.
+    pub fn uptime(&self) -> Duration {
+        let cycles = u128::from(self.cycles.load(Ordering::Relaxed));
+        let nanos = cycles * 1_000_000_000 / u128::from(pit::BASE_FREQUENCY);
+        Duration::from_nanos(nanos as u64)
     }
+}
 
-    pub fn hours(&self) -> (usize, usize, usize) {
-        let (seconds, minutes) = self.minutes();
-        let hours = minutes / 60;
-        (seconds, minutes - (60 * hours), hours)
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -14,6 +14,26 @@ pub enum Status {
     AlreadyExists = 5,
     NotEmpty = 6,
     PermissionDenied,
+    /// A process was killed because it faulted.
+    Killed,
+}
+
+impl Status {
+    /// The status with the code `code`, as returned by `Termination::sys_report`.
+    pub fn from_code(code: u64) -> Option<Status> {
+        const ALL: [Status; 9] = [
+            Status::Success,
+            Status::NotFound,
+            Status::WrongType,
+            Status::FailedToWrite,
+            Status::FailedToRead,
+            Status::AlreadyExists,
+            Status::NotEmpty,
+            Status::PermissionDenied,
+            Status::Killed,
+        ];
+        ALL.iter().copied().find(|status| *status as u64 == code)
+    }
 }
 
 impl FromResidual for Status {
@@ -41,6 +61,7 @@ impl Try for Status {
             Status::AlreadyExists => ControlFlow::Break(self),
             Status::NotEmpty => ControlFlow::Break(self),
             Status::PermissionDenied => ControlFlow::Break(self),
+            Status::Killed => ControlFlow::Break(self),
         }
     }
 }
@@ -0,0 +1,59 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll};
+use futures_util::future::poll_fn;
+
+/*
+Cooperative poll budget. A task whose futures keep being ready never returns Pending, so nothing
+else would run until it is done. The executor gives each poll of a task a budget, and the futures
+of `task` spend a unit of it whenever they are ready. Once it is spent they return Pending and wake
+the task instead, which puts it back at the end of its run queue. Long loops that await nothing of
+`task` can spend it with `consume_budget`. Executors on other threads have budgets of their own,
+the scheduler swaps the budget along with the thread.
+ */
+
+/// Units a task may spend in one poll.
+pub const POLL_BUDGET: u32 = 128;
+
+/// Budget outside of an executor, never spent.
+pub(crate) const UNLIMITED: u32 = u32::MAX;
+
+/// Budget left to the task being polled on the running thread.
+static BUDGET: AtomicU32 = AtomicU32::new(UNLIMITED);
+
+/// Sets the budget of the running thread, returning the one it replaces.
+pub(crate) fn replace(budget: u32) -> u32 {
+    BUDGET.swap(budget, Ordering::Relaxed)
+}
+
+/// Budget left to the task being polled, `None` outside of an executor.
+pub fn remaining() -> Option<u32> {
+    match BUDGET.load(Ordering::Relaxed) {
+        UNLIMITED => None,
+        budget => Some(budget),
+    }
+}
+
+/// Spends a unit of the budget, or wakes the task to be polled again later once it is spent.
+pub fn poll_proceed(cx: &mut Context<'_>) -> Poll<()> {
+    let spent = BUDGET.fetch_update(
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+        |budget| match budget {
+            0 => None,
+            UNLIMITED => Some(UNLIMITED),
+            budget => Some(budget - 1),
+        },
+    );
+    match spent {
+        Ok(_) => Poll::Ready(()),
+        Err(_) => {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// Spends a unit of the budget, yielding to the other tasks once it is spent.
+pub async fn consume_budget() {
+    poll_fn(poll_proceed).await
+}
@@ -0,0 +1,106 @@
+use super::budget;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+
+/*
+Cooperative cancellation. Unlike aborting, which drops a task wherever it is waiting, a cancelled
+token only tells the tasks holding it to wind down, which they notice by checking `is_cancelled`
+or by awaiting `cancelled`, e.g. raced against their work.
+ */
+
+/// A cancellation flag shared by its clones. Cancelling a token also cancels its children.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    /// Wakers of the pending `Cancelled` futures, by their ID.
+    wakers: Mutex<BTreeMap<u64, Waker>>,
+    children: Mutex<Vec<CancellationToken>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token that is cancelled along with this one, but can also be cancelled on its own.
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        let mut children = self.inner.children.lock();
+        // checked under the lock, so `cancel` either sees the child or the child sees the flag.
+        if self.is_cancelled() {
+            child.cancel();
+        } else {
+            children.push(child.clone());
+        }
+        child
+    }
+
+    /// Cancels the token, its clones and its children, waking every task awaiting `cancelled`.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let wakers = core::mem::take(&mut *self.inner.wakers.lock());
+        for (_, waker) in wakers {
+            waker.wake();
+        }
+        let children = core::mem::take(&mut *self.inner.children.lock());
+        for child in children {
+            child.cancel();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Completes once the token is cancelled.
+    pub fn cancelled(&self) -> Cancelled {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Cancelled {
+            token: self.clone(),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Future returned by `CancellationToken::cancelled`.
+#[must_use = "futures do nothing unless awaited"]
+pub struct Cancelled {
+    token: CancellationToken,
+    id: u64,
+}
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.token.is_cancelled() {
+            return budget::poll_proceed(cx);
+        }
+        let mut wakers = self.token.inner.wakers.lock();
+        // `cancel` sets the flag before it takes the wakers.
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        wakers.insert(self.id, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Cancelled {
+    fn drop(&mut self) {
+        self.token.inner.wakers.lock().remove(&self.id);
+    }
+}
@@ -1,19 +1,164 @@
-use super::{Task, TaskId};
+use super::budget::{self, POLL_BUDGET};
+use super::isolate;
+use super::join::{JoinError, JoinHandle, JoinState};
+use super::timer;
+use super::{Priority, Task, TaskId, TaskInfo};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
 use alloc::task::Wake;
-use alloc::{collections::BTreeMap, sync::Arc};
+use alloc::vec::Vec;
+use core::future::Future;
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::task::Waker;
 use core::task::{Context, Poll};
 use crossbeam_queue::ArrayQueue;
 use futures_util::FutureExt;
+use spin::Mutex;
 /*
-Asynchronous execute for kernel tasks. Currently used for welcome message and shell.
+Asynchronous execute for kernel tasks. Currently used for welcome message and shell. While an
+executor runs, `task::spawn` hands new tasks to it through its `Spawner`, so tasks can start other
+tasks. Spawned tasks wait in a list until the executor takes them in. Aborted tasks are dropped
+when they next come up, and with `isolate_panics` a panicking task is failed instead of taking the
+kernel down, see `isolate`.
+
+Woken tasks wait in a run queue. A task is in it at most once, however often it is woken, as its
+waker marks it queued. The queue is fixed size, so wakes never allocate and can come from IRQ
+handlers. A wake that finds it full leaves the task marked only, and the executor looks for the
+marked tasks once the queue is drained.
+
+Each `Priority` has its own run queue, and the executor takes the next task from the highest class
+with ready tasks, except every `FAIRNESS_INTERVAL` polls, when it starts at one of the lower
+classes, each of them in turn, so a busy class can not starve the ones below it for good. A task
+that is ready over and over only keeps its poll short, see `budget`.
  */
 
+/// Size of a run queue, more woken tasks than this are found by looking through all tasks.
+const RUN_QUEUE_CAPACITY: usize = 1024;
+
+/// Polls between turns of the lower classes.
+const FAIRNESS_INTERVAL: u64 = 32;
+
+/// A run queue for each `Priority`, highest first.
+type RunQueues = [RunQueue; Priority::ALL.len()];
+
+/// Spawner of the running executor.
+pub(super) static SPAWNER: Mutex<Option<Spawner>> = Mutex::new(None);
+
 /// Executor itself. Contains a map of tasks, queue and Wakers/
 pub struct Executor {
     tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    waker_cache: BTreeMap<TaskId, Arc<TaskWaker>>,
+    /// Tasks found marked queued after a run queue was full, by priority.
+    spilled: [VecDeque<TaskId>; Priority::ALL.len()],
+    /// Polls so far, for taking turns between the classes.
+    polls: u64,
+    shared: Arc<Shared>,
+    /// Poll tasks below a recovery point for panics.
+    isolate_panics: bool,
+}
+
+/// State an executor shares with its spawners.
+struct Shared {
+    run_queues: Arc<RunQueues>,
+    /// Tasks spawned through a `Spawner`, not yet taken in by the executor.
+    incoming: Mutex<Vec<(Task, Arc<TaskWaker>)>>,
+    /// The tasks that have not completed.
+    listed: Mutex<BTreeMap<TaskId, TaskInfo>>,
+}
+
+impl Shared {
+    /// Lists `task` and makes its waker.
+    fn register(&self, task: &Task) -> Arc<TaskWaker> {
+        let info = TaskInfo {
+            id: task.id,
+            name: task.name,
+            priority: task.priority,
+        };
+        self.listed.lock().insert(task.id, info);
+        TaskWaker::new(task.id, task.priority, self.run_queues.clone())
+    }
+}
+
+/// Tasks ready to be polled.
+struct RunQueue {
+    queue: ArrayQueue<TaskId>,
+    /// Set when a task was woken while `queue` was full.
+    overflowed: AtomicBool,
+}
+
+impl RunQueue {
+    fn new() -> Self {
+        Self {
+            queue: ArrayQueue::new(RUN_QUEUE_CAPACITY),
+            overflowed: AtomicBool::new(false),
+        }
+    }
+
+    fn push(&self, task_id: TaskId) {
+        if self.queue.push(task_id).is_err() {
+            self.overflowed.store(true, Ordering::Release);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty() && !self.overflowed.load(Ordering::Acquire)
+    }
+}
+
+/// Spawns tasks on an executor from anywhere, also from its own tasks.
+#[derive(Clone)]
+pub struct Spawner {
+    shared: Arc<Shared>,
+}
+
+impl Spawner {
+    /// Runs `future` as a task and returns a handle to its output.
+    pub fn spawn<F>(&self, name: &'static str, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawn_with_priority(name, Priority::default(), future)
+    }
+
+    /// Runs `future` as a task of the class `priority` and returns a handle to its output.
+    pub fn spawn_with_priority<F>(
+        &self,
+        name: &'static str,
+        priority: Priority,
+        future: F,
+    ) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let state = Arc::new(JoinState::new());
+        let mut task = {
+            let state = state.clone();
+            Task::new(name, async move { state.complete(future.await) }).with_priority(priority)
+        };
+        task.join = Some(state.clone());
+        let waker = self.shared.register(&task);
+        let handle = JoinHandle::new(task.id, name, state, Waker::from(waker.clone()));
+        self.add(task, waker);
+        handle
+    }
+
+    /// Adds `task` to the executor.
+    pub fn spawn_task(&self, task: Task) {
+        let waker = self.shared.register(&task);
+        self.add(task, waker);
+    }
+
+    fn add(&self, task: Task, waker: Arc<TaskWaker>) {
+        self.shared.incoming.lock().push((task, waker.clone()));
+        waker.wake_task();
+    }
+
+    /// The tasks that have not completed.
+    pub fn list(&self) -> Vec<TaskInfo> {
+        self.shared.listed.lock().values().cloned().collect()
+    }
 }
 
 impl Default for Executor {
@@ -27,15 +172,51 @@ impl Executor {
     pub fn new() -> Self {
         Executor {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
             waker_cache: BTreeMap::new(),
+            spilled: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            polls: 0,
+            shared: Arc::new(Shared {
+                run_queues: Arc::new([RunQueue::new(), RunQueue::new(), RunQueue::new()]),
+                incoming: Mutex::new(Vec::new()),
+                listed: Mutex::new(BTreeMap::new()),
+            }),
+            isolate_panics: false,
         }
     }
 
+    /// Sets whether a panicking task is failed, with its `JoinHandle` resolving to
+    /// `JoinError::Panicked`, while the other tasks keep running. Needs the panic handler to call
+    /// `isolate::recover`. Off by default, as the spin locks a panicking poll held stay locked.
+    pub fn isolate_panics(&mut self, isolate: bool) {
+        self.isolate_panics = isolate;
+    }
+
     /// Runs the executor's tasks. Never returns.
     pub fn run(&mut self) -> ! {
+        *SPAWNER.lock() = Some(self.spawner());
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Runs the executor's tasks until `future` completes, and returns its output, or why it did
+    /// not complete.
+    pub fn block_on<F>(&mut self, future: F) -> Result<F::Output, JoinError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let spawner = self.spawner();
+        // an executor blocked on from a task hands spawning back when done.
+        let previous = SPAWNER.lock().replace(spawner.clone());
+        let mut handle = spawner.spawn("block_on", future);
         loop {
             self.run_ready_tasks();
+            if let Some(output) = handle.try_take() {
+                *SPAWNER.lock() = previous;
+                return output;
+            }
             self.sleep_if_idle();
         }
     }
@@ -43,57 +224,155 @@ impl Executor {
     /// Spawn a task, adds a task to the queue.
     pub fn spawn(&mut self, task: Task) {
         let task_id = task.id;
+        let waker = self.shared.register(&task);
         if self.tasks.insert(task.id, task).is_some() {
             panic!("task with same ID already in tasks");
         }
-        self.task_queue.push(task_id).expect("queue full");
+        self.waker_cache.insert(task_id, waker.clone());
+        waker.wake_task();
     }
 
-    /// Looks for Ready tasks, removes them from queue when complete, pushes them back when executing.
+    /// A handle to spawn tasks on this executor with.
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Polls ready tasks until none are left.
     fn run_ready_tasks(&mut self) {
-        // destructure `self` to avoid borrow checker errors
-        let Self {
-            tasks,
-            task_queue,
-            waker_cache,
-        } = self;
-
-        // Loop for every TaskId in queue.
-        while let Some(task_id) = task_queue.pop() {
-            // Get a reference to a task from its ID.
-            let task = match tasks.get_mut(&task_id) {
-                Some(t) => t,
-                None => continue, // go to next ID in queue.
-            };
-
-            // get waker reference based on the task's ID
-            let waker = waker_cache.entry(task_id).or_insert_with(|| {
-                Waker::from(Arc::new(TaskWaker::new(task_id, task_queue.clone())))
-            });
-
-            // Get the Task's context.
-            let mut context = Context::from_waker(waker);
-
-            // Poll the task's state with context.
-            match task.future.poll_unpin(&mut context) {
-                // Task is complete, remove from queue and its waker from cache
-                Poll::Ready(()) => {
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
+        while let Some(task_id) = self.next_task() {
+            self.run_task(task_id);
+        }
+    }
+
+    /// The next ready task, from the highest class with ready tasks, or from a lower class on its
+    /// turn.
+    fn next_task(&mut self) -> Option<TaskId> {
+        let classes = Priority::ALL.len();
+        self.polls = self.polls.wrapping_add(1);
+        let first = match self.polls % FAIRNESS_INTERVAL {
+            // the classes below the highest take turns.
+            0 => 1 + (self.polls / FAIRNESS_INTERVAL) as usize % (classes - 1),
+            _ => 0,
+        };
+        for i in 0..classes {
+            let priority = Priority::ALL[(first + i) % classes];
+            if let Some(task_id) = self.pop(priority) {
+                return Some(task_id);
+            }
+        }
+        None
+    }
+
+    /// The next ready task of the class `priority`.
+    fn pop(&mut self, priority: Priority) -> Option<TaskId> {
+        let class = priority as usize;
+        let run_queue = &self.shared.run_queues[class];
+        if let Some(task_id) = self.spilled[class].pop_front() {
+            return Some(task_id);
+        }
+        if let Some(task_id) = run_queue.queue.pop() {
+            return Some(task_id);
+        }
+
+        // tasks woken while the queue was full are only marked queued.
+        if !run_queue.overflowed.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        self.take_incoming();
+        let spilled = &mut self.spilled[class];
+        spilled.extend(
+            self.waker_cache
+                .values()
+                .filter(|waker| waker.priority == priority && waker.queued.load(Ordering::Acquire))
+                .map(|waker| waker.task_id),
+        );
+        spilled.pop_front()
+    }
+
+    /// Polls a woken task.
+    fn run_task(&mut self, task_id: TaskId) {
+        // take in spawned tasks, one of them may be up.
+        if !self.tasks.contains_key(&task_id) {
+            self.take_incoming();
+        }
+
+        // Get a reference to a task from its ID.
+        let (task, waker) = match (self.tasks.get_mut(&task_id), self.waker_cache.get(&task_id)) {
+            (Some(task), Some(waker)) => (task, waker),
+            _ => return, // go to next ID in queue.
+        };
+        // polled since it was queued, a wake from now on queues it again.
+        if !waker.queued.swap(false, Ordering::AcqRel) {
+            return;
+        }
+
+        if matches!(&task.join, Some(join) if join.is_aborted()) {
+            let task = self.remove(task_id).unwrap();
+            let join = task.join.clone();
+            drop(task);
+            if let Some(join) = join {
+                join.fail(JoinError::Aborted);
+            }
+            return;
+        }
+
+        // Get the Task's context.
+        let waker = Waker::from(waker.clone());
+        let mut context = Context::from_waker(&waker);
+
+        // Poll the task's state with context.
+        let previous = budget::replace(POLL_BUDGET);
+        let poll = match self.isolate_panics {
+            true => isolate::poll(task, &mut context),
+            false => Some(task.future.poll_unpin(&mut context)),
+        };
+        budget::replace(previous);
+        match poll {
+            // Task is complete, remove from queue and its waker from cache
+            Some(Poll::Ready(())) => {
+                self.remove(task_id);
+            }
+            // Task is not complete, do nothing
+            Some(Poll::Pending) => {}
+            // Task panicked, leak it as it may be broken.
+            None => {
+                let task = self.remove(task_id).unwrap();
+                if let Some(join) = &task.join {
+                    join.fail(JoinError::Panicked);
                 }
-                // Task is not complete, do nothing
-                Poll::Pending => {}
+                core::mem::forget(task.future);
+                // the panic may have come with interrupts disabled.
+                x86_64::instructions::interrupts::enable();
+                // its timers are never dropped.
+                let _ = timer::purge(&waker);
             }
         }
     }
 
+    /// Takes in the tasks spawned through a `Spawner`.
+    fn take_incoming(&mut self) {
+        for (task, waker) in self.shared.incoming.lock().drain(..) {
+            self.waker_cache.insert(task.id, waker);
+            self.tasks.insert(task.id, task);
+        }
+    }
+
+    /// Removes a task that is done or failed.
+    fn remove(&mut self, task_id: TaskId) -> Option<Task> {
+        self.waker_cache.remove(&task_id);
+        self.shared.listed.lock().remove(&task_id);
+        self.tasks.remove(&task_id)
+    }
+
     /// halt the thread when all tasks complete.
     fn sleep_if_idle(&self) {
         use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
         // disable CPU interrupts
         interrupts::disable();
-        if self.task_queue.is_empty() {
+        if self.shared.run_queues.iter().all(RunQueue::is_empty) {
             // queue is empty, enable interrupts and halt the thread.
             enable_and_hlt();
         } else {
@@ -105,21 +384,28 @@ impl Executor {
 /// Task waker wakes a task.
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    priority: Priority,
+    /// Set while the task is in the run queue, or marked for the executor when it was full.
+    queued: AtomicBool,
+    run_queues: Arc<RunQueues>,
 }
 
 impl TaskWaker {
-    /// Create a new TaskWaker with a task's ID and queue.
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> TaskWaker {
-        TaskWaker {
+    /// Create a new TaskWaker with a task's ID, priority and queues.
+    fn new(task_id: TaskId, priority: Priority, run_queues: Arc<RunQueues>) -> Arc<TaskWaker> {
+        Arc::new(TaskWaker {
             task_id,
-            task_queue,
-        }
+            priority,
+            queued: AtomicBool::new(false),
+            run_queues,
+        })
     }
 
-    // wake the task
+    // wake the task, unless it is queued already.
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.run_queues[self.priority as usize].push(self.task_id);
+        }
     }
 }
 
@@ -0,0 +1,70 @@
+use core::fmt;
+use core::task::Waker;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/*
+Waker slot for futures woken by IRQ handlers or the scheduler. `AtomicWaker::wake` takes the waker
+out and drops it, which frees the task's waker if that was the last reference, and IRQ handlers must
+not free. This slot keeps its waker and wakes it by reference, so wakers are only dropped by tasks.
+Tasks hold its lock with interrupts disabled, so an IRQ handler never finds it locked.
+ */
+
+/// A waker that IRQ handlers can wake without dropping it.
+pub struct IrqWaker {
+    waker: Mutex<Option<Waker>>,
+}
+
+impl IrqWaker {
+    pub const fn new() -> Self {
+        Self {
+            waker: Mutex::new(None),
+        }
+    }
+
+    /// Stores `waker`, unless the stored one wakes the same task.
+    pub fn register(&self, waker: &Waker) {
+        // cloned and dropped with interrupts enabled.
+        let mut new = Some(waker.clone());
+        without_interrupts(|| {
+            let mut slot = self.waker.lock();
+            if !matches!(&*slot, Some(stored) if stored.will_wake(waker)) {
+                core::mem::swap(&mut *slot, &mut new);
+            }
+        });
+        drop(new);
+    }
+
+    /// Wakes the stored waker and keeps it. Used from IRQ handlers.
+    pub fn wake(&self) {
+        if let Some(slot) = self.waker.try_lock() {
+            if let Some(waker) = &*slot {
+                waker.wake_by_ref();
+            }
+        }
+    }
+
+    /// Removes the stored waker.
+    pub fn take(&self) -> Option<Waker> {
+        without_interrupts(|| self.waker.lock().take())
+    }
+
+    /// Returns true if the stored waker wakes the same task as `waker`.
+    pub fn will_wake(&self, waker: &Waker) -> bool {
+        without_interrupts(
+            || matches!(&*self.waker.lock(), Some(stored) if stored.will_wake(waker)),
+        )
+    }
+}
+
+impl Default for IrqWaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for IrqWaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IrqWaker").finish_non_exhaustive()
+    }
+}
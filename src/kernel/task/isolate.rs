@@ -0,0 +1,147 @@
+use super::{Task, TaskId};
+use crate::drivers::io;
+use crate::kernel::interrupts::irq;
+use crate::kernel::mem::{globalloc, vmm};
+use crate::kernel::thread::{self, ThreadId};
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use core::ptr::{self, addr_of_mut};
+use core::sync::atomic::{AtomicPtr, Ordering};
+use core::task::{Context, Poll};
+use futures_util::FutureExt;
+
+/*
+Panic isolation. The kernel aborts on panic, there is no unwinding, so an executor isolating panics
+polls each task below a recovery point: `task_try` saves the callee saved registers and the stack
+pointer, and a panic handler that calls `recover` jumps back there with `task_jump`, dropping the
+frames of the panicking poll. The task is leaked rather than dropped, as its state may be half
+updated, and so are any locks its poll held. Panics in IRQ handlers or on other threads are not
+caught, and neither are panics with the heap or the page table locked, as the kernel can not go on
+without them. The report does not wait for the screen or serial port either, with one of them
+locked the panic goes to the panic handler.
+ */
+
+global_asm!(
+    // fn task_try(f: extern "C" fn(*mut u8), data: *mut u8, buffer: *mut JumpBuffer) -> u64
+    ".global task_try",
+    "task_try:",
+    "mov [rdx + 0x00], rbx",
+    "mov [rdx + 0x08], rbp",
+    "mov [rdx + 0x10], r12",
+    "mov [rdx + 0x18], r13",
+    "mov [rdx + 0x20], r14",
+    "mov [rdx + 0x28], r15",
+    // the stack pointer and return address as they are once task_try returns.
+    "lea rax, [rsp + 8]",
+    "mov [rdx + 0x30], rax",
+    "mov rax, [rsp]",
+    "mov [rdx + 0x38], rax",
+    // the call pushed the return address, realign the stack to 16 bytes.
+    "sub rsp, 8",
+    "mov rax, rdi",
+    "mov rdi, rsi",
+    "call rax",
+    "add rsp, 8",
+    "xor eax, eax",
+    "ret",
+    // fn task_jump(buffer: *const JumpBuffer) -> !, returns 1 from the task_try that filled it.
+    ".global task_jump",
+    "task_jump:",
+    "mov rbx, [rdi + 0x00]",
+    "mov rbp, [rdi + 0x08]",
+    "mov r12, [rdi + 0x10]",
+    "mov r13, [rdi + 0x18]",
+    "mov r14, [rdi + 0x20]",
+    "mov r15, [rdi + 0x28]",
+    "mov rsp, [rdi + 0x30]",
+    "mov eax, 1",
+    "jmp [rdi + 0x38]",
+);
+
+extern "C" {
+    fn task_try(f: extern "C" fn(*mut u8), data: *mut u8, buffer: *mut JumpBuffer) -> u64;
+    fn task_jump(buffer: *const JumpBuffer) -> !;
+}
+
+/// Callee saved registers, stack pointer and return address saved by `task_try`.
+#[repr(C)]
+struct JumpBuffer([u64; 8]);
+
+/// Where a panic in the task being polled returns to.
+struct Recovery {
+    buffer: JumpBuffer,
+    thread: ThreadId,
+    task: TaskId,
+    name: &'static str,
+}
+
+/// The innermost recovery point, null while no task is polled in isolation.
+static RECOVERY: AtomicPtr<Recovery> = AtomicPtr::new(ptr::null_mut());
+
+/// Polls `task`, returning `None` if it panicked.
+pub(super) fn poll(task: &mut Task, context: &mut Context<'_>) -> Option<Poll<()>> {
+    struct Poller<'a, 'b> {
+        task: &'a mut Task,
+        context: &'a mut Context<'b>,
+        result: Poll<()>,
+    }
+
+    extern "C" fn poll_task(data: *mut u8) {
+        let poller = unsafe { &mut *(data as *mut Poller) };
+        poller.result = poller.task.future.poll_unpin(poller.context);
+    }
+
+    let mut recovery = Recovery {
+        buffer: JumpBuffer([0; 8]),
+        thread: thread::current(),
+        task: task.id,
+        name: task.name,
+    };
+    let mut poller = Poller {
+        task,
+        context,
+        result: Poll::Pending,
+    };
+
+    let recovery: *mut Recovery = &mut recovery;
+    let previous = RECOVERY.swap(recovery, Ordering::SeqCst);
+    let panicked = unsafe {
+        task_try(
+            poll_task,
+            &mut poller as *mut Poller as *mut u8,
+            addr_of_mut!((*recovery).buffer),
+        )
+    };
+    RECOVERY.store(previous, Ordering::SeqCst);
+
+    match panicked {
+        0 => Some(poller.result),
+        _ => None,
+    }
+}
+
+/// Reports a panic of a task polled in isolation and resumes its executor, which fails the task.
+/// Returns if the panic did not come from such a task, or leaves the kernel unable to go on or to
+/// report it. Panic handlers call it first.
+pub fn recover(info: &PanicInfo) {
+    let recovery = RECOVERY.load(Ordering::SeqCst);
+    if recovery.is_null() || irq::in_irq() {
+        return;
+    }
+    let recovery = unsafe { &*recovery };
+    if thread::try_current() != Some(recovery.thread) {
+        return;
+    }
+    if globalloc::heap_locked() || vmm::is_locked() {
+        return;
+    }
+    // a panic while reporting goes to the panic handler.
+    RECOVERY.store(ptr::null_mut(), Ordering::SeqCst);
+    let reported = io::try_print(format_args!(
+        "task {} ({}) panicked: {}\n",
+        recovery.task, recovery.name, info
+    ));
+    if reported {
+        unsafe { task_jump(&recovery.buffer) }
+    }
+}
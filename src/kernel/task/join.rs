@@ -0,0 +1,161 @@
+use super::{budget, TaskId};
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+/// Why a task has no output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted.
+    Aborted,
+    /// The task panicked while its executor isolated panics.
+    Panicked,
+}
+
+/// What the executor reports to the handle of a task that did not complete.
+pub(super) trait Completion: Send + Sync {
+    /// Returns true once the task was aborted.
+    fn is_aborted(&self) -> bool;
+    fn abort(&self);
+    fn fail(&self, error: JoinError);
+}
+
+/// Output of a task, filled in when it completes.
+pub(super) struct JoinState<T> {
+    output: Mutex<Option<Result<T, JoinError>>>,
+    waker: AtomicWaker,
+    aborted: AtomicBool,
+}
+
+impl<T> JoinState<T> {
+    pub(super) fn new() -> Self {
+        Self {
+            output: Mutex::new(None),
+            waker: AtomicWaker::new(),
+            aborted: AtomicBool::new(false),
+        }
+    }
+
+    pub(super) fn complete(&self, output: T) {
+        *self.output.lock() = Some(Ok(output));
+        self.waker.wake();
+    }
+}
+
+impl<T: Send> Completion for JoinState<T> {
+    fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Acquire)
+    }
+
+    fn abort(&self) {
+        self.aborted.store(true, Ordering::Release);
+    }
+
+    fn fail(&self, error: JoinError) {
+        self.output.lock().get_or_insert(Err(error));
+        self.waker.wake();
+    }
+}
+
+/// Future resolving to the output of a spawned task. Dropping it leaves the task running.
+pub struct JoinHandle<T> {
+    id: TaskId,
+    name: &'static str,
+    state: Arc<JoinState<T>>,
+    /// Wakes the task, for aborting it.
+    task_waker: Waker,
+}
+
+impl<T: Send + 'static> JoinHandle<T> {
+    pub(super) fn new(
+        id: TaskId,
+        name: &'static str,
+        state: Arc<JoinState<T>>,
+        task_waker: Waker,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            state,
+            task_waker,
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns true if the task has completed, failed or been aborted, and its output has not
+    /// been taken yet.
+    pub fn is_finished(&self) -> bool {
+        self.state.output.lock().is_some()
+    }
+
+    /// Stops the task the next time its executor gets to it, see `AbortHandle::abort`.
+    pub fn abort(&self) {
+        self.abort_handle().abort()
+    }
+
+    /// A handle to abort the task with, which does not own its output.
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle {
+            id: self.id,
+            state: self.state.clone(),
+            task_waker: self.task_waker.clone(),
+        }
+    }
+
+    /// The output of the task, if it has completed.
+    pub(super) fn try_take(&mut self) -> Option<Result<T, JoinError>> {
+        self.state.output.lock().take()
+    }
+}
+
+impl<T: Send + 'static> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.is_finished() && budget::poll_proceed(cx).is_pending() {
+            return Poll::Pending;
+        }
+        if let Some(output) = self.try_take() {
+            return Poll::Ready(output);
+        }
+        self.state.waker.register(cx.waker());
+        // the task may have completed before the waker was registered.
+        match self.try_take() {
+            Some(output) => Poll::Ready(output),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Aborts a task from anywhere.
+#[derive(Clone)]
+pub struct AbortHandle {
+    id: TaskId,
+    state: Arc<dyn Completion>,
+    task_waker: Waker,
+}
+
+impl AbortHandle {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Stops the task the next time its executor gets to it: it is dropped without being polled
+    /// again and its handle resolves to `JoinError::Aborted`. Does nothing once it completed.
+    pub fn abort(&self) {
+        self.state.abort();
+        // wake the task so the executor notices.
+        self.task_waker.wake_by_ref();
+    }
+}
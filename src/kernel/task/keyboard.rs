@@ -1,3 +1,6 @@
+use crate::kernel::interrupts::irq::{self, IrqError, IrqHandle};
+use crate::kernel::task::budget;
+use crate::kernel::task::irq_waker::IrqWaker;
 use crate::vga_println;
 use conquer_once::spin::OnceCell;
 use core::{
@@ -6,10 +9,10 @@ use core::{
 };
 use crossbeam_queue::ArrayQueue;
 use futures_util::stream::Stream;
-use futures_util::task::AtomicWaker;
+use x86_64::instructions::port::Port;
 
-/// Constant AtomicWaker used for keyboard events.
-static WAKER: AtomicWaker = AtomicWaker::new();
+/// Waker used for keyboard events.
+static WAKER: IrqWaker = IrqWaker::new();
 /// Queue of scancodes
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 
@@ -26,6 +29,18 @@ pub(crate) fn add_scancode(scancode: u8) {
     }
 }
 
+/// Registers the keyboard interrupt handler.
+pub fn init() -> Result<IrqHandle, IrqError> {
+    irq::register_fn(irq::KEYBOARD, interrupt)
+}
+
+/// Reads the scancode of a key press or release from the PS/2 controller.
+fn interrupt() {
+    let mut port = Port::new(0x60);
+    let scancode: u8 = unsafe { port.read() };
+    add_scancode(scancode);
+}
+
 /// Structure for Scancode stream.
 pub struct ScancodeStream {
     _private: (),
@@ -55,6 +70,11 @@ impl Stream for ScancodeStream {
             .try_get()
             .expect("scancode queue not initialized");
 
+        // a burst of input yields to the other tasks now and then.
+        if !queue.is_empty() && budget::poll_proceed(cx).is_pending() {
+            return Poll::Pending;
+        }
+
         // fast path
         if let Some(scancode) = queue.pop() {
             return Poll::Ready(Some(scancode));
@@ -1,14 +1,31 @@
 use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Display;
 use core::future::Future;
 use core::pin::Pin;
 use core::sync::atomic::{AtomicU64, Ordering};
+use executor::{Spawner, SPAWNER};
+use join::Completion;
 
+pub mod budget;
+pub mod cancel;
 pub mod executor;
+pub mod irq_waker;
+pub mod isolate;
+pub mod join;
 pub mod keyboard;
+pub mod sync;
+pub mod timer;
+
+pub use budget::consume_budget;
+pub use cancel::CancellationToken;
+pub use join::{AbortHandle, JoinError, JoinHandle};
+pub use sync::{Mutex, Notify, RwLock, Semaphore};
 
 /// Structure for Task IDs
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
+pub struct TaskId(u64);
 
 impl TaskId {
     /// Create an ID, on higher than the last still in existence.
@@ -18,18 +35,121 @@ impl TaskId {
     }
 }
 
-/// Structure for a task. Contains an ID and a task's Future
+impl Display for TaskId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+/// Scheduling class of a task. The executor polls the ready tasks of a higher class first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Tasks driven by interrupts, such as keyboard input.
+    Io,
+    /// Tasks a user waits on, the default.
+    #[default]
+    Interactive,
+    /// Work nobody waits on.
+    Background,
+}
+
+impl Priority {
+    /// All classes, highest first.
+    pub const ALL: [Priority; 3] = [Priority::Io, Priority::Interactive, Priority::Background];
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Priority::Io => "io",
+            Priority::Interactive => "interactive",
+            Priority::Background => "background",
+        })
+    }
+}
+
+/// Structure for a task. Contains an ID, a name for diagnostics, a priority and a task's Future
 pub struct Task {
     id: TaskId, // new
-    future: Pin<Box<dyn Future<Output = ()>>>,
+    name: &'static str,
+    priority: Priority,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    /// Told when the task is aborted or panics, for tasks with a `JoinHandle`.
+    join: Option<Arc<dyn Completion>>,
 }
 
 impl Task {
     /// Create a Task with a new TaskId.
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+    pub fn new(name: &'static str, future: impl Future<Output = ()> + Send + 'static) -> Task {
         Task {
             id: TaskId::new(), // new
+            name,
+            priority: Priority::default(),
             future: Box::pin(future),
+            join: None,
         }
     }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Sets the scheduling class of the task.
+    pub fn with_priority(mut self, priority: Priority) -> Task {
+        self.priority = priority;
+        self
+    }
+}
+
+/// Snapshot of a task for listings.
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: &'static str,
+    pub priority: Priority,
+}
+
+/// Runs `future` as a task on the running executor. Panics if no executor is running.
+pub fn spawn<F>(name: &'static str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawner()
+        .expect("spawned a task with no executor running")
+        .spawn(name, future)
+}
+
+/// Runs `future` as a task of the class `priority` on the running executor. Panics if no executor
+/// is running.
+pub fn spawn_with_priority<F>(
+    name: &'static str,
+    priority: Priority,
+    future: F,
+) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawner()
+        .expect("spawned a task with no executor running")
+        .spawn_with_priority(name, priority, future)
+}
+
+/// Handle to the running executor, `None` if there is none.
+pub fn spawner() -> Option<Spawner> {
+    SPAWNER.lock().clone()
+}
+
+/// The tasks of the running executor that have not completed.
+pub fn list() -> Vec<TaskInfo> {
+    spawner().map(|spawner| spawner.list()).unwrap_or_default()
 }
@@ -0,0 +1,17 @@
+/*
+Synchronization for tasks. A `spin::Mutex` held across an await point has every other task that
+wants it spin the CPU, and with the holder waiting to be polled by the same executor it never gets
+free. These types have a task wait for its turn instead, and wake it once it comes, in the order the
+tasks came. They only hold a spin lock briefly and wake tasks, so they are used from tasks and
+threads, not from IRQ handlers.
+ */
+
+mod mutex;
+mod notify;
+mod rwlock;
+mod semaphore;
+
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::{Notified, Notify};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Acquire, Semaphore, SemaphorePermit};
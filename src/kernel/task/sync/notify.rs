@@ -0,0 +1,151 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+/// Not notified yet.
+const WAITING: u8 = 0;
+/// Notified by `notify_one`, which passes on to the next task if it gives up waiting.
+const NOTIFIED_ONE: u8 = 1;
+/// Notified by `notify_waiters`.
+const NOTIFIED_ALL: u8 = 2;
+
+/// Wakes waiting tasks on request, without a value.
+pub struct Notify {
+    state: Mutex<State>,
+}
+
+struct State {
+    /// A `notify_one` no task was waiting for, taken by the next `notified`.
+    permit: bool,
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+/// A task waiting to be notified.
+struct Waiter {
+    notified: AtomicU8,
+    waker: AtomicWaker,
+}
+
+impl State {
+    fn notify_one(&mut self) {
+        match self.waiters.pop_front() {
+            Some(waiter) => waiter.notify(NOTIFIED_ONE),
+            None => self.permit = true,
+        }
+    }
+}
+
+impl Waiter {
+    fn notify(&self, how: u8) {
+        self.notified.store(how, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                permit: false,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Wakes the task that waited longest, or, if none waits, lets the next one through at once.
+    pub fn notify_one(&self) {
+        self.state.lock().notify_one();
+    }
+
+    /// Wakes every waiting task. Tasks that come to wait after are not let through.
+    pub fn notify_waiters(&self) {
+        let waiters = core::mem::take(&mut self.state.lock().waiters);
+        for waiter in waiters {
+            waiter.notify(NOTIFIED_ALL);
+        }
+    }
+
+    /// Completes once the task is notified. It waits from its first poll on.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            waiter: None,
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Notify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notify").finish_non_exhaustive()
+    }
+}
+
+/// Future returned by `Notify::notified`.
+#[must_use = "futures do nothing unless awaited"]
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    /// Its place among the waiters, once polled.
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        if let Some(waiter) = &this.waiter {
+            waiter.waker.register(cx.waker());
+            if waiter.notified.load(Ordering::Acquire) == WAITING {
+                return Poll::Pending;
+            }
+            this.waiter = None;
+            return Poll::Ready(());
+        }
+
+        let mut state = this.notify.state.lock();
+        if state.permit {
+            state.permit = false;
+            return Poll::Ready(());
+        }
+        let waiter = Arc::new(Waiter {
+            notified: AtomicU8::new(WAITING),
+            waker: AtomicWaker::new(),
+        });
+        waiter.waker.register(cx.waker());
+        state.waiters.push_back(waiter.clone());
+        this.waiter = Some(waiter);
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None => return,
+        };
+        let mut state = self.notify.state.lock();
+        match waiter.notified.load(Ordering::Acquire) {
+            // a `notify_one` is not lost on a task that gave up.
+            NOTIFIED_ONE => state.notify_one(),
+            NOTIFIED_ALL => {}
+            _ => {
+                if let Some(i) = state.waiters.iter().position(|w| Arc::ptr_eq(w, &waiter)) {
+                    state.waiters.remove(i);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::kernel::task::budget;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+/// Hands out permits to tasks, which wait for them in the order they asked.
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+/// A task waiting for permits.
+struct Waiter {
+    permits: usize,
+    /// Set once the permits are handed to it.
+    granted: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl State {
+    /// Hands out permits to the waiters at the front of the queue, as far as they go.
+    fn grant(&mut self) {
+        while let Some(waiter) = self.waiters.front() {
+            if waiter.permits > self.permits {
+                break;
+            }
+            self.permits -= waiter.permits;
+            waiter.granted.store(true, Ordering::Release);
+            waiter.waker.wake();
+            self.waiters.pop_front();
+        }
+    }
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                permits,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Permits not handed out.
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+
+    /// Adds permits, waking the tasks they go to.
+    pub fn add_permits(&self, permits: usize) {
+        let mut state = self.state.lock();
+        state.permits += permits;
+        state.grant();
+    }
+
+    /// Completes with a permit once one is free and the tasks that asked before have theirs.
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Completes with `permits` permits at once, see `acquire`.
+    pub fn acquire_many(&self, permits: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            permits,
+            waiter: None,
+        }
+    }
+
+    /// A permit if one is free and no task is waiting.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// `permits` permits if they are free and no task is waiting.
+    pub fn try_acquire_many(&self, permits: usize) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state.lock();
+        if !state.waiters.is_empty() || state.permits < permits {
+            return None;
+        }
+        state.permits -= permits;
+        Some(SemaphorePermit {
+            semaphore: self,
+            permits,
+        })
+    }
+}
+
+/// Future returned by `Semaphore::acquire`. Dropping it gives up its place in the queue.
+#[must_use = "futures do nothing unless awaited"]
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+    /// Its place in the queue, once it had to wait.
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<SemaphorePermit<'a>> {
+        let this = &mut *self;
+        let (semaphore, permits) = (this.semaphore, this.permits);
+
+        if let Some(waiter) = &this.waiter {
+            waiter.waker.register(cx.waker());
+            if !waiter.granted.load(Ordering::Acquire) {
+                return Poll::Pending;
+            }
+            this.waiter = None;
+            return Poll::Ready(SemaphorePermit { semaphore, permits });
+        }
+
+        let mut state = semaphore.state.lock();
+        if state.waiters.is_empty() && state.permits >= permits {
+            // free permits would have a task taking locks in a loop never yield.
+            if budget::poll_proceed(cx).is_pending() {
+                return Poll::Pending;
+            }
+            state.permits -= permits;
+            return Poll::Ready(SemaphorePermit { semaphore, permits });
+        }
+        let waiter = Arc::new(Waiter {
+            permits,
+            granted: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        });
+        waiter.waker.register(cx.waker());
+        state.waiters.push_back(waiter.clone());
+        this.waiter = Some(waiter);
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None => return,
+        };
+        let mut state = self.semaphore.state.lock();
+        if waiter.granted.load(Ordering::Acquire) {
+            // granted, but never taken.
+            state.permits += waiter.permits;
+        } else if let Some(i) = state.waiters.iter().position(|w| Arc::ptr_eq(w, &waiter)) {
+            state.waiters.remove(i);
+        }
+        // the waiters behind it may be served now.
+        state.grant();
+    }
+}
+
+/// Permits taken from a semaphore, given back when dropped.
+#[must_use = "dropping a permit gives it back at once"]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    pub fn num_permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Keeps the permits taken, they are not given back.
+    pub fn forget(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::kernel::interrupts::irq::{self, IrqError, IrqHandle};
+use crate::kernel::interrupts::pit;
+use crate::kernel::sc::Instant;
+use crate::kernel::task::budget;
+use crate::kernel::task::irq_waker::IrqWaker;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use futures_util::future::poll_fn;
+use futures_util::stream::Stream;
+use spin::Mutex;
+
+/*
+Timers for tasks. Pending timers sit in a hashed timer wheel, in the slot of the timer tick they
+are due at. The timer IRQ advances the wheel and wakes the timers of the slots it passes, only ever
+waking, so it never allocates or frees. Timers are added and removed by the futures that own them.
+The IRQ only tries to lock the wheel, so tasks lock it with interrupts enabled, and a tick that
+finds it locked is caught up by the next one. The timers of a task that will never drop them, such
+as one failed by a panic, are removed with `purge`. Timer ticks only decide when a task is woken, a
+future is done once the nanosecond clock passed its deadline.
+ */
+
+/// Number of slots in the wheel, timers due further ahead wait for it to come around.
+const SLOTS: usize = 256;
+
+/// Timer ticks counted by the wheel.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+static WHEEL: Mutex<Wheel> = Mutex::new(Wheel::new());
+
+/// A pending timer.
+struct Entry {
+    /// The timer tick it is due at.
+    tick: u64,
+    waker: IrqWaker,
+}
+
+struct Wheel {
+    /// Last tick whose slot was woken.
+    processed: u64,
+    slots: [Vec<Arc<Entry>>; SLOTS],
+}
+
+impl Wheel {
+    const fn new() -> Self {
+        const EMPTY: Vec<Arc<Entry>> = Vec::new();
+        Self {
+            processed: 0,
+            slots: [EMPTY; SLOTS],
+        }
+    }
+
+    fn remove(&mut self, entry: &Arc<Entry>) {
+        let slot = &mut self.slots[entry.tick as usize % SLOTS];
+        if let Some(i) = slot.iter().position(|e| Arc::ptr_eq(e, entry)) {
+            slot.swap_remove(i);
+        }
+    }
+}
+
+/// Drives the wheel from the timer IRQ.
+pub fn init() -> Result<IrqHandle, IrqError> {
+    irq::register_fn(irq::TIMER, tick)
+}
+
+/// Number of pending timers.
+pub fn pending() -> usize {
+    WHEEL.lock().slots.iter().map(Vec::len).sum()
+}
+
+/// Removes the timers that would wake `waker`, returning how many. Returns `None` if the wheel is
+/// locked, as by a poll that panicked holding it.
+pub fn purge(waker: &Waker) -> Option<usize> {
+    let mut wheel = WHEEL.try_lock()?;
+    let mut removed = 0;
+    for slot in wheel.slots.iter_mut() {
+        let pending = slot.len();
+        slot.retain(|entry| !entry.waker.will_wake(waker));
+        removed += pending - slot.len();
+    }
+    Some(removed)
+}
+
+/// Advances the wheel by one tick, waking the timers that are due.
+fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    let mut wheel = match WHEEL.try_lock() {
+        Some(wheel) => wheel,
+        None => return,
+    };
+    // after a full turn every slot has been passed.
+    let first = (wheel.processed + 1).max((now + 1).saturating_sub(SLOTS as u64));
+    for tick in first..=now {
+        for entry in wheel.slots[tick as usize % SLOTS].iter() {
+            if entry.tick <= now {
+                entry.waker.wake();
+            }
+        }
+    }
+    wheel.processed = now;
+}
+
+/// Adds a timer due at `tick`, or at the first tick the wheel has not passed yet.
+fn register(tick: u64, waker: &Waker) -> Arc<Entry> {
+    let mut wheel = WHEEL.lock();
+    let entry = Arc::new(Entry {
+        tick: tick.max(wheel.processed + 1),
+        waker: IrqWaker::new(),
+    });
+    entry.waker.register(waker);
+    wheel.slots[entry.tick as usize % SLOTS].push(entry.clone());
+    entry
+}
+
+/// Timer ticks covering at least `duration`.
+fn ticks(duration: Duration) -> u64 {
+    let hz = u128::from(pit::frequency());
+    ((duration.as_nanos() * hz + 999_999_999) / 1_000_000_000) as u64
+}
+
+/// Completes once `duration` has passed.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Completes once `deadline` has passed.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        entry: None,
+    }
+}
+
+/// Runs `future` for at most `duration`, failing with `Elapsed` if it is not done by then.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+/// Ticks once every `period`, starting one `period` from now. Panics if `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    assert!(period > Duration::ZERO, "interval period must be non-zero");
+    Interval {
+        period,
+        sleep: sleep(period),
+    }
+}
+
+/// Future returned by `sleep` and `sleep_until`.
+#[must_use = "futures do nothing unless awaited"]
+pub struct Sleep {
+    deadline: Instant,
+    /// The timer in the wheel, once polled.
+    entry: Option<Arc<Entry>>,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Moves the deadline, also after the sleep completed.
+    pub fn reset(&mut self, deadline: Instant) {
+        self.cancel();
+        self.deadline = deadline;
+    }
+
+    fn cancel(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            WHEEL.lock().remove(&entry);
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            if budget::poll_proceed(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.cancel();
+            return Poll::Ready(());
+        }
+
+        match &self.entry {
+            // still pending, the task may have moved to another waker.
+            Some(entry) if entry.tick > TICKS.load(Ordering::Relaxed) => {
+                entry.waker.register(cx.waker())
+            }
+            // woken a little early, the ticks do not line up with the clock.
+            _ => {
+                self.cancel();
+                let tick = TICKS.load(Ordering::Relaxed) + ticks(self.deadline - now);
+                self.entry = Some(register(tick, cx.waker()));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Error of a `timeout` whose time ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Future returned by `timeout`.
+#[must_use = "futures do nothing unless awaited"]
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F> Timeout<F> {
+    pub fn into_inner(self) -> F {
+        self.future
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // `future` is never moved out of a pinned `Timeout`, `sleep` is `Unpin`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.sleep).poll(cx).map(|()| Err(Elapsed))
+    }
+}
+
+/// Returned by `interval`. A tick that comes too late to catch up is skipped.
+pub struct Interval {
+    period: Duration,
+    sleep: Sleep,
+}
+
+impl Interval {
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Completes at the next tick with the instant it was due.
+    pub async fn tick(&mut self) -> Instant {
+        poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        match Pin::new(&mut self.sleep).poll(cx) {
+            Poll::Ready(()) => {
+                let due = self.sleep.deadline();
+                let now = Instant::now();
+                let mut next = due + self.period;
+                if next <= now {
+                    next = now + self.period;
+                }
+                self.sleep.reset(next);
+                Poll::Ready(due)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        self.get_mut().poll_tick(cx).map(Some)
+    }
+}
@@ -0,0 +1,352 @@
+use crate::kernel::mem::space;
+use crate::kernel::mem::stack::{KernelStack, DEFAULT_STACK_SIZE};
+use crate::kernel::mem::vmm::VmError;
+use crate::kernel::task::budget;
+use crate::kernel::task::irq_waker::IrqWaker;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::Poll;
+use futures_util::future::poll_fn;
+use scheduler::{State, Thread, SCHEDULER};
+use spin::Mutex;
+use switch::SwitchFrame;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::VirtAddr;
+
+pub mod scheduler;
+pub mod switch;
+
+/*
+Preemptive kernel threads. Each thread runs on its own guard paged stack and is switched out by
+the timer interrupt, so CPU bound work does not starve the executor running the shell, which lives
+on the boot thread. Blocking calls such as `join` and `sleep` block the whole thread, so async tasks
+should not call them.
+ */
+
+/// Structure for Thread IDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    /// Create an ID, on higher than the last still in existence.
+    pub(crate) fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Errors when spawning a thread.
+#[derive(Debug)]
+pub enum ThreadError {
+    /// The thread table is full.
+    TooManyThreads,
+    /// The stack could not be mapped.
+    Stack(VmError),
+}
+
+/// Owned permission to join a thread. Dropping it detaches the thread.
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    id: ThreadId,
+    result: Arc<Mutex<Option<T>>>,
+    /// Woken by the scheduler once the thread finished.
+    finished: Arc<IrqWaker>,
+}
+
+impl<T> JoinHandle<T> {
+    /// ID of the thread.
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Returns true if the thread has returned.
+    pub fn is_finished(&self) -> bool {
+        without_interrupts(|| {
+            let mut scheduler = SCHEDULER.lock();
+            match scheduler.slot_of(self.id) {
+                Some(slot) => scheduler.get_mut(slot).unwrap().state == State::Finished,
+                None => true,
+            }
+        })
+    }
+
+    /// Completes once the thread has returned, after which `join` does not block. For tasks, which
+    /// must not block the executor's thread.
+    pub async fn finished(&self) {
+        poll_fn(|cx| {
+            self.finished.register(cx.waker());
+            match self.is_finished() {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Blocks until the thread returns, then frees its stack and returns its result.
+    pub fn join(self) -> T {
+        loop {
+            let finished = without_interrupts(|| {
+                let mut scheduler = SCHEDULER.lock();
+                let slot = scheduler.slot_of(self.id).expect("joined thread is gone");
+                let current = scheduler.current_slot();
+                let thread = scheduler.get_mut(slot).unwrap();
+                if thread.state == State::Finished {
+                    return Some(scheduler.remove(slot));
+                }
+
+                thread.joiner = Some(current);
+                scheduler.current().state = State::Blocked;
+                None
+            });
+
+            match finished {
+                Some(thread) => {
+                    free_stack(thread);
+                    break;
+                }
+                None => yield_now(),
+            }
+        }
+
+        self.result
+            .lock()
+            .take()
+            .expect("joined thread did not leave a result")
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        without_interrupts(|| {
+            let mut scheduler = SCHEDULER.lock();
+            if let Some(slot) = scheduler.slot_of(self.id) {
+                scheduler.get_mut(slot).unwrap().detached = true;
+            }
+        });
+    }
+}
+
+/// Registers the boot thread and starts the idle thread. Needs `mem_init`.
+pub fn init() {
+    let stack = KernelStack::allocate("idle thread stack", DEFAULT_STACK_SIZE)
+        .expect("failed to allocate idle thread stack");
+    let rsp = unsafe { switch::initial_context(&stack, idle, 0) };
+
+    let main = Thread {
+        id: ThreadId::new(),
+        name: "main",
+        stack: None,
+        rsp: 0,
+        page_table: space::kernel_page_table(),
+        state: State::Running,
+        joiner: None,
+        detached: true,
+        budget: budget::UNLIMITED,
+        finished: None,
+    };
+    let idle = Thread {
+        id: ThreadId::new(),
+        name: "idle",
+        stack: Some(stack),
+        rsp,
+        page_table: space::kernel_page_table(),
+        state: State::Ready,
+        joiner: None,
+        detached: true,
+        budget: budget::UNLIMITED,
+        finished: None,
+    };
+    without_interrupts(|| SCHEDULER.lock().start(main, idle));
+}
+
+/// Runs `f` on a new thread with a stack of `DEFAULT_STACK_SIZE` bytes.
+pub fn spawn<F, T>(name: &'static str, f: F) -> Result<JoinHandle<T>, ThreadError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let result = Arc::new(Mutex::new(None));
+    let main: Box<dyn FnOnce()> = {
+        let result = result.clone();
+        Box::new(move || *result.lock() = Some(f()))
+    };
+    let main = Box::into_raw(Box::new(main));
+
+    let id = ThreadId::new();
+    let started = start_thread(id, name, space::kernel_page_table(), |stack| unsafe {
+        switch::initial_context(stack, start, main as u64)
+    });
+    match started {
+        Ok(finished) => Ok(JoinHandle {
+            id,
+            result,
+            finished,
+        }),
+        Err(error) => {
+            drop(unsafe { Box::from_raw(main) });
+            Err(error)
+        }
+    }
+}
+
+/// Runs user code at `entry` in ring 3 on a new thread with `page_table` loaded, the user stack
+/// pointer at `user_stack` and `args` in rdi, rsi and rdx. The thread's result is whatever is
+/// stored in `result` before it calls `exit`.
+pub(crate) fn spawn_user<T>(
+    id: ThreadId,
+    name: &'static str,
+    page_table: PhysFrame,
+    entry: VirtAddr,
+    user_stack: VirtAddr,
+    args: [u64; 3],
+    result: Arc<Mutex<Option<T>>>,
+) -> Result<JoinHandle<T>, ThreadError> {
+    let finished = start_thread(id, name, page_table, |stack| unsafe {
+        switch::user_context(stack, entry, user_stack, args)
+    })?;
+    Ok(JoinHandle {
+        id,
+        result,
+        finished,
+    })
+}
+
+/// Resumes user code on a new thread with `page_table` loaded and the registers in `frame`, which
+/// must hold a ring 3 context, e.g. a copy of the one a syscall was entered with.
+pub(crate) fn spawn_user_frame<T>(
+    id: ThreadId,
+    name: &'static str,
+    page_table: PhysFrame,
+    frame: SwitchFrame,
+    result: Arc<Mutex<Option<T>>>,
+) -> Result<JoinHandle<T>, ThreadError> {
+    let finished = start_thread(id, name, page_table, |stack| unsafe {
+        switch::frame_context(stack, frame)
+    })?;
+    Ok(JoinHandle {
+        id,
+        result,
+        finished,
+    })
+}
+
+/// Adds a thread running on a fresh stack from the context `context` builds on it. Returns the
+/// waker woken once it finished.
+fn start_thread(
+    id: ThreadId,
+    name: &'static str,
+    page_table: PhysFrame,
+    context: impl FnOnce(&KernelStack) -> u64,
+) -> Result<Arc<IrqWaker>, ThreadError> {
+    reap();
+
+    let finished = Arc::new(IrqWaker::new());
+    let stack = KernelStack::allocate(name, DEFAULT_STACK_SIZE).map_err(ThreadError::Stack)?;
+    let thread = Thread {
+        id,
+        name,
+        stack: Some(stack),
+        rsp: context(&stack),
+        page_table,
+        state: State::Ready,
+        joiner: None,
+        detached: false,
+        budget: budget::UNLIMITED,
+        finished: Some(finished.clone()),
+    };
+    if let Some(thread) = without_interrupts(|| SCHEDULER.lock().insert(thread)) {
+        free_stack(thread);
+        return Err(ThreadError::TooManyThreads);
+    }
+
+    Ok(finished)
+}
+
+/// Gives the rest of the time slice to the next ready thread.
+pub fn yield_now() {
+    // must match `switch::YIELD_VECTOR`.
+    unsafe { asm!("int 0x81") };
+}
+
+/// Blocks the current thread for at least `ticks` timer ticks.
+pub fn sleep(ticks: u64) {
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let until = scheduler.ticks() + ticks;
+        scheduler.current().state = State::Sleeping(until);
+    });
+    yield_now();
+}
+
+/// ID of the running thread.
+pub fn current() -> ThreadId {
+    without_interrupts(|| SCHEDULER.lock().current().id)
+}
+
+/// ID of the running thread, `None` while the scheduler is locked or not started, e.g. when
+/// panicking in the middle of a switch.
+pub(crate) fn try_current() -> Option<ThreadId> {
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.try_lock()?;
+        let slot = scheduler.current_slot();
+        scheduler.get_mut(slot).map(|thread| thread.id)
+    })
+}
+
+/// Name of the running thread.
+pub fn name() -> &'static str {
+    without_interrupts(|| SCHEDULER.lock().current().name)
+}
+
+/// Switches the running thread to the level 4 table `page_table`.
+pub(crate) fn set_page_table(page_table: PhysFrame) {
+    without_interrupts(|| {
+        SCHEDULER.lock().current().page_table = page_table;
+        let (_, flags) = Cr3::read();
+        unsafe { Cr3::write(page_table, flags) };
+    });
+}
+
+/// Timer ticks since the scheduler started counting.
+pub fn ticks() -> u64 {
+    without_interrupts(|| SCHEDULER.lock().ticks())
+}
+
+/// Ends the current thread. Its stack is freed by whoever joins or reaps it.
+pub(crate) fn exit() -> ! {
+    without_interrupts(|| SCHEDULER.lock().exit_current());
+    yield_now();
+    unreachable!("finished thread was scheduled");
+}
+
+/// First code run by a spawned thread.
+extern "C" fn start(main: u64) -> ! {
+    let main = unsafe { Box::from_raw(main as *mut Box<dyn FnOnce()>) };
+    main();
+    exit();
+}
+
+extern "C" fn idle(_: u64) -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Frees the stacks of finished detached threads.
+fn reap() {
+    while let Some(thread) = without_interrupts(|| SCHEDULER.lock().reap()) {
+        free_stack(thread);
+    }
+}
+
+fn free_stack(thread: Thread) {
+    if let Some(stack) = thread.stack {
+        // the thread is out of the table, so nothing runs on the stack anymore.
+        unsafe { stack.free() }.expect("failed to free thread stack");
+    }
+}
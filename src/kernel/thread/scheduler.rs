@@ -0,0 +1,208 @@
+use super::ThreadId;
+use crate::kernel::gdt;
+use crate::kernel::mem::stack::KernelStack;
+use crate::kernel::task::budget;
+use crate::kernel::task::irq_waker::IrqWaker;
+use alloc::sync::Arc;
+use spin::Mutex;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::PhysFrame;
+
+/*
+Round robin scheduler over a fixed table of threads. It runs from the timer interrupt, so it never
+allocates and its lock is only ever taken with interrupts disabled.
+ */
+
+/// Maximum number of threads, including the boot and idle threads.
+pub const MAX_THREADS: usize = 64;
+/// Slot of the thread the kernel booted on.
+const MAIN: usize = 0;
+/// Slot of the thread run when nothing else is ready.
+const IDLE: usize = 1;
+
+pub(super) static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum State {
+    Ready,
+    Running,
+    /// Waiting until the tick count reaches the value.
+    Sleeping(u64),
+    /// Waiting for another thread to finish.
+    Blocked,
+    Finished,
+}
+
+#[derive(Debug)]
+pub(super) struct Thread {
+    pub id: ThreadId,
+    pub name: &'static str,
+    /// `None` for the boot thread, which runs on the bootloader's stack.
+    pub stack: Option<KernelStack>,
+    /// Saved stack pointer while the thread is not running.
+    pub rsp: u64,
+    /// Level 4 table loaded while the thread runs.
+    pub page_table: PhysFrame,
+    pub state: State,
+    /// Slot of the thread blocked in `join` on this one.
+    pub joiner: Option<usize>,
+    /// Nobody will join the thread, it is reaped once finished.
+    pub detached: bool,
+    /// Poll budget of the task the thread's executor polls, saved while the thread is not running.
+    pub budget: u32,
+    /// Woken once the thread finished, for tasks waiting on its `JoinHandle`. `None` for the boot
+    /// and idle threads.
+    pub finished: Option<Arc<IrqWaker>>,
+}
+
+pub(super) struct Scheduler {
+    threads: [Option<Thread>; MAX_THREADS],
+    current: usize,
+    ticks: u64,
+    running: bool,
+}
+
+impl Scheduler {
+    const fn new() -> Self {
+        const EMPTY: Option<Thread> = None;
+        Scheduler {
+            threads: [EMPTY; MAX_THREADS],
+            current: MAIN,
+            ticks: 0,
+            running: false,
+        }
+    }
+
+    /// Registers the running boot thread and the idle thread and starts switching.
+    pub fn start(&mut self, main: Thread, idle: Thread) {
+        self.threads[MAIN] = Some(main);
+        self.threads[IDLE] = Some(idle);
+        self.current = MAIN;
+        self.running = true;
+    }
+
+    /// Puts `thread` in a free slot. Gives it back if the table is full.
+    pub fn insert(&mut self, thread: Thread) -> Option<Thread> {
+        match self.threads.iter_mut().skip(IDLE + 1).find(|t| t.is_none()) {
+            Some(slot) => {
+                *slot = Some(thread);
+                None
+            }
+            None => Some(thread),
+        }
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub fn current_slot(&self) -> usize {
+        self.current
+    }
+
+    pub fn current(&mut self) -> &mut Thread {
+        self.threads[self.current]
+            .as_mut()
+            .expect("current thread slot is empty")
+    }
+
+    pub fn slot_of(&self, id: ThreadId) -> Option<usize> {
+        self.threads
+            .iter()
+            .position(|t| matches!(t, Some(t) if t.id == id))
+    }
+
+    pub fn get_mut(&mut self, slot: usize) -> Option<&mut Thread> {
+        self.threads[slot].as_mut()
+    }
+
+    /// Empties `slot`, which must hold a finished thread.
+    pub fn remove(&mut self, slot: usize) -> Thread {
+        let thread = self.threads[slot].take().expect("removed empty slot");
+        debug_assert_eq!(thread.state, State::Finished);
+        thread
+    }
+
+    /// Marks the current thread finished and wakes its joiner and the task waiting for it.
+    pub fn exit_current(&mut self) {
+        let thread = self.current();
+        thread.state = State::Finished;
+        if let Some(finished) = &thread.finished {
+            finished.wake();
+        }
+        if let Some(joiner) = thread.joiner.take() {
+            self.wake(joiner);
+        }
+    }
+
+    /// Takes a finished detached thread out of the table, for its stack to be freed.
+    pub fn reap(&mut self) -> Option<Thread> {
+        let slot = self
+            .threads
+            .iter()
+            .position(|t| matches!(t, Some(t) if t.detached && t.state == State::Finished))?;
+        Some(self.remove(slot))
+    }
+
+    pub fn wake(&mut self, slot: usize) {
+        if let Some(thread) = self.threads[slot].as_mut() {
+            if matches!(thread.state, State::Blocked | State::Sleeping(_)) {
+                thread.state = State::Ready;
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        self.ticks += 1;
+        let now = self.ticks;
+        for thread in self.threads.iter_mut().flatten() {
+            if matches!(thread.state, State::Sleeping(until) if until <= now) {
+                thread.state = State::Ready;
+            }
+        }
+    }
+
+    /// Saves `rsp` for the current thread and returns the saved stack pointer of the next one.
+    fn switch(&mut self, rsp: u64) -> u64 {
+        if !self.running {
+            return rsp;
+        }
+
+        let thread = self.current();
+        thread.rsp = rsp;
+        thread.budget = budget::replace(budget::UNLIMITED);
+        if thread.state == State::Running {
+            thread.state = State::Ready;
+        }
+
+        // the slots after the current one first, the current one last.
+        let next = (1..=MAX_THREADS)
+            .map(|i| (self.current + i) % MAX_THREADS)
+            .filter(|&slot| slot != IDLE)
+            .find(|&slot| matches!(&self.threads[slot], Some(t) if t.state == State::Ready))
+            .unwrap_or(IDLE);
+
+        self.current = next;
+        let thread = self.current();
+        thread.state = State::Running;
+        budget::replace(thread.budget);
+        // user threads enter the kernel on their own stack.
+        if let Some(stack) = &thread.stack {
+            gdt::set_kernel_stack(stack.top());
+        }
+        let (page_table, flags) = Cr3::read();
+        if page_table != thread.page_table {
+            unsafe { Cr3::write(thread.page_table, flags) };
+        }
+        thread.rsp
+    }
+}
+
+/// Called by the switch entries with interrupts disabled.
+pub(super) fn switch(rsp: u64, tick: bool) -> u64 {
+    let mut scheduler = SCHEDULER.lock();
+    if tick {
+        scheduler.tick();
+    }
+    scheduler.switch(rsp)
+}
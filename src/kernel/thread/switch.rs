@@ -0,0 +1,183 @@
+use super::scheduler;
+use crate::kernel::gdt;
+use crate::kernel::mem::stack::KernelStack;
+use core::arch::global_asm;
+use core::mem::size_of;
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+
+/*
+Context switch entries. Both save every general purpose register on the interrupted stack, hand
+the stack pointer to the scheduler and resume whichever stack it returns, so a thread's saved
+context is simply a `SwitchFrame` at its saved stack pointer. The kernel is built without SSE,
+so there is no floating point state to save.
+ */
+
+/// Vector `yield_now` raises to switch threads without waiting for the timer.
+pub const YIELD_VECTOR: u8 = 0x81;
+
+/// Registers as laid out on the stack by the switch entries, lowest address first.
+#[repr(C)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SwitchFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    // pushed by the CPU on interrupt entry.
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Defines an interrupt entry `$entry` that saves a `SwitchFrame` and resumes the stack returned by
+/// `extern "C" fn $switch(rsp: u64) -> u64`.
+macro_rules! switch_entry {
+    ($entry:literal, $switch:literal) => {
+        global_asm!(
+            concat!(".global ", $entry),
+            concat!($entry, ":"),
+            "push rax",
+            "push rbx",
+            "push rcx",
+            "push rdx",
+            "push rsi",
+            "push rdi",
+            "push rbp",
+            "push r8",
+            "push r9",
+            "push r10",
+            "push r11",
+            "push r12",
+            "push r13",
+            "push r14",
+            "push r15",
+            // the CPU pushed five words, so after fifteen more the stack is 16 byte aligned.
+            "mov rdi, rsp",
+            concat!("call ", $switch),
+            "mov rsp, rax",
+            "pop r15",
+            "pop r14",
+            "pop r13",
+            "pop r12",
+            "pop r11",
+            "pop r10",
+            "pop r9",
+            "pop r8",
+            "pop rbp",
+            "pop rdi",
+            "pop rsi",
+            "pop rdx",
+            "pop rcx",
+            "pop rbx",
+            "pop rax",
+            "iretq",
+        );
+    };
+}
+
+pub(crate) use switch_entry;
+
+switch_entry!("thread_timer_entry", "thread_timer_switch");
+switch_entry!("thread_yield_entry", "thread_yield_switch");
+
+extern "C" {
+    fn thread_timer_entry();
+    fn thread_yield_entry();
+}
+
+#[no_mangle]
+extern "C" fn thread_timer_switch(rsp: u64) -> u64 {
+    crate::kernel::interrupts::idt::timer_tick();
+    scheduler::switch(rsp, true)
+}
+
+#[no_mangle]
+extern "C" fn thread_yield_switch(rsp: u64) -> u64 {
+    scheduler::switch(rsp, false)
+}
+
+/// Address of the timer interrupt handler.
+pub fn timer_entry() -> VirtAddr {
+    VirtAddr::new(thread_timer_entry as *const () as u64)
+}
+
+/// Address of the `YIELD_VECTOR` handler.
+pub fn yield_entry() -> VirtAddr {
+    VirtAddr::new(thread_yield_entry as *const () as u64)
+}
+
+/// Builds the context a new thread starts from on `stack`: interrupts enabled, running
+/// `entry(arg)`. Returns the stack pointer to resume.
+pub(super) unsafe fn initial_context(
+    stack: &KernelStack,
+    entry: extern "C" fn(u64) -> !,
+    arg: u64,
+) -> u64 {
+    let (code, data) = gdt::kernel_selectors();
+    write_frame(
+        stack,
+        SwitchFrame {
+            rdi: arg,
+            rip: entry as usize as u64,
+            cs: u64::from(code.0),
+            // bit 1 is reserved and always set.
+            rflags: RFlags::INTERRUPT_FLAG.bits() | 0x2,
+            // as if `entry` had been called, which leaves the stack 8 bytes off alignment.
+            rsp: (stack.top() - 8u64).as_u64(),
+            ss: u64::from(data.0),
+            ..SwitchFrame::default()
+        },
+    )
+}
+
+/// Builds the context a new user thread starts from: ring 3 at `entry` with the user stack
+/// pointer `user_stack` and `args` in rdi, rsi and rdx. `stack` becomes the kernel stack the
+/// thread enters on interrupts.
+pub(super) unsafe fn user_context(
+    stack: &KernelStack,
+    entry: VirtAddr,
+    user_stack: VirtAddr,
+    args: [u64; 3],
+) -> u64 {
+    let (code, data) = gdt::user_selectors();
+    write_frame(
+        stack,
+        SwitchFrame {
+            rdi: args[0],
+            rsi: args[1],
+            rdx: args[2],
+            rip: entry.as_u64(),
+            cs: u64::from(code.0),
+            rflags: RFlags::INTERRUPT_FLAG.bits() | 0x2,
+            rsp: user_stack.as_u64(),
+            ss: u64::from(data.0),
+            ..SwitchFrame::default()
+        },
+    )
+}
+
+/// Builds the context a thread resumes user code from with the registers in `frame`.
+pub(super) unsafe fn frame_context(stack: &KernelStack, frame: SwitchFrame) -> u64 {
+    write_frame(stack, frame)
+}
+
+/// Places `frame` at the top of `stack` and returns its address.
+unsafe fn write_frame(stack: &KernelStack, frame: SwitchFrame) -> u64 {
+    let ptr = (stack.top() - size_of::<SwitchFrame>() as u64).as_mut_ptr::<SwitchFrame>();
+    ptr.write(frame);
+    ptr as u64
+}
@@ -0,0 +1,149 @@
+use super::image::{Image, Segment};
+use crate::kernel::mem::space;
+use alloc::vec::Vec;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/*
+ELF64 executables. Only statically linked x86_64 executables are supported: every PT_LOAD segment
+is loaded at its link address, which must lie in the user window, and the rest of the file, section
+headers included, is ignored.
+ */
+
+/// Size of the ELF file header.
+const HEADER_SIZE: usize = 64;
+/// Size of a program header.
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// `e_type` of executables.
+const ET_EXEC: u16 = 2;
+/// `e_machine` of x86_64.
+const EM_X86_64: u16 = 0x3e;
+/// `p_type` of loadable segments.
+const PT_LOAD: u32 = 1;
+/// `p_flags` bit of executable segments.
+const PF_X: u32 = 1;
+/// `p_flags` bit of writable segments.
+const PF_W: u32 = 2;
+
+/// Reasons a file can not be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file ends inside a header or segment.
+    Truncated,
+    /// The file does not start with the ELF magic.
+    NotElf,
+    /// Not a little endian 64 bit x86_64 file.
+    Unsupported,
+    /// Not an executable, e.g. a shared object or relocatable object.
+    NotExecutable,
+    /// A segment lies outside the user window or is larger in the file than in memory.
+    BadSegment,
+    /// The file has no loadable segments, or the entry point is not in an executable one.
+    BadEntry,
+}
+
+/// Parses the executable `file` into an image borrowing the segment contents from it.
+pub fn parse(file: &[u8]) -> Result<Image<'_>, ElfError> {
+    let header = file.get(..HEADER_SIZE).ok_or(ElfError::Truncated)?;
+    if header[..4] != *b"\x7fELF" {
+        return Err(ElfError::NotElf);
+    }
+    // 64 bit, little endian, version 1.
+    if header[4..7] != [2, 1, 1] || u16_at(header, 18) != EM_X86_64 {
+        return Err(ElfError::Unsupported);
+    }
+    if u16_at(header, 16) != ET_EXEC {
+        return Err(ElfError::NotExecutable);
+    }
+
+    let entry = u64_at(header, 24);
+    let ph_offset = u64_at(header, 32) as usize;
+    let ph_size = u16_at(header, 54) as usize;
+    let ph_count = u16_at(header, 56) as usize;
+    if ph_count > 0 && ph_size < PROGRAM_HEADER_SIZE {
+        return Err(ElfError::Unsupported);
+    }
+
+    let mut segments = Vec::new();
+    for i in 0..ph_count {
+        let start = ph_size
+            .checked_mul(i)
+            .and_then(|offset| offset.checked_add(ph_offset))
+            .ok_or(ElfError::Truncated)?;
+        let ph = start
+            .checked_add(PROGRAM_HEADER_SIZE)
+            .and_then(|end| file.get(start..end))
+            .ok_or(ElfError::Truncated)?;
+        if u32_at(ph, 0) != PT_LOAD || u64_at(ph, 40) == 0 {
+            continue;
+        }
+        segments.push(segment(file, ph)?);
+    }
+
+    let executable = segments.iter().any(|segment| {
+        !segment.flags.contains(PageTableFlags::NO_EXECUTE)
+            && segment.start.as_u64() <= entry
+            && entry < segment.start.as_u64() + segment.size
+    });
+    if !executable {
+        return Err(ElfError::BadEntry);
+    }
+
+    Ok(Image {
+        entry: VirtAddr::new(entry),
+        segments,
+    })
+}
+
+/// The segment described by the PT_LOAD program header `ph`.
+fn segment<'a>(file: &'a [u8], ph: &[u8]) -> Result<Segment<'a>, ElfError> {
+    let flags = u32_at(ph, 4);
+    let offset = u64_at(ph, 8) as usize;
+    let start = u64_at(ph, 16);
+    let file_size = u64_at(ph, 32);
+    let size = u64_at(ph, 40);
+
+    let end = start.checked_add(size).ok_or(ElfError::BadSegment)?;
+    let in_window = matches!(VirtAddr::try_new(start), Ok(addr) if space::is_user(addr))
+        && matches!(VirtAddr::try_new(end - 1), Ok(addr) if space::is_user(addr));
+    if file_size > size || !in_window {
+        return Err(ElfError::BadSegment);
+    }
+    let data = offset
+        .checked_add(file_size as usize)
+        .and_then(|end| file.get(offset..end))
+        .ok_or(ElfError::Truncated)?;
+
+    let mut page_flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if flags & PF_W != 0 {
+        page_flags |= PageTableFlags::WRITABLE;
+    }
+    if flags & PF_X == 0 {
+        page_flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    Ok(Segment {
+        start: VirtAddr::new(start),
+        size,
+        data,
+        flags: page_flags,
+    })
+}
+
+// callers have checked `bytes` is long enough.
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(word)
+}
@@ -0,0 +1,49 @@
+use crate::kernel::mem::space::USER_START;
+use alloc::vec;
+use alloc::vec::Vec;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/*
+Loadable program images. An image is a list of segments to copy into a fresh address space and the
+address to start running at. ELF executables are parsed into images by `elf::parse`, built in
+programs are flat images of position independent code.
+ */
+
+/// A program ready to be started by `process::create`.
+#[derive(Debug, Clone)]
+pub struct Image<'a> {
+    /// Address the program starts running at.
+    pub entry: VirtAddr,
+    pub segments: Vec<Segment<'a>>,
+}
+
+/// A range of user memory and its initial contents.
+#[derive(Debug, Clone)]
+pub struct Segment<'a> {
+    /// First address of the segment.
+    pub start: VirtAddr,
+    /// Size in memory, the bytes past `data` are zeroed.
+    pub size: u64,
+    /// Initial contents of the start of the segment.
+    pub data: &'a [u8],
+    /// Flags the pages of the segment are mapped with.
+    pub flags: PageTableFlags,
+}
+
+impl<'a> Image<'a> {
+    /// An image running `code` from its first byte, loaded read only at the start of the user
+    /// window.
+    pub fn flat(code: &'a [u8]) -> Self {
+        let start = VirtAddr::new(USER_START);
+        Image {
+            entry: start,
+            segments: vec![Segment {
+                start,
+                size: code.len() as u64,
+                data: code,
+                flags: PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+            }],
+        }
+    }
+}
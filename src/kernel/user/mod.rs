@@ -0,0 +1,226 @@
+use crate::kernel::environ::environmentref;
+use crate::kernel::fs::{filesystemref, Identifier};
+use crate::kernel::gdt;
+use crate::kernel::mem::region::{Backing, Region, GUARD_SIZE};
+use crate::kernel::mem::space::AddressSpace;
+use crate::kernel::mem::vmm::VmError;
+use crate::kernel::process::{self, Pid};
+use crate::kernel::status::Status;
+use crate::kernel::thread::{self, ThreadError};
+use crate::vga_println;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use elf::ElfError;
+use image::Image;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+pub mod elf;
+pub mod image;
+pub mod programs;
+pub mod syscall;
+
+pub use crate::kernel::mem::space::{USER_END, USER_START};
+
+/*
+User mode programs. A program is loaded from an `Image` into the address space of a new process
+and run on its own thread in ring 3. It can only reach the kernel through the syscalls in
+`syscall`, and is killed, freeing its memory, if it faults. Programs start like System V processes:
+the stack pointer points at argc, followed by the argv and envp arrays, and argc, argv and envp are
+also passed in rdi, rsi and rdx.
+ */
+
+/// Size of a program's stack, excluding its guard page.
+pub const USER_STACK_SIZE: u64 = 16 * 4096;
+/// Most bytes of the stack that arguments and environment may take up.
+pub const MAX_ARGS_SIZE: u64 = USER_STACK_SIZE / 4;
+
+/// Errors when starting a program.
+#[derive(Debug)]
+pub enum UserError {
+    /// The program has no code.
+    Empty,
+    /// No file at the given path, or no process to fork.
+    NotFound,
+    /// The file is not a loadable executable.
+    Elf(ElfError),
+    /// Arguments and environment take more than `MAX_ARGS_SIZE` bytes.
+    ArgumentsTooLong,
+    /// Mapping the program failed.
+    Memory(VmError),
+    /// Starting its thread failed.
+    Thread(ThreadError),
+}
+
+impl From<VmError> for UserError {
+    fn from(error: VmError) -> Self {
+        UserError::Memory(error)
+    }
+}
+
+/// Copies `code` into user memory and runs it as a process from its first byte, with `name` as
+/// its only argument.
+pub fn spawn(name: &str, code: &[u8]) -> Result<Pid, UserError> {
+    process::create(name, &Image::flat(code), &[name])
+}
+
+/// Loads the ELF executable at `path` and runs it as a process with the arguments `args`, `path`
+/// itself being the first. Relative paths start at the current working directory.
+pub fn exec(path: &str, args: &[&str]) -> Result<Pid, UserError> {
+    let path =
+        process::with_current(|process| process.path(path)).unwrap_or_else(|| absolute(path));
+    let fs = filesystemref();
+    let file = fs
+        .open(Identifier::Name(path.clone()))
+        .and_then(|file| file.id())
+        .and_then(|id| fs.read(id))
+        .ok_or(UserError::NotFound)?;
+    let image = elf::parse(&file).map_err(UserError::Elf)?;
+
+    let argv: Vec<&str> = core::iter::once(path.as_str())
+        .chain(args.iter().copied())
+        .collect();
+    process::create(&path, &image, &argv)
+}
+
+/// Where the startup stack of a program ended up.
+pub(crate) struct StartupStack {
+    pub rsp: VirtAddr,
+    pub argc: u64,
+    pub argv: VirtAddr,
+    pub envp: VirtAddr,
+}
+
+/// Maps the segments of `image` and the stack into `space`, and writes `args` and `env` to the
+/// top of the stack.
+pub(crate) fn load(
+    space: &mut AddressSpace,
+    image: &Image,
+    args: &[&str],
+    env: &[&str],
+) -> Result<StartupStack, UserError> {
+    if image.segments.iter().all(|segment| segment.size == 0) {
+        return Err(UserError::Empty);
+    }
+
+    for segment in image.segments.iter().filter(|segment| segment.size > 0) {
+        let name = if segment.flags.contains(PageTableFlags::WRITABLE) {
+            "data"
+        } else if segment.flags.contains(PageTableFlags::NO_EXECUTE) {
+            "rodata"
+        } else {
+            "text"
+        };
+        space.map(Region::new(
+            name,
+            segment.start,
+            segment.size,
+            segment.flags,
+        ))?;
+        space.write(segment.start, segment.data)?;
+    }
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::NO_EXECUTE;
+    let size = USER_STACK_SIZE + GUARD_SIZE;
+    let stack = Region {
+        backing: Backing::Stack,
+        ..Region::new("stack", VirtAddr::new(USER_END - size), size, flags)
+    };
+    space.map(stack)?;
+
+    let (bytes, startup) = startup_stack(stack.end, args, env)?;
+    space.write(startup.rsp, &bytes)?;
+    Ok(startup)
+}
+
+/// Lays out argc, the argv and envp arrays and the strings they point to below `top`. Returns
+/// the bytes from the new stack pointer up to `top`.
+fn startup_stack(
+    top: VirtAddr,
+    args: &[&str],
+    env: &[&str],
+) -> Result<(Vec<u8>, StartupStack), UserError> {
+    let strings: usize = args.iter().chain(env).map(|s| s.len() + 1).sum();
+    let pointers = 8 * (1 + args.len() + 1 + env.len() + 1);
+    // the stack pointer points at argc and is 16 byte aligned.
+    let size = ((strings + pointers) as u64 + 15) & !15;
+    if size > MAX_ARGS_SIZE {
+        return Err(UserError::ArgumentsTooLong);
+    }
+
+    let rsp = top - size;
+    let mut bytes = vec![0; size as usize];
+    let mut string_at = size as usize - strings;
+    let mut place = |s: &str| {
+        bytes[string_at..string_at + s.len()].copy_from_slice(s.as_bytes());
+        let ptr = rsp.as_u64() + string_at as u64;
+        string_at += s.len() + 1;
+        ptr
+    };
+
+    let mut words = vec![args.len() as u64];
+    words.extend(args.iter().map(|s| place(s)));
+    words.push(0);
+    words.extend(env.iter().map(|s| place(s)));
+    words.push(0);
+    for (i, word) in words.iter().enumerate() {
+        bytes[i * 8..i * 8 + 8].copy_from_slice(&word.to_le_bytes());
+    }
+
+    let startup = StartupStack {
+        rsp,
+        argc: args.len() as u64,
+        argv: rsp + 8u64,
+        envp: rsp + 8 * (args.len() as u64 + 2),
+    };
+    Ok((bytes, startup))
+}
+
+/// `path` relative to the global working directory.
+fn absolute(path: &str) -> String {
+    if path.starts_with('/') {
+        return String::from(path);
+    }
+    let cwd = environmentref().cwd();
+    match cwd.ends_with('/') {
+        true => format!("{}{}", cwd, path),
+        false => format!("{}/{}", cwd, path),
+    }
+}
+
+/// Where a program killed by an exception resumes, in kernel mode on its thread's stack.
+extern "C" fn fault_exit() -> ! {
+    process::exit(Status::Killed)
+}
+
+/// Makes an exception raised in user mode return into `fault_exit` instead of the program.
+/// Returns false, leaving the frame alone, if the exception came from the kernel.
+pub(crate) fn kill_on_fault(stack_frame: &mut InterruptStackFrame, exception: &str) -> bool {
+    if stack_frame.code_segment & 3 != 3 {
+        return false;
+    }
+
+    vga_println!(
+        "{}: killed by {} at {:?}",
+        thread::name(),
+        exception,
+        stack_frame.instruction_pointer
+    );
+    let (code, data) = gdt::kernel_selectors();
+    unsafe {
+        stack_frame.as_mut().update(|frame| {
+            frame.instruction_pointer = VirtAddr::new(fault_exit as *const () as u64);
+            frame.code_segment = u64::from(code.0);
+            // as if `fault_exit` had been called, see `thread::switch::initial_context`.
+            frame.stack_pointer = gdt::kernel_stack() - 8u64;
+            frame.stack_segment = u64::from(data.0);
+        });
+    }
+    true
+}
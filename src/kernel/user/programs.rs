@@ -0,0 +1,57 @@
+use core::arch::global_asm;
+
+/*
+Programs built into the kernel image. Each one is position independent code between a start and
+an end label, copied into user memory by `user::spawn`.
+ */
+
+global_asm!(
+    ".global user_hello_start",
+    ".global user_hello_end",
+    "user_hello_start:",
+    "mov rax, 1", // write
+    "lea rdi, [rip + user_hello_msg]",
+    "lea rsi, [rip + user_hello_msg_end]",
+    "sub rsi, rdi",
+    "int 0x80",
+    "xor edi, edi",
+    "xor eax, eax", // exit(0)
+    "int 0x80",
+    "ud2",
+    "user_hello_msg:",
+    ".ascii \"Hello from user mode!\\n\"",
+    "user_hello_msg_end:",
+    "user_hello_end:",
+);
+
+extern "C" {
+    static user_hello_start: u8;
+    static user_hello_end: u8;
+}
+
+/// Names and code of the built in programs.
+const BUILTINS: [(&str, fn() -> &'static [u8]); 1] = [("hello", hello)];
+
+/// Finds the built in program `name`.
+pub fn find(name: &str) -> Option<(&'static str, &'static [u8])> {
+    BUILTINS
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(builtin, code)| (*builtin, code()))
+}
+
+/// Names of the built in programs.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    BUILTINS.iter().map(|(name, _)| *name)
+}
+
+fn hello() -> &'static [u8] {
+    unsafe { code_between(&user_hello_start, &user_hello_end) }
+}
+
+/// The bytes from `start` up to `end`.
+unsafe fn code_between(start: &'static u8, end: &'static u8) -> &'static [u8] {
+    let start = start as *const u8;
+    let len = end as *const u8 as usize - start as usize;
+    core::slice::from_raw_parts(start, len)
+}
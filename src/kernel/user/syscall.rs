@@ -0,0 +1,291 @@
+use crate::kernel::fs::{filesystemref, Identifier};
+use crate::kernel::process::{self, Process};
+use crate::kernel::status::Status;
+use crate::kernel::thread::{self, switch::SwitchFrame};
+use crate::vga_print;
+use alloc::string::String;
+use core::arch::global_asm;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/*
+Syscalls, entered from user mode with `int 0x80`. The syscall number goes in rax and up to five
+arguments in rdi, rsi, rdx, r10 and r8. The result comes back in rax, errors as the negated
+`Status` code. Strings and buffers are passed as pointer and length, paths are relative to the
+working directory of the calling process.
+ */
+
+/// Vector of the syscall interrupt.
+pub const SYSCALL_VECTOR: u8 = 0x80;
+
+/// `exit(status)`, never returns for a valid `Status` code.
+pub const EXIT: u64 = 0;
+/// `write(str, len)`, prints to the screen.
+pub const WRITE: u64 = 1;
+/// `yield()`
+pub const YIELD: u64 = 2;
+/// `sleep(ticks)`
+pub const SLEEP: u64 = 3;
+/// `getenv(name, len, buf, buf_len)`, returns the length of the value.
+pub const GETENV: u64 = 4;
+/// `setenv(name, len, value, value_len)`
+pub const SETENV: u64 = 5;
+/// `getcwd(buf, buf_len)`, returns the length of the working directory.
+pub const GETCWD: u64 = 6;
+/// `mkfile(path, len)`, returns the new file's id.
+pub const MKFILE: u64 = 7;
+/// `mkdir(path, len)`, returns the new directory's id.
+pub const MKDIR: u64 = 8;
+/// `size(path, len)`, returns the size of a file or directory.
+pub const SIZE: u64 = 9;
+/// `list(buf, buf_len)`, fills `buf` with the names in the working directory, one per line, and
+/// returns the length of the full list.
+pub const LIST: u64 = 10;
+/// `chdir(path, len)`, changes the working directory of the process.
+pub const CHDIR: u64 = 11;
+/// `open(path, len)`, returns a descriptor reading the file from its start.
+pub const OPEN: u64 = 12;
+/// `close(fd)`
+pub const CLOSE: u64 = 13;
+/// `read(fd, buf, buf_len)`, returns the number of bytes read, zero at the end of the file.
+pub const READ: u64 = 14;
+/// `wait(pid)`, blocks until the child `pid` exits and returns its status code.
+pub const WAIT: u64 = 15;
+/// `fork()`, returns the child's pid in the parent and zero in the child.
+pub const FORK: u64 = 16;
+
+type Handler = fn(&[u64; 5]) -> Result<u64, Status>;
+
+/// Handlers indexed by syscall number, all but `FORK`.
+const SYSCALLS: [Handler; 16] = [
+    sys_exit, sys_write, sys_yield, sys_sleep, sys_getenv, sys_setenv, sys_getcwd, sys_mkfile,
+    sys_mkdir, sys_size, sys_list, sys_chdir, sys_open, sys_close, sys_read, sys_wait,
+];
+
+crate::kernel::thread::switch::switch_entry!("syscall_entry", "syscall_dispatch");
+
+extern "C" {
+    fn syscall_entry();
+}
+
+/// Address of the `SYSCALL_VECTOR` handler.
+pub fn entry() -> VirtAddr {
+    VirtAddr::new(syscall_entry as *const () as u64)
+}
+
+#[no_mangle]
+extern "C" fn syscall_dispatch(rsp: u64) -> u64 {
+    let frame = unsafe { &mut *(rsp as *mut SwitchFrame) };
+    let args = [frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8];
+
+    // handlers take locks a preempted thread may hold, so let the timer in.
+    interrupts::enable();
+    let result = match frame.rax {
+        // the child starts from a copy of the caller's registers.
+        FORK => sys_fork(frame),
+        number => match SYSCALLS.get(number as usize) {
+            Some(handler) => handler(&args),
+            None => Err(Status::NotFound),
+        },
+    };
+    interrupts::disable();
+
+    frame.rax = match result {
+        Ok(value) => value,
+        Err(status) => (-(status as i64)) as u64,
+    };
+    rsp
+}
+
+/// Checks that the `len` bytes at `ptr` lie in a single user accessible region of the calling
+/// program, writable if `write` is set.
+fn check_user(ptr: u64, len: u64, write: bool) -> Result<(), Status> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    let start = VirtAddr::try_new(ptr).map_err(|_| Status::PermissionDenied)?;
+    let last = ptr
+        .checked_add(len - 1)
+        .and_then(|last| VirtAddr::try_new(last).ok())
+        .ok_or(Status::PermissionDenied)?;
+    let region = current(|process| process.space().and_then(|space| space.find(start)))?
+        .ok_or(Status::PermissionDenied)?;
+
+    let mut needed = PageTableFlags::USER_ACCESSIBLE;
+    if write {
+        needed |= PageTableFlags::WRITABLE;
+    }
+    if !region.contains(last) || region.guard_contains(start) || !region.flags.contains(needed) {
+        return Err(Status::PermissionDenied);
+    }
+    Ok(())
+}
+
+/// Runs `f` on the calling process.
+fn current<R>(f: impl FnOnce(&mut Process) -> R) -> Result<R, Status> {
+    process::with_current(f).ok_or(Status::NotFound)
+}
+
+/// The path at `ptr`, relative to the working directory of the calling process.
+fn user_path(ptr: u64, len: u64) -> Result<String, Status> {
+    let path = user_str(ptr, len)?;
+    current(|process| process.path(path))
+}
+
+fn user_str<'a>(ptr: u64, len: u64) -> Result<&'a str, Status> {
+    check_user(ptr, len, false)?;
+    let bytes = unsafe { core::slice::from_raw_parts(ptr as *const u8, len as usize) };
+    core::str::from_utf8(bytes).map_err(|_| Status::WrongType)
+}
+
+/// Copies as much of `data` as fits into the user buffer. Returns the full length of `data`.
+fn copy_out(ptr: u64, len: u64, data: &[u8]) -> Result<u64, Status> {
+    let count = data.len().min(len as usize);
+    check_user(ptr, count as u64, true)?;
+    unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), ptr as *mut u8, count) };
+    Ok(data.len() as u64)
+}
+
+fn sys_exit(args: &[u64; 5]) -> Result<u64, Status> {
+    let status = Status::from_code(args[0]).ok_or(Status::WrongType)?;
+    process::exit(status)
+}
+
+fn sys_write(args: &[u64; 5]) -> Result<u64, Status> {
+    vga_print!("{}", user_str(args[0], args[1])?);
+    Ok(args[1])
+}
+
+fn sys_yield(_: &[u64; 5]) -> Result<u64, Status> {
+    thread::yield_now();
+    Ok(0)
+}
+
+fn sys_sleep(args: &[u64; 5]) -> Result<u64, Status> {
+    thread::sleep(args[0]);
+    Ok(0)
+}
+
+fn sys_getenv(args: &[u64; 5]) -> Result<u64, Status> {
+    let name = user_str(args[0], args[1])?;
+    let value = current(|process| process.env().get(name))?.ok_or(Status::NotFound)?;
+    copy_out(args[2], args[3], value.as_bytes())
+}
+
+fn sys_setenv(args: &[u64; 5]) -> Result<u64, Status> {
+    let name = user_str(args[0], args[1])?;
+    let value = user_str(args[2], args[3])?;
+    let status = current(|process| {
+        let env = process.env();
+        if env.contains_entry(name) {
+            env.update(name, value)
+        } else {
+            env.add(name, value).err().unwrap_or(Status::Success)
+        }
+    })?;
+    match status {
+        Status::Success => Ok(0),
+        error => Err(error),
+    }
+}
+
+fn sys_getcwd(args: &[u64; 5]) -> Result<u64, Status> {
+    let cwd = current(|process| process.cwd())?;
+    copy_out(args[0], args[1], cwd.as_bytes())
+}
+
+fn sys_mkfile(args: &[u64; 5]) -> Result<u64, Status> {
+    let path = user_path(args[0], args[1])?;
+    let fd = filesystemref()
+        .create_file(&path)
+        .ok_or(Status::FailedToWrite)?;
+    fd.id().map(u64::from).ok_or(Status::FailedToWrite)
+}
+
+fn sys_mkdir(args: &[u64; 5]) -> Result<u64, Status> {
+    let path = user_path(args[0], args[1])?;
+    let fd = filesystemref()
+        .create_dir(&path)
+        .ok_or(Status::FailedToWrite)?;
+    fd.id().map(u64::from).ok_or(Status::FailedToWrite)
+}
+
+fn sys_size(args: &[u64; 5]) -> Result<u64, Status> {
+    let path = user_path(args[0], args[1])?;
+    let fd = filesystemref()
+        .open(Identifier::Name(path))
+        .ok_or(Status::NotFound)?;
+    fd.size().map(|size| size as u64).ok_or(Status::NotFound)
+}
+
+fn sys_list(args: &[u64; 5]) -> Result<u64, Status> {
+    let cwd = current(|process| process.cwd())?;
+    let map = filesystemref().list(&cwd).ok_or(Status::NotFound)?;
+    let mut list = String::new();
+    for fd in map.values() {
+        list.push_str(&fd.name().ok_or(Status::FailedToRead)?);
+        list.push('\n');
+    }
+    copy_out(args[0], args[1], list.as_bytes())
+}
+
+fn sys_chdir(args: &[u64; 5]) -> Result<u64, Status> {
+    let path = user_path(args[0], args[1])?;
+    if path != "/" {
+        let fd = filesystemref()
+            .open(Identifier::Name(path.clone()))
+            .ok_or(Status::NotFound)?;
+        if !fd.kind().ok_or(Status::NotFound)? {
+            return Err(Status::WrongType);
+        }
+    }
+    match current(|process| process.env().update("cwd", &path))? {
+        Status::Success => Ok(0),
+        error => Err(error),
+    }
+}
+
+fn sys_open(args: &[u64; 5]) -> Result<u64, Status> {
+    let path = user_path(args[0], args[1])?;
+    let id = filesystemref()
+        .open(Identifier::Name(path))
+        .and_then(|fd| fd.id())
+        .ok_or(Status::NotFound)?;
+    let fd = current(|process| process.files().open(id))?.ok_or(Status::FailedToRead)?;
+    Ok(fd as u64)
+}
+
+fn sys_close(args: &[u64; 5]) -> Result<u64, Status> {
+    current(|process| process.files().close(args[0] as usize))?.ok_or(Status::NotFound)?;
+    Ok(0)
+}
+
+fn sys_read(args: &[u64; 5]) -> Result<u64, Status> {
+    let fd = args[0] as usize;
+    let file = current(|process| process.files().get(fd))?.ok_or(Status::NotFound)?;
+    let data = filesystemref().read(file.id).ok_or(Status::WrongType)?;
+
+    let rest = data.get(file.offset..).unwrap_or(&[]);
+    let count = rest.len().min(args[2] as usize);
+    copy_out(args[1], count as u64, &rest[..count])?;
+    current(|process| process.files().seek(fd, file.offset + count))?;
+    Ok(count as u64)
+}
+
+fn sys_wait(args: &[u64; 5]) -> Result<u64, Status> {
+    let parent = current(|process| process.pid())?;
+    let pid = process::list()
+        .into_iter()
+        .find(|info| info.pid.as_u64() == args[0] && info.parent == Some(parent))
+        .ok_or(Status::NotFound)?
+        .pid;
+    let status = process::wait(pid).ok_or(Status::NotFound)?;
+    Ok(status as u64)
+}
+
+fn sys_fork(frame: &SwitchFrame) -> Result<u64, Status> {
+    let pid = process::fork(frame).map_err(|_| Status::FailedToWrite)?;
+    Ok(pid.as_u64())
+}
@@ -36,16 +36,28 @@ pub fn halt_wr() {
     x86_64::instructions::hlt();
 }
 
-/// The `init()` function initiates the x86 CPU's GDT, IDT and PIC in order.
+/// The `init()` function initiates the x86 CPU's GDT, IDT, PIT and PIC in order, then claims the
+/// keyboard IRQ and drives the task timers from the timer IRQ.
 pub fn init() {
     kernel::gdt::init();
     kernel::interrupts::idt::init();
+    kernel::interrupts::pit::init();
     kernel::interrupts::pic::init();
+    // both stay registered for good.
+    let _ = kernel::task::keyboard::init();
+    let _ = kernel::task::timer::init();
 }
 
-/// The `mem_init` function initiates memory, heap, and the global allocator. Returns a Memory item struct.
-pub fn mem_init(boot_info: &'static BootInfo) -> kernel::mem::MemoryItems {
-    kernel::mem::mem_init(boot_info)
+/// The `mem_init` function initiates memory, heap, and the global allocator, switches interrupt
+/// delivery to the APICs if there are any, starts the nanosecond clock, then moves the interrupt
+/// stacks onto guard paged stacks and starts thread scheduling.
+pub fn mem_init(boot_info: &'static BootInfo) {
+    kernel::mem::mem_init(boot_info);
+    // without APICs the PICs stay in charge.
+    let _ = kernel::interrupts::apic::init();
+    kernel::clock::init();
+    kernel::gdt::init_stacks();
+    kernel::thread::init();
 }
 
 /// Teastable trait, trait to run code tests
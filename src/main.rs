@@ -7,7 +7,7 @@
 extern crate alloc;
 
 use flario::kernel::task::executor::Executor;
-use flario::kernel::task::Task;
+use flario::kernel::task::{Priority, Task};
 use flario::*;
 // Defines entry point for the bootloader, bootloader defines_start function.
 entry_point!(main);
@@ -15,11 +15,12 @@ entry_point!(main);
 /// The main entry point of the Flario kernel.
 fn main(boot_info: &'static BootInfo) -> ! {
     init();
-    let _mem_items = mem_init(boot_info);
+    mem_init(boot_info);
 
     let mut exe = Executor::new();
-    exe.spawn(Task::new(welcome()));
-    exe.spawn(Task::new(shell::main::shell()));
+    exe.spawn(Task::new("welcome", welcome()));
+    // the shell handles the keyboard, it comes first.
+    exe.spawn(Task::new("shell", shell::main::shell()).with_priority(Priority::Io));
     exe.run();
 }
 
@@ -27,7 +28,9 @@ fn main(boot_info: &'static BootInfo) -> ! {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    vs_println!("{}", info);
+    kernel::task::isolate::recover(info);
+    // the panicking code may hold the output, e.g. heap corruption found freeing inside a print.
+    drivers::io::try_print(format_args!("{}\n", info));
     halt();
 }
 
@@ -10,11 +10,13 @@ pub struct Command {
 }
 
 impl Command {
-    pub fn execute(self) -> Status {
-        self.run()
+    pub async fn execute(self) -> Status {
+        self.run().await
     }
 
-    pub fn run(self) -> Status {
+    /// Runs the program. Programs that wait, such as `exec`, are awaited so the other tasks keep
+    /// running.
+    pub async fn run(self) -> Status {
         match self.arg_zero {
             ArgZero::Help => super::programs::help::main(self.args),
             ArgZero::About => super::programs::about::main(self.args),
@@ -29,7 +31,13 @@ impl Command {
             ArgZero::Logo => super::programs::logo::main(self.args),
             ArgZero::NotFound => super::programs::not_found::main(self.args),
             ArgZero::Time => super::programs::time::main(self.args),
+            ArgZero::Date => super::programs::date::main(self.args),
+            ArgZero::Uptime => super::programs::uptime::main(self.args),
             ArgZero::Cd => super::programs::cd::main(self.args),
+            ArgZero::Meminfo => super::programs::meminfo::main(self.args),
+            ArgZero::Exec => super::programs::exec::main(self.args).await,
+            ArgZero::Ps => super::programs::ps::main(self.args),
+            ArgZero::Tasks => super::programs::tasks::main(self.args),
         }
     }
 }
@@ -57,7 +65,13 @@ pub enum ArgZero {
     Logo,
     NotFound,
     Time,
+    Date,
+    Uptime,
     Cd,
+    Meminfo,
+    Exec,
+    Ps,
+    Tasks,
 }
 
 impl core::fmt::Display for ArgZero {
@@ -79,7 +93,13 @@ impl core::fmt::Display for ArgZero {
                 ArgZero::Env => "env",
                 ArgZero::NotFound => "not found",
                 ArgZero::Time => "time",
+                ArgZero::Date => "date",
+                ArgZero::Uptime => "uptime",
                 ArgZero::Cd => "cd",
+                ArgZero::Meminfo => "meminfo",
+                ArgZero::Exec => "exec",
+                ArgZero::Ps => "ps",
+                ArgZero::Tasks => "tasks",
             }
         )
     }
@@ -102,7 +122,13 @@ impl From<String> for ArgZero {
             "logo" => ArgZero::Logo,
             "env" => ArgZero::Env,
             "time" => ArgZero::Time,
+            "date" => ArgZero::Date,
+            "uptime" => ArgZero::Uptime,
             "cd" => ArgZero::Cd,
+            "meminfo" => ArgZero::Meminfo,
+            "exec" => ArgZero::Exec,
+            "ps" => ArgZero::Ps,
+            "tasks" => ArgZero::Tasks,
             _ => ArgZero::NotFound,
         }
     }
@@ -128,7 +128,7 @@ impl Shell {
     }
 
     pub async fn exe(&mut self, cmd: Command) {
-        self.code = cmd.execute();
+        self.code = cmd.execute().await;
     }
 }
 
@@ -0,0 +1,14 @@
+crate::include_lib!(std, io, time);
+
+pub fn main(_: Vec<String>) -> Status {
+    match rtc::read() {
+        Some(now) => {
+            vga_println!("{} UTC", now);
+            Status::Success
+        }
+        None => {
+            vga_println!("date: the real-time clock holds no valid date");
+            Status::FailedToRead
+        }
+    }
+}
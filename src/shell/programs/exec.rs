@@ -0,0 +1,40 @@
+crate::include_lib!(std, io, user);
+
+pub async fn main(args: Vec<String>) -> Status {
+    if args.is_empty() {
+        vga_println!("Usage: exec <program | path> [args...]");
+        vga_print!("Programs:");
+        for name in programs::names() {
+            vga_print!(" {}", name);
+        }
+        vga_println!();
+        return Status::FailedToRead;
+    }
+
+    let name = args[0].to_string();
+    let rest: Vec<&str> = args[1..].iter().map(|arg| arg.as_str()).collect();
+    let started = match programs::find(&name) {
+        Some((builtin, code)) => {
+            let mut argv = Vec::from([builtin]);
+            argv.extend(rest);
+            process::create(builtin, &Image::flat(code), &argv)
+        }
+        None => exec(&name, &rest),
+    };
+
+    match started {
+        Ok(pid) => {
+            let status = process::wait_async(pid).await.unwrap_or(Status::NotFound);
+            vga_println!("{} (pid {}) exited with {:?}", name, pid, status);
+            status
+        }
+        Err(UserError::NotFound) => {
+            vga_println!("exec: no program or file named {}", name);
+            Status::NotFound
+        }
+        Err(error) => {
+            vga_println!("exec: failed to start {}: {:?}", name, error);
+            Status::FailedToRead
+        }
+    }
+}
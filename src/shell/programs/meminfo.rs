@@ -0,0 +1,49 @@
+crate::include_lib!(std, io, mem);
+
+pub fn main(_: Vec<String>) -> Status {
+    let stats = stats();
+    let heap = stats.heap;
+
+    vga_println!(
+        "heap: {} used, {} free, {} mapped (limit {})",
+        heap.heap_used,
+        heap.heap_free,
+        heap.heap_size,
+        heap_limit()
+    );
+
+    vga_println!("size\tallocs\tfrees\tlive\tslabs\tfree blocks");
+    for class in heap.classes.iter() {
+        vga_println!(
+            "{}\t{}\t{}\t{}\t{}\t{} ({} bytes)",
+            class.block_size,
+            class.allocs,
+            class.frees,
+            class.live(),
+            class.slabs,
+            class.free_blocks,
+            class.free_bytes()
+        );
+    }
+    vga_println!(
+        "large\t{}\t{}\t{}\t({} bytes live)",
+        heap.large_allocs,
+        heap.large_frees,
+        heap.large_allocs - heap.large_frees,
+        heap.large_bytes
+    );
+
+    match stats.frames {
+        Some(frames) => vga_println!(
+            "frames: {} used, {} free, {} total ({} KiB / {} KiB)",
+            frames.used,
+            frames.free,
+            frames.total,
+            FrameStats::kib(frames.used),
+            FrameStats::kib(frames.total)
+        ),
+        None => vga_println!("frames: not initialized"),
+    }
+
+    Status::Success
+}
@@ -1,13 +1,19 @@
 pub mod about;
 pub mod clear;
+pub mod date;
 pub mod env;
+pub mod exec;
 pub mod help;
 pub mod logo;
 pub mod ls;
+pub mod meminfo;
 pub mod mkdir;
 pub mod mkfile;
 pub mod not_found;
+pub mod ps;
+pub mod tasks;
 pub mod time;
+pub mod uptime;
 //pub mod read;
 //pub mod rmdir;
 pub mod cd;
@@ -28,6 +34,7 @@ mod includes {
         pub use crate::shell::programs::includes::stat::{Status, Termination};
         pub use crate::shell::programs::includes::str::{String, ToString};
         pub use crate::shell::programs::includes::vec::Vec;
+        pub use alloc::format;
     }
 
     pub mod stat {
@@ -35,7 +42,7 @@ mod includes {
     }
 
     pub mod fs {
-        pub use crate::kernel::fs::{FileSystem, Inode, FileSyetemRef, filesystemref, Identifier};
+        pub use crate::kernel::fs::{filesystemref, FileSyetemRef, FileSystem, Identifier, Inode};
     }
 
     pub mod vec {
@@ -51,10 +58,26 @@ mod includes {
     }
 
     pub mod time {
+        pub use crate::drivers::rtc;
         pub use crate::kernel::sc::{Instant, SYSTEM_CLOCK};
     }
 
+    pub mod mem {
+        pub use crate::kernel::mem::globalloc::heap::heap_limit;
+        pub use crate::kernel::mem::{stats, FrameStats, MemoryStats};
+    }
+
+    pub mod user {
+        pub use crate::kernel::process::{self, State};
+        pub use crate::kernel::user::image::Image;
+        pub use crate::kernel::user::{exec, programs, UserError};
+    }
+
+    pub mod task {
+        pub use crate::kernel::task;
+    }
+
     pub mod env {
-        pub use crate::kernel::environ::{Key, EnvironmentRef, environmentref};
+        pub use crate::kernel::environ::{environmentref, EnvironmentRef, Key};
     }
 }
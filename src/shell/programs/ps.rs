@@ -0,0 +1,24 @@
+crate::include_lib!(std, io, user);
+
+pub fn main(_: Vec<String>) -> Status {
+    vga_println!("pid\tparent\tfiles\tstate\tname");
+    for info in process::list() {
+        let parent = match info.parent {
+            Some(parent) => parent.to_string(),
+            None => "-".to_string(),
+        };
+        let state = match info.state {
+            State::Running => "running".to_string(),
+            State::Exited(status) => format!("exited ({:?})", status),
+        };
+        vga_println!(
+            "{}\t{}\t{}\t{}\t{}",
+            info.pid,
+            parent,
+            info.files,
+            state,
+            info.name
+        );
+    }
+    Status::Success
+}
@@ -0,0 +1,9 @@
+crate::include_lib!(std, io, task);
+
+pub fn main(_: Vec<String>) -> Status {
+    vga_println!("id\tpriority\tname");
+    for info in task::list() {
+        vga_println!("{}\t{}\t{}", info.id, info.priority, info.name);
+    }
+    Status::Success
+}
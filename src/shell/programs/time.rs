@@ -2,8 +2,14 @@ crate::include_lib!(std, io, time);
 
 pub fn main(_: Vec<String>) -> Status {
     let now = Instant::now();
-    let time = now.hours();
+    let seconds = now.duration_since(Instant::zero()).as_secs();
 
-    vga_println!("{}:{}:{}\t{}", time.2, time.1, time.0, now);
+    vga_println!(
+        "{}:{}:{}\t{}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        now
+    );
     Status::Success
 }
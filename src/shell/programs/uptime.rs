@@ -0,0 +1,15 @@
+crate::include_lib!(std, io, time);
+
+pub fn main(_: Vec<String>) -> Status {
+    let seconds = SYSTEM_CLOCK.uptime().as_secs();
+
+    vga_println!(
+        "up {}:{:02}:{:02}, {} ticks at {:.2} Hz",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        SYSTEM_CLOCK.ticks(),
+        SYSTEM_CLOCK.rate()
+    );
+    Status::Success
+}
@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use flario::kernel::acpi;
+use flario::kernel::interrupts::{apic, irq};
+use flario::kernel::thread;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+#[test_case]
+fn madt_lists_interrupt_controllers() {
+    let madt = acpi::madt().expect("no MADT");
+    assert!(madt.local_apic.as_u64() != 0);
+    assert!(!madt.io_apics.is_empty());
+}
+
+#[test_case]
+fn missing_tables_are_not_found() {
+    assert_eq!(acpi::find_table(b"NONE"), Err(acpi::AcpiError::NotFound));
+}
+
+#[test_case]
+fn apic_replaces_pic() {
+    assert!(apic::is_enabled());
+}
+
+#[test_case]
+fn legacy_irqs_keep_their_vectors() {
+    assert_eq!(apic::irq_vector(0), Some(32));
+    assert_eq!(apic::irq_vector(1), Some(33));
+    // lines without a handler stay masked.
+    assert_eq!(apic::irq_vector(irq::MOUSE), None);
+}
+
+#[test_case]
+fn timer_interrupts_are_acknowledged() {
+    // the timer only fires again after the end of interrupt reached the right controller.
+    let start = thread::ticks();
+    while thread::ticks() < start + 5 {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
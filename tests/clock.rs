@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use flario::kernel::clock;
+use flario::kernel::interrupts::pit;
+use flario::kernel::sc::Instant;
+use flario::kernel::thread;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+#[test_case]
+fn started_at_boot() {
+    assert!(clock::source().is_some());
+    // at least 1 MHz, so the clock resolves microseconds.
+    assert!(clock::frequency().unwrap() >= 1_000_000);
+}
+
+#[test_case]
+fn never_goes_back() {
+    let mut last = Instant::now();
+    for _ in 0..10_000 {
+        let now = Instant::now();
+        assert!(now >= last);
+        last = now;
+    }
+}
+
+#[test_case]
+fn measures_the_pit() {
+    // 10 ms on the PIT stopwatch.
+    let start = Instant::now();
+    pit::wait((pit::BASE_FREQUENCY / 100) as u16);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(9), "{:?}", elapsed);
+    assert!(elapsed <= Duration::from_millis(20), "{:?}", elapsed);
+}
+
+#[test_case]
+fn agrees_with_the_timer() {
+    let ticks = 10;
+    let start = Instant::now();
+    let first = thread::ticks();
+    while thread::ticks() < first + ticks {
+        x86_64::instructions::hlt();
+    }
+    // the first tick may come right away.
+    let expected = Duration::from_secs(ticks - 1) / pit::frequency();
+    assert!(start.elapsed() >= expected);
+}
+
+#[test_case]
+fn duration_arithmetic() {
+    let start = Instant::zero();
+    let later = start + Duration::from_millis(1500);
+    assert_eq!(later - start, Duration::from_millis(1500));
+    assert_eq!(start - later, Duration::ZERO);
+    assert_eq!(
+        later - Duration::from_millis(500),
+        start + Duration::from_secs(1)
+    );
+    assert_eq!(start.checked_sub(Duration::from_nanos(1)), None);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use flario::kernel::mem::region::{self, Region, RegionError};
+use flario::kernel::mem::space::AddressSpace;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+const TEST_START: u64 = 0x_5555_0000_0000;
+const TEST_SIZE: u64 = 4 * 4096;
+
+#[test_case]
+fn demand_region_is_backed_on_access() {
+    let start = VirtAddr::new(TEST_START);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    region::reserve(Region::demand("test", start, TEST_SIZE, flags)).unwrap();
+
+    let frames = flario::kernel::mem::stats().frames.unwrap().used;
+    for page in 0..TEST_SIZE / 4096 {
+        let ptr = (start + page * 4096 + 8u64).as_mut_ptr::<u64>();
+        unsafe {
+            assert_eq!(ptr.read_volatile(), 0);
+            ptr.write_volatile(page);
+            assert_eq!(ptr.read_volatile(), page);
+        }
+    }
+    assert!(flario::kernel::mem::stats().frames.unwrap().used > frames);
+}
+
+#[test_case]
+fn overlapping_regions_are_rejected() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let region = Region::new("overlap", VirtAddr::new(TEST_START + 4096), 4096, flags);
+    assert_eq!(region::reserve(region), Err(RegionError::Overlap));
+    assert_eq!(
+        region::find(VirtAddr::new(TEST_START)).map(|r| r.name),
+        Some("test")
+    );
+}
+
+#[test_case]
+fn later_kernel_entries_reach_older_spaces() {
+    let space = AddressSpace::new().unwrap();
+    // a level 4 entry of its own, which the kernel creates only after the space.
+    let start = VirtAddr::new(0x_5800_0000_0000);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    region::reserve(Region::demand("late", start, 4096, flags)).unwrap();
+
+    let ptr = start.as_mut_ptr::<u64>();
+    without_interrupts(|| {
+        let (kernel, cr3_flags) = Cr3::read();
+        unsafe {
+            Cr3::write(space.page_table(), cr3_flags);
+            ptr.write_volatile(7);
+            Cr3::write(kernel, cr3_flags);
+        }
+    });
+    assert_eq!(unsafe { ptr.read_volatile() }, 7);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
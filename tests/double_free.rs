@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use flario::{drivers::qemu, vs_print, vs_println};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    vs_print!("double_free...\t");
+    flario::init();
+    flario::mem_init(boot_info);
+
+    unsafe {
+        let layout = Layout::new::<u64>();
+        let ptr = alloc(layout);
+        dealloc(ptr, layout);
+        dealloc(ptr, layout);
+    }
+
+    vs_println!("[FAILED]");
+    qemu::exit_qemu(qemu::QemuExitCode::Failed);
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    vs_println!("[OK]");
+    qemu::exit_qemu(qemu::QemuExitCode::Success);
+}
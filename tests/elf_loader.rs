@@ -0,0 +1,218 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use flario::kernel::fs::filesystemref;
+use flario::kernel::process;
+use flario::kernel::status::Status;
+use flario::kernel::user::elf::{self, ElfError};
+use flario::kernel::user::{self, USER_START};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+/// Defines `$name()`, returning the code assembled from `$line`s.
+macro_rules! program {
+    ($name:ident, $start:literal, $end:literal, [$($line:literal),* $(,)?]) => {
+        global_asm!(
+            concat!(".global ", $start),
+            concat!(".global ", $end),
+            concat!($start, ":"),
+            $($line,)*
+            concat!($end, ":"),
+        );
+
+        fn $name() -> &'static [u8] {
+            extern "C" {
+                #[link_name = $start]
+                static START: u8;
+                #[link_name = $end]
+                static END: u8;
+            }
+            unsafe {
+                let start = &START as *const u8;
+                let len = &END as *const u8 as usize - start as usize;
+                core::slice::from_raw_parts(start, len)
+            }
+        }
+    };
+}
+
+// exits with argc as passed in rdi.
+program!(
+    exit_argc,
+    "test_exit_argc_start",
+    "test_exit_argc_end",
+    ["xor eax, eax", "int 0x80", "ud2"]
+);
+
+// exits with argc as found on the stack.
+program!(
+    exit_stack_argc,
+    "test_exit_stack_argc_start",
+    "test_exit_stack_argc_end",
+    ["mov rdi, [rsp]", "xor eax, eax", "int 0x80", "ud2"]
+);
+
+// exits with zero if argv[1] starts with '*'.
+program!(
+    exit_first_arg,
+    "test_exit_first_arg_start",
+    "test_exit_first_arg_end",
+    [
+        "mov rdi, [rsi + 8]",
+        "movzx edi, byte ptr [rdi]",
+        "sub edi, '*'",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+    ]
+);
+
+// exits with zero if envp[0] starts with 'c', as in "cwd=/".
+program!(
+    exit_first_env,
+    "test_exit_first_env_start",
+    "test_exit_first_env_end",
+    [
+        "mov rdi, [rdx]",
+        "movzx edi, byte ptr [rdi]",
+        "sub edi, 'c'",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+    ]
+);
+
+// writes to its own code.
+program!(
+    write_text,
+    "test_write_text_start",
+    "test_write_text_end",
+    ["lea rax, [rip]", "mov byte ptr [rax], 0", "ud2"]
+);
+
+/// Size of the ELF header and the single program header `executable` writes.
+const HEADERS: u64 = 64 + 56;
+
+/// A minimal executable with `code` as its only segment, loaded right after the headers at
+/// `base` and started from its first byte.
+fn executable_at(base: u64, code: &[u8]) -> Vec<u8> {
+    let mut file = Vec::new();
+    file.extend_from_slice(b"\x7fELF");
+    file.extend_from_slice(&[2, 1, 1, 0]);
+    file.extend_from_slice(&[0; 8]);
+    file.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    file.extend_from_slice(&0x3eu16.to_le_bytes()); // x86_64
+    file.extend_from_slice(&1u32.to_le_bytes());
+    file.extend_from_slice(&(base + HEADERS).to_le_bytes()); // entry
+    file.extend_from_slice(&64u64.to_le_bytes()); // program headers
+    file.extend_from_slice(&0u64.to_le_bytes()); // section headers
+    file.extend_from_slice(&0u32.to_le_bytes());
+    file.extend_from_slice(&64u16.to_le_bytes());
+    file.extend_from_slice(&56u16.to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&[0; 6]);
+
+    file.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+    file.extend_from_slice(&5u32.to_le_bytes()); // readable, executable
+    file.extend_from_slice(&HEADERS.to_le_bytes());
+    file.extend_from_slice(&(base + HEADERS).to_le_bytes());
+    file.extend_from_slice(&(base + HEADERS).to_le_bytes());
+    file.extend_from_slice(&(code.len() as u64).to_le_bytes());
+    file.extend_from_slice(&(code.len() as u64).to_le_bytes());
+    file.extend_from_slice(&4096u64.to_le_bytes());
+
+    file.extend_from_slice(code);
+    file
+}
+
+fn executable(code: &[u8]) -> Vec<u8> {
+    executable_at(USER_START, code)
+}
+
+/// Runs `code` as an executable and returns the code of its exit status.
+fn run(code: &[u8], args: &[&str]) -> u64 {
+    let file = executable(code);
+    let image = elf::parse(&file).unwrap();
+    let pid = process::create("elf test", &image, args).unwrap();
+    process::wait(pid).unwrap() as u64
+}
+
+#[test_case]
+fn arguments_are_passed_in_registers() {
+    assert_eq!(run(exit_argc(), &["prog", "a", "b"]), 3);
+}
+
+#[test_case]
+fn arguments_are_passed_on_the_stack() {
+    assert_eq!(run(exit_stack_argc(), &["prog", "a"]), 2);
+}
+
+#[test_case]
+fn argument_strings_are_copied() {
+    assert_eq!(run(exit_first_arg(), &["prog", "*"]), 0);
+}
+
+#[test_case]
+fn environment_strings_are_copied() {
+    assert_eq!(run(exit_first_env(), &["prog"]), 0);
+}
+
+#[test_case]
+fn code_is_read_only() {
+    assert_eq!(run(write_text(), &[]), Status::Killed as u64);
+}
+
+#[test_case]
+fn invalid_files_are_rejected() {
+    assert_eq!(elf::parse(b"#!/bin/sh").unwrap_err(), ElfError::Truncated);
+    assert_eq!(elf::parse(&[0; 128]).unwrap_err(), ElfError::NotElf);
+
+    let mut file = executable(exit_argc());
+    file[16] = 3; // ET_DYN
+    assert_eq!(elf::parse(&file).unwrap_err(), ElfError::NotExecutable);
+
+    let file = executable_at(0x40_0000, exit_argc());
+    assert_eq!(elf::parse(&file).unwrap_err(), ElfError::BadSegment);
+
+    let mut file = executable(exit_argc());
+    file.truncate(file.len() - 1);
+    assert_eq!(elf::parse(&file).unwrap_err(), ElfError::Truncated);
+}
+
+#[test_case]
+fn executables_run_from_the_filesystem() {
+    let fs = filesystemref();
+    let id = fs.create_file("/argc").unwrap().id().unwrap();
+    fs.write(id, &executable(exit_argc())).unwrap();
+
+    // the path is the first argument.
+    let pid = user::exec("/argc", &["a", "b"]).unwrap();
+    assert_eq!(process::wait(pid), Some(Status::FailedToWrite));
+    assert!(matches!(
+        user::exec("/missing", &[]),
+        Err(user::UserError::NotFound)
+    ));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
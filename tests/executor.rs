@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use flario::kernel::task::executor::Executor;
+use flario::kernel::task::{self, timer};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+#[test_case]
+fn block_on_returns_the_output() {
+    assert_eq!(Executor::new().block_on(async { 42 }), Ok(42));
+}
+
+#[test_case]
+fn join_handles_yield_task_output() {
+    let output = Executor::new().block_on(async { task::spawn("child", async { 7 }).await });
+    assert_eq!(output, Ok(Ok(7)));
+}
+
+#[test_case]
+fn tasks_spawn_tasks() {
+    let sum = Executor::new().block_on(async {
+        let handles: Vec<_> = (0..10u64)
+            .map(|i| {
+                task::spawn("square", async move {
+                    // nested spawns reach the same executor.
+                    task::spawn("inner", async move { i * i }).await.unwrap()
+                })
+            })
+            .collect();
+        let mut sum = 0;
+        for handle in handles {
+            sum += handle.await.unwrap();
+        }
+        sum
+    });
+    assert_eq!(sum, Ok(285));
+}
+
+#[test_case]
+fn tasks_are_listed_by_name() {
+    Executor::new()
+        .block_on(async {
+            let sleeper = task::spawn("sleeper", timer::sleep(Duration::from_millis(20)));
+            let names: Vec<_> = task::list().iter().map(|info| info.name).collect();
+            assert!(names.contains(&"sleeper"));
+            assert!(names.contains(&"block_on"));
+            assert_eq!(sleeper.name(), "sleeper");
+
+            sleeper.await.unwrap();
+            assert!(task::list().iter().all(|info| info.name != "sleeper"));
+        })
+        .unwrap();
+}
+
+#[test_case]
+fn dropped_handles_detach() {
+    let done = Arc::new(AtomicBool::new(false));
+    let flag = done.clone();
+    Executor::new()
+        .block_on(async move {
+            drop(task::spawn("detached", async move {
+                timer::sleep(Duration::from_millis(10)).await;
+                flag.store(true, Ordering::SeqCst);
+            }));
+            timer::sleep(Duration::from_millis(50)).await;
+        })
+        .unwrap();
+    assert!(done.load(Ordering::SeqCst));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use flario::kernel::mem::region::{self, Region};
+use flario::kernel::mem::{space, vmm};
+use flario::{drivers::qemu, vs_print, vs_println};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/// Each case faults with the VMM lock held, which must panic instead of hanging. The panic handler
+/// runs the next one.
+const CASES: [(&str, fn()); 2] = [
+    ("demand paging", demand_page),
+    ("copy on write", copy_on_write),
+];
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    vs_print!("fault_in_vmm...\t");
+    flario::init();
+    flario::mem_init(boot_info);
+    run_next()
+}
+
+fn run_next() -> ! {
+    match CASES.get(NEXT.fetch_add(1, Ordering::SeqCst)) {
+        Some((name, case)) => {
+            case();
+            vs_println!("[FAILED] {} did not panic", name);
+            qemu::exit_qemu(qemu::QemuExitCode::Failed);
+        }
+        None => {
+            vs_println!("[OK]");
+            qemu::exit_qemu(qemu::QemuExitCode::Success);
+        }
+    }
+}
+
+/// Takes the VMM lock, which a case that panicked before still holds.
+fn hold_vmm() {
+    if !vmm::is_locked() {
+        core::mem::forget(vmm::vmm().unwrap());
+    }
+}
+
+fn demand_page() {
+    let start = VirtAddr::new(0x_5555_0000_0000);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    region::reserve(Region::demand("locked test", start, 4096, flags)).unwrap();
+
+    hold_vmm();
+    unsafe { start.as_mut_ptr::<u64>().write_volatile(1) };
+}
+
+fn copy_on_write() {
+    hold_vmm();
+    // what the page fault handler calls on a write to a shared user page.
+    let _ = space::copy_on_write(VirtAddr::new(space::USER_START));
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    run_next()
+}
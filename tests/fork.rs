@@ -0,0 +1,182 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use flario::kernel::process;
+use flario::kernel::status::Status;
+use flario::kernel::user;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+/// Defines `$name()`, returning the code assembled from `$line`s.
+macro_rules! program {
+    ($name:ident, $start:literal, $end:literal, [$($line:literal),* $(,)?]) => {
+        global_asm!(
+            concat!(".global ", $start),
+            concat!(".global ", $end),
+            concat!($start, ":"),
+            $($line,)*
+            concat!($end, ":"),
+        );
+
+        fn $name() -> &'static [u8] {
+            extern "C" {
+                #[link_name = $start]
+                static START: u8;
+                #[link_name = $end]
+                static END: u8;
+            }
+            unsafe {
+                let start = &START as *const u8;
+                let len = &END as *const u8 as usize - start as usize;
+                core::slice::from_raw_parts(start, len)
+            }
+        }
+    };
+}
+
+// forks, the child changes a stack slot and exits with it, the parent exits with zero if the child
+// exited with 2 and its own slot still holds 1.
+program!(
+    fork_memory,
+    "test_fork_memory_start",
+    "test_fork_memory_end",
+    [
+        "sub rsp, 16",
+        "mov qword ptr [rsp], 1",
+        "mov eax, 16",
+        "int 0x80",
+        "test rax, rax",
+        "jnz 2f",
+        "mov qword ptr [rsp], 2",
+        "mov rdi, [rsp]",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+        "2:",
+        "mov rdi, rax",
+        "mov eax, 15",
+        "int 0x80",
+        "lea rdi, [rax - 2]",
+        "add rdi, [rsp]",
+        "sub rdi, 1",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+    ]
+);
+
+// forks, the child has getcwd write to the shared stack and exits with zero if it reads back "/",
+// the parent exits with zero if the child did and its own buffer is unchanged.
+program!(
+    fork_syscall_write,
+    "test_fork_syscall_write_start",
+    "test_fork_syscall_write_end",
+    [
+        "sub rsp, 64",
+        "mov byte ptr [rsp], 'x'",
+        "mov eax, 16",
+        "int 0x80",
+        "test rax, rax",
+        "jnz 2f",
+        "mov eax, 6",
+        "mov rdi, rsp",
+        "mov esi, 64",
+        "int 0x80",
+        "movzx edi, byte ptr [rsp]",
+        "sub edi, '/'",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+        "2:",
+        "mov rdi, rax",
+        "mov eax, 15",
+        "int 0x80",
+        "movzx edi, byte ptr [rsp]",
+        "sub edi, 'x'",
+        "add rdi, rax",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+    ]
+);
+
+// exits with the negated result of waiting for a process that is not its child.
+program!(
+    wait_stranger,
+    "test_wait_stranger_start",
+    "test_wait_stranger_end",
+    [
+        "mov eax, 15",
+        "xor edi, edi",
+        "int 0x80",
+        "mov rdi, rax",
+        "neg rdi",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+    ]
+);
+
+fn free_frames() -> usize {
+    flario::kernel::mem::stats().frames.unwrap().free
+}
+
+fn run(name: &str, code: &[u8]) -> Status {
+    process::wait(user::spawn(name, code).unwrap()).unwrap()
+}
+
+#[test_case]
+fn child_writes_are_private() {
+    assert_eq!(run("fork memory", fork_memory()), Status::Success);
+}
+
+#[test_case]
+fn syscall_writes_copy_shared_pages() {
+    assert_eq!(
+        run("fork syscall write", fork_syscall_write()),
+        Status::Success
+    );
+}
+
+#[test_case]
+fn waited_children_leave_the_table() {
+    run("fork memory", fork_memory());
+    assert!(process::list()
+        .iter()
+        .all(|info| info.name != "fork memory"));
+}
+
+#[test_case]
+fn only_children_can_be_waited_for() {
+    assert_eq!(run("wait stranger", wait_stranger()), Status::NotFound);
+}
+
+#[test_case]
+fn shared_frames_are_freed() {
+    let free = free_frames();
+    for _ in 0..10 {
+        run("fork memory", fork_memory());
+    }
+    // page tables created for the first program stay around.
+    assert!(free_frames() + 8 >= free);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
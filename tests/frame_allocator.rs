@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use flario::kernel::mem::vmm::VmmGuard;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+fn vmm() -> VmmGuard {
+    flario::kernel::mem::vmm::vmm().expect("memory not initialized")
+}
+
+#[test_case]
+fn allocate_and_free_frame() {
+    let mut vmm = vmm();
+    let allocator = vmm.frame_allocator();
+    let free = allocator.free_frames();
+
+    let frame = allocator.allocate_frame().expect("out of frames");
+    assert!(allocator.is_used(frame));
+    assert_eq!(allocator.free_frames(), free - 1);
+
+    unsafe { allocator.deallocate_frame(frame) };
+    assert!(!allocator.is_used(frame));
+    assert_eq!(allocator.free_frames(), free);
+}
+
+#[test_case]
+fn freed_frame_is_reused() {
+    let mut vmm = vmm();
+    let allocator = vmm.frame_allocator();
+
+    let frame = allocator.allocate_frame().expect("out of frames");
+    unsafe { allocator.deallocate_frame(frame) };
+    let again = allocator.allocate_frame().expect("out of frames");
+    assert_eq!(frame, again);
+    unsafe { allocator.deallocate_frame(again) };
+}
+
+#[test_case]
+fn shared_frame_is_freed_by_last_reference() {
+    let mut vmm = vmm();
+    let allocator = vmm.frame_allocator();
+    let free = allocator.free_frames();
+
+    let frame = allocator.allocate_frame().expect("out of frames");
+    assert_eq!(allocator.references(frame), 1);
+    allocator.share(frame);
+    assert_eq!(allocator.references(frame), 2);
+
+    unsafe { allocator.deallocate_frame(frame) };
+    assert!(allocator.is_used(frame));
+    assert_eq!(allocator.free_frames(), free - 1);
+
+    unsafe { allocator.deallocate_frame(frame) };
+    assert!(!allocator.is_used(frame));
+    assert_eq!(allocator.references(frame), 0);
+    assert_eq!(allocator.free_frames(), free);
+}
+
+#[test_case]
+fn allocate_contiguous_frames() {
+    let mut vmm = vmm();
+    let allocator = vmm.frame_allocator();
+    let free = allocator.free_frames();
+
+    let range = allocator
+        .allocate_contiguous(16)
+        .expect("no contiguous run");
+    assert_eq!(range.end - range.start, 16);
+    for frame in range {
+        assert!(allocator.is_used(frame));
+    }
+    assert_eq!(allocator.free_frames(), free - 16);
+
+    unsafe { allocator.deallocate_contiguous(range) };
+    assert_eq!(allocator.free_frames(), free);
+}
+
+#[test_case]
+fn frame_accounting() {
+    let mut vmm = vmm();
+    let allocator = vmm.frame_allocator();
+    let (total, used, free) = (
+        allocator.total_frames(),
+        allocator.used_frames(),
+        allocator.free_frames(),
+    );
+
+    // a fixed array, the heap must not be touched while the manager is locked.
+    let mut frames = [None; 8];
+    for frame in frames.iter_mut() {
+        *frame = allocator.allocate_frame();
+    }
+    assert_eq!(allocator.used_frames(), used + frames.len());
+    assert_eq!(allocator.free_frames(), free - frames.len());
+
+    for frame in frames.iter() {
+        unsafe { allocator.deallocate_frame(frame.expect("out of frames")) };
+    }
+    assert_eq!(allocator.used_frames(), used);
+    assert_eq!(allocator.free_frames(), free);
+    assert_eq!(allocator.total_frames(), total);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
@@ -0,0 +1,86 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use flario::kernel::mem::stack::{self, KernelStack};
+use flario::{drivers::qemu, vs_print, vs_println};
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    vs_print!("guard_overflow...\t");
+    flario::init();
+    flario::mem_init(boot_info);
+    init_test_idt();
+
+    let stack = KernelStack::allocate("overflow test", 4096).expect("stack allocation failed");
+    unsafe {
+        asm!(
+            "mov rsp, {top}",
+            "call {recurse}",
+            top = in(reg) stack.top().as_u64(),
+            recurse = sym stack_overflow,
+            options(noreturn)
+        );
+    }
+}
+
+#[allow(unconditional_recursion)]
+extern "C" fn stack_overflow() {
+    stack_overflow();
+    volatile::Volatile::new(0).read();
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(flario::kernel::gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
+
+fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+/// Only reached if the page fault frame could be pushed below the overflowed stack.
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: PageFaultErrorCode,
+) {
+    vs_println!("[FAILED] page fault handled on the overflowed stack");
+    qemu::exit_qemu(qemu::QemuExitCode::Failed);
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    use x86_64::registers::control::Cr2;
+
+    match stack::overflowed(Cr2::read()) {
+        Some("overflow test") => {
+            vs_println!("[OK]");
+            qemu::exit_qemu(qemu::QemuExitCode::Success);
+        }
+        other => {
+            vs_println!("[FAILED] overflowed stack {:?}", other);
+            qemu::exit_qemu(qemu::QemuExitCode::Failed);
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
@@ -10,14 +10,14 @@ use alloc::boxed::Box;
 use alloc::vec::Vec;
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use flario::kernel::mem::globalloc::heap::HEAP_SIZE;
+use flario::kernel::mem::globalloc::heap::{heap_limit, HEAP_SIZE};
 
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
     use flario::*;
     init();
-    let _mem_items = mem_init(boot_info);
+    mem_init(boot_info);
 
     test_main();
     halt();
@@ -59,6 +59,47 @@ fn many_boxes_long_lived() {
     assert_eq!(*ll, 1);
 }
 
+#[test_case]
+fn heap_grows_past_initial_size() {
+    let size = HEAP_SIZE * 4;
+    assert!(size < heap_limit());
+
+    let mut vec: Vec<u8> = Vec::with_capacity(size);
+    vec.resize(size, 0xab);
+    assert!(vec.iter().all(|b| *b == 0xab));
+}
+
+#[test_case]
+fn allocator_stats_track_blocks() {
+    use flario::kernel::mem::stats;
+
+    let before = stats().heap.classes[0];
+    let x = Box::new(7u64);
+    let during = stats().heap.classes[0];
+    assert_eq!(during.allocs, before.allocs + 1);
+    assert_eq!(during.live(), before.live() + 1);
+
+    drop(x);
+    let after = stats().heap.classes[0];
+    assert_eq!(after.frees, before.frees + 1);
+    assert_eq!(after.live(), before.live());
+    assert!(stats().frames.is_some());
+}
+
+#[test_case]
+fn empty_slabs_return_to_heap() {
+    use flario::kernel::mem::stats;
+
+    let before = stats().heap;
+    let boxes: Vec<Box<[u8; 64]>> = (0..1000).map(|_| Box::new([0u8; 64])).collect();
+    assert!(stats().heap.classes[3].slabs > before.classes[3].slabs);
+
+    drop(boxes);
+    let after = stats().heap;
+    assert!(after.classes[3].slabs <= before.classes[3].slabs + 1);
+    assert!(after.classes[3].reclaimed > before.classes[3].reclaimed);
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     flario::test_panic_handler(info)
@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use flario::kernel::interrupts::apic;
+use flario::kernel::interrupts::irq::{self, IrqError};
+use flario::kernel::thread;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+/// Waits for `ticks` timer interrupts.
+fn wait_ticks(ticks: u64) {
+    let start = thread::ticks();
+    while thread::ticks() < start + ticks {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn keyboard_is_claimed_at_init() {
+    assert!(irq::is_claimed(irq::KEYBOARD));
+    assert!(!irq::is_claimed(irq::MOUSE));
+}
+
+#[test_case]
+fn closures_run_on_their_line() {
+    let count = Arc::new(AtomicU64::new(0));
+    let handle = {
+        let count = count.clone();
+        irq::register(irq::TIMER, move || {
+            count.fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap()
+    };
+    wait_ticks(3);
+    assert!(count.load(Ordering::Relaxed) >= 2);
+
+    irq::unregister(handle);
+    let after = count.load(Ordering::Relaxed);
+    wait_ticks(3);
+    assert_eq!(count.load(Ordering::Relaxed), after);
+}
+
+#[test_case]
+fn functions_share_a_line() {
+    static FIRST: AtomicU64 = AtomicU64::new(0);
+    static SECOND: AtomicU64 = AtomicU64::new(0);
+
+    let first = irq::register_fn(irq::TIMER, || {
+        FIRST.fetch_add(1, Ordering::Relaxed);
+    })
+    .unwrap();
+    let second = irq::register_fn(irq::TIMER, || {
+        SECOND.fetch_add(1, Ordering::Relaxed);
+    })
+    .unwrap();
+    wait_ticks(3);
+    irq::unregister(first);
+    irq::unregister(second);
+
+    assert!(FIRST.load(Ordering::Relaxed) >= 2);
+    assert!(SECOND.load(Ordering::Relaxed) >= 2);
+}
+
+#[test_case]
+fn lines_are_unmasked_while_claimed() {
+    // COM2, edge triggered and quiet.
+    const LINE: u8 = 3;
+    assert_eq!(apic::irq_vector(LINE), None);
+
+    let handles: Vec<_> = (0..irq::MAX_SHARED)
+        .map(|_| irq::register_fn(LINE, || {}).unwrap())
+        .collect();
+    assert_eq!(apic::irq_vector(LINE), Some(irq::vector(LINE)));
+    assert_eq!(
+        irq::register_fn(LINE, || {}).unwrap_err(),
+        IrqError::LineFull
+    );
+
+    for handle in handles {
+        irq::unregister(handle);
+    }
+    assert!(!irq::is_claimed(LINE));
+    assert_eq!(apic::irq_vector(LINE), None);
+}
+
+#[test_case]
+fn invalid_lines_are_rejected() {
+    assert_eq!(
+        irq::register_fn(irq::IRQ_LINES, || {}).unwrap_err(),
+        IrqError::InvalidLine
+    );
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
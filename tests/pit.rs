@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use flario::kernel::interrupts::pit;
+use flario::kernel::sc::{Instant, SYSTEM_CLOCK};
+use flario::kernel::thread;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+#[test_case]
+fn programmed_at_boot() {
+    assert_eq!(pit::frequency(), pit::DEFAULT_FREQUENCY);
+    let rate = pit::DEFAULT_FREQUENCY as f32;
+    assert!((rate - 0.01..rate + 0.01).contains(&pit::rate()));
+}
+
+#[test_case]
+fn frequency_is_clamped() {
+    assert_eq!(pit::set_frequency(0), pit::MIN_FREQUENCY);
+    assert_eq!(pit::set_frequency(u32::MAX), pit::MAX_FREQUENCY);
+    assert_eq!(
+        pit::set_frequency(pit::DEFAULT_FREQUENCY),
+        pit::DEFAULT_FREQUENCY
+    );
+}
+
+#[test_case]
+fn clock_follows_the_frequency() {
+    assert_eq!(pit::set_frequency(50), 50);
+    let rate = SYSTEM_CLOCK.rate();
+    assert!((49.99..50.01).contains(&rate));
+
+    // the timer keeps firing at the new rate.
+    let start = thread::ticks();
+    while thread::ticks() < start + 3 {
+        x86_64::instructions::hlt();
+    }
+    pit::set_frequency(pit::DEFAULT_FREQUENCY);
+}
+
+#[test_case]
+fn uptime_survives_frequency_changes() {
+    let start = Instant::now();
+    let uptime = SYSTEM_CLOCK.uptime();
+    for hz in [50, 200, pit::DEFAULT_FREQUENCY].iter() {
+        pit::set_frequency(*hz);
+        let ticks = SYSTEM_CLOCK.ticks();
+        while SYSTEM_CLOCK.ticks() < ticks + 5 {
+            x86_64::instructions::hlt();
+        }
+    }
+    let counted = SYSTEM_CLOCK.uptime() - uptime;
+    let measured = start.elapsed();
+    // the ticks before and after lie off by at most a tick at the slowest rate.
+    let slack = Duration::from_millis(40);
+    assert!(counted < measured + slack, "{:?} {:?}", counted, measured);
+    assert!(measured < counted + slack, "{:?} {:?}", counted, measured);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
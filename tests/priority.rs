@@ -0,0 +1,198 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+use flario::kernel::task::executor::Executor;
+use flario::kernel::task::{self, budget, timer, Priority};
+use flario::kernel::thread;
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+#[test_case]
+fn higher_classes_run_first() {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let log = order.clone();
+    Executor::new()
+        .block_on(async move {
+            let handles: Vec<_> = [Priority::Background, Priority::Interactive, Priority::Io]
+                .iter()
+                .map(|&priority| {
+                    let log = log.clone();
+                    task::spawn_with_priority("ordered", priority, async move {
+                        log.lock().push(priority);
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        })
+        .unwrap();
+    assert_eq!(
+        *order.lock(),
+        [Priority::Io, Priority::Interactive, Priority::Background]
+    );
+}
+
+#[test_case]
+fn lower_classes_are_not_starved() {
+    Executor::new()
+        .block_on(async {
+            let stop = Arc::new(AtomicBool::new(false));
+            let busy = {
+                let stop = stop.clone();
+                task::spawn_with_priority("busy", Priority::Io, async move {
+                    while !stop.load(Ordering::SeqCst) {
+                        task::consume_budget().await;
+                    }
+                })
+            };
+            task::spawn_with_priority("background", Priority::Background, async move {
+                stop.store(true, Ordering::SeqCst);
+            })
+            .await
+            .unwrap();
+            busy.await.unwrap();
+        })
+        .unwrap();
+}
+
+#[test_case]
+fn middle_classes_are_not_starved() {
+    Executor::new()
+        .block_on(async {
+            let stop = Arc::new(AtomicBool::new(false));
+            let busy = |priority| {
+                let stop = stop.clone();
+                task::spawn_with_priority("busy", priority, async move {
+                    while !stop.load(Ordering::SeqCst) {
+                        task::consume_budget().await;
+                    }
+                })
+            };
+            let io = busy(Priority::Io);
+            let background = busy(Priority::Background);
+            let stopper = stop.clone();
+            task::spawn_with_priority("interactive", Priority::Interactive, async move {
+                stopper.store(true, Ordering::SeqCst);
+            })
+            .await
+            .unwrap();
+            io.await.unwrap();
+            background.await.unwrap();
+        })
+        .unwrap();
+}
+
+#[test_case]
+fn ready_loops_yield_once_the_budget_is_spent() {
+    Executor::new()
+        .block_on(async {
+            let ran = Arc::new(AtomicUsize::new(0));
+            let counter = {
+                let ran = ran.clone();
+                task::spawn("counter", async move {
+                    ran.fetch_add(1, Ordering::SeqCst);
+                })
+            };
+            // every sleep is ready at once, only the budget makes the loop yield.
+            for _ in 0..budget::POLL_BUDGET * 2 {
+                timer::sleep(Duration::ZERO).await;
+            }
+            assert_eq!(ran.load(Ordering::SeqCst), 1);
+            counter.await.unwrap();
+        })
+        .unwrap();
+}
+
+#[test_case]
+fn budget_is_set_while_polled() {
+    assert_eq!(budget::remaining(), None);
+    let remaining = Executor::new().block_on(async { budget::remaining() });
+    assert_eq!(remaining, Ok(Some(budget::POLL_BUDGET)));
+    assert_eq!(budget::remaining(), None);
+}
+
+#[test_case]
+fn budgets_are_per_thread() {
+    let ready = Arc::new(AtomicBool::new(false));
+    let done = Arc::new(AtomicBool::new(false));
+    let (seen, other) = Executor::new()
+        .block_on(async move {
+            for _ in 0..5 {
+                task::consume_budget().await;
+            }
+            let other = {
+                let (ready, done) = (ready.clone(), done.clone());
+                thread::spawn("other executor", move || {
+                    Executor::new()
+                        .block_on(async move {
+                            for _ in 0..10 {
+                                task::consume_budget().await;
+                            }
+                            ready.store(true, Ordering::SeqCst);
+                            // preempted in the middle of a poll.
+                            while !done.load(Ordering::SeqCst) {
+                                thread::yield_now();
+                            }
+                            budget::remaining()
+                        })
+                        .unwrap()
+                })
+                .unwrap()
+            };
+            while !ready.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+            let seen = budget::remaining();
+            done.store(true, Ordering::SeqCst);
+            (seen, other.join())
+        })
+        .unwrap();
+    assert_eq!(seen, Some(budget::POLL_BUDGET - 5));
+    assert_eq!(other, Some(budget::POLL_BUDGET - 10));
+}
+
+#[test_case]
+fn tasks_are_listed_with_priority() {
+    Executor::new()
+        .block_on(async {
+            let sleeper = task::spawn_with_priority(
+                "sleeper",
+                Priority::Background,
+                timer::sleep(Duration::from_millis(10)),
+            );
+            let info = task::list()
+                .into_iter()
+                .find(|info| info.name == "sleeper")
+                .unwrap();
+            assert_eq!(info.priority, Priority::Background);
+            sleeper.await.unwrap();
+        })
+        .unwrap();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
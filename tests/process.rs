@@ -0,0 +1,241 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use bootloader::{entry_point, BootInfo};
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use flario::kernel::environ::environmentref;
+use flario::kernel::fs::filesystemref;
+use flario::kernel::process::{self, State};
+use flario::kernel::status::Status;
+use flario::kernel::task::executor::Executor;
+use flario::kernel::task::{self, timer};
+use flario::kernel::thread;
+use flario::kernel::user;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+/// Defines `$name()`, returning the code assembled from `$line`s.
+macro_rules! program {
+    ($name:ident, $start:literal, $end:literal, [$($line:literal),* $(,)?]) => {
+        global_asm!(
+            concat!(".global ", $start),
+            concat!(".global ", $end),
+            concat!($start, ":"),
+            $($line,)*
+            concat!($end, ":"),
+        );
+
+        fn $name() -> &'static [u8] {
+            extern "C" {
+                #[link_name = $start]
+                static START: u8;
+                #[link_name = $end]
+                static END: u8;
+            }
+            unsafe {
+                let start = &START as *const u8;
+                let len = &END as *const u8 as usize - start as usize;
+                core::slice::from_raw_parts(start, len)
+            }
+        }
+    };
+}
+
+program!(
+    exit_5,
+    "test_process_exit_5_start",
+    "test_process_exit_5_end",
+    ["xor eax, eax", "mov edi, 5", "int 0x80", "ud2"]
+);
+
+// sets PROCESS_TEST=1 and exits with zero if getenv sees the one byte value.
+program!(
+    setenv,
+    "test_process_setenv_start",
+    "test_process_setenv_end",
+    [
+        "mov eax, 5",
+        "lea rdi, [rip + 2f]",
+        "mov esi, 12",
+        "lea rdx, [rip + 3f]",
+        "mov r10d, 1",
+        "int 0x80",
+        "sub rsp, 16",
+        "mov eax, 4",
+        "lea rdi, [rip + 2f]",
+        "mov esi, 12",
+        "mov rdx, rsp",
+        "mov r10d, 16",
+        "int 0x80",
+        "lea rdi, [rax - 1]",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+        "2: .ascii \"PROCESS_TEST\"",
+        "3: .ascii \"1\"",
+    ]
+);
+
+// changes to "pdir" and exits with zero if the working directory is now "/pdir".
+program!(
+    chdir,
+    "test_process_chdir_start",
+    "test_process_chdir_end",
+    [
+        "mov eax, 11",
+        "lea rdi, [rip + 2f]",
+        "mov esi, 4",
+        "int 0x80",
+        "sub rsp, 64",
+        "mov eax, 6",
+        "mov rdi, rsp",
+        "mov esi, 64",
+        "int 0x80",
+        "lea rdi, [rax - 5]",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+        "2: .ascii \"pdir\"",
+    ]
+);
+
+// reads "pfile" through a descriptor and exits with zero if it got five bytes, then none.
+program!(
+    read_file,
+    "test_process_read_start",
+    "test_process_read_end",
+    [
+        "mov eax, 12",
+        "lea rdi, [rip + 2f]",
+        "mov esi, 5",
+        "int 0x80",
+        "mov rbx, rax",
+        "sub rsp, 64",
+        "mov eax, 14",
+        "mov rdi, rbx",
+        "mov rsi, rsp",
+        "mov edx, 64",
+        "int 0x80",
+        "lea r12, [rax - 5]",
+        "mov eax, 14",
+        "mov rdi, rbx",
+        "mov rsi, rsp",
+        "mov edx, 64",
+        "int 0x80",
+        "lea rdi, [r12 + rax]",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+        "2: .ascii \"pfile\"",
+    ]
+);
+
+program!(
+    spin_then_exit_5,
+    "test_process_spin_start",
+    "test_process_spin_end",
+    [
+        "mov ecx, 100000000",
+        "2: dec rcx",
+        "jnz 2b",
+        "xor eax, eax",
+        "mov edi, 5",
+        "int 0x80",
+        "ud2",
+    ]
+);
+
+fn run(name: &str, code: &[u8]) -> Status {
+    process::wait(user::spawn(name, code).unwrap()).unwrap()
+}
+
+#[test_case]
+fn wait_collects_the_status_once() {
+    let pid = user::spawn("exit 5", exit_5()).unwrap();
+    assert_eq!(process::wait(pid), Some(Status::AlreadyExists));
+    assert_eq!(process::wait(pid), None);
+}
+
+#[test_case]
+fn waiting_tasks_let_the_others_run() {
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let counted = ticks.clone();
+    let status = Executor::new()
+        .block_on(async move {
+            let ticker = task::spawn("ticker", async move {
+                loop {
+                    timer::sleep(Duration::from_millis(1)).await;
+                    counted.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+            let pid = user::spawn("spin", spin_then_exit_5()).unwrap();
+            let status = process::wait_async(pid).await;
+            ticker.abort();
+            status
+        })
+        .unwrap();
+    assert_eq!(status, Some(Status::AlreadyExists));
+    assert!(ticks.load(Ordering::SeqCst) > 0);
+}
+
+#[test_case]
+fn exited_processes_stay_listed_until_waited_for() {
+    let pid = user::spawn("exit 5", exit_5()).unwrap();
+    let state = || {
+        process::list()
+            .into_iter()
+            .find(|info| info.pid == pid)
+            .map(|info| info.state)
+    };
+    while state() == Some(State::Running) {
+        thread::yield_now();
+    }
+    assert_eq!(state(), Some(State::Exited(Status::AlreadyExists)));
+
+    process::wait(pid);
+    assert_eq!(state(), None);
+}
+
+#[test_case]
+fn environment_changes_stay_in_the_process() {
+    assert_eq!(run("setenv", setenv()), Status::Success);
+    assert!(!environmentref().contains_entry("PROCESS_TEST"));
+}
+
+#[test_case]
+fn working_directory_is_per_process() {
+    filesystemref().create_dir("/pdir").unwrap();
+    assert_eq!(run("chdir", chdir()), Status::Success);
+    assert_eq!(environmentref().cwd(), "/");
+}
+
+#[test_case]
+fn files_are_read_through_descriptors() {
+    let fs = filesystemref();
+    let id = fs.create_file("/pfile").unwrap().id().unwrap();
+    fs.write(id, b"hello").unwrap();
+    assert_eq!(run("read", read_file()), Status::Success);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use flario::drivers::rtc::{self, DateTime};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+#[test_case]
+fn reads_a_valid_date() {
+    let now = rtc::read().expect("no valid date in the RTC");
+    assert!(now.year() >= 2020);
+    // the date is validated, so it survives a round trip.
+    assert_eq!(DateTime::from_timestamp(now.timestamp()), Some(now));
+}
+
+#[test_case]
+fn time_moves_forward() {
+    let first = rtc::read().unwrap();
+    let second = rtc::read().unwrap();
+    assert!(second >= first);
+}
+
+#[test_case]
+fn epoch_conversion() {
+    let epoch = DateTime::new(1970, 1, 1, 0, 0, 0).unwrap();
+    assert_eq!(epoch.timestamp(), 0);
+    assert_eq!(DateTime::from_timestamp(0), Some(epoch));
+
+    let leap_day = DateTime::new(2000, 2, 29, 12, 0, 0).unwrap();
+    assert_eq!(leap_day.timestamp(), 951_825_600);
+    assert_eq!(DateTime::from_timestamp(951_825_600), Some(leap_day));
+
+    let last = DateTime::from_timestamp(i32::MAX as u64);
+    assert_eq!(last, DateTime::new(2038, 1, 19, 3, 14, 7));
+    assert_eq!(
+        DateTime::from_timestamp(1_704_067_199),
+        DateTime::new(2023, 12, 31, 23, 59, 59)
+    );
+}
+
+#[test_case]
+fn far_timestamps_do_not_overflow() {
+    let last = DateTime::new(u16::MAX, 12, 31, 23, 59, 59).unwrap();
+    assert_eq!(DateTime::from_timestamp(last.timestamp()), Some(last));
+    assert_eq!(DateTime::from_timestamp(last.timestamp() + 1), None);
+    assert_eq!(DateTime::from_timestamp(u64::MAX), None);
+
+    // the first day after a 400 year cycle.
+    let date = DateTime::new(2370, 1, 1, 0, 0, 0).unwrap();
+    assert_eq!(date.timestamp(), 12_622_780_800);
+    assert_eq!(DateTime::from_timestamp(12_622_780_800), Some(date));
+}
+
+#[test_case]
+fn invalid_dates_are_rejected() {
+    assert_eq!(DateTime::new(2023, 2, 29, 0, 0, 0), None);
+    assert_eq!(DateTime::new(1900, 2, 28, 0, 0, 0), None);
+    assert_eq!(DateTime::new(2024, 13, 1, 0, 0, 0), None);
+    assert_eq!(DateTime::new(2024, 4, 31, 0, 0, 0), None);
+    assert_eq!(DateTime::new(2024, 1, 1, 24, 0, 0), None);
+    assert!(DateTime::new(2024, 2, 29, 23, 59, 59).is_some());
+}
+
+#[test_case]
+fn formats_as_iso_8601() {
+    use alloc::format;
+    let date = DateTime::new(2024, 2, 9, 3, 5, 7).unwrap();
+    assert_eq!(format!("{}", date), "2024-02-09 03:05:07");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
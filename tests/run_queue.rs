@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::Poll;
+use core::time::Duration;
+use flario::kernel::task::executor::Executor;
+use flario::kernel::task::{self, timer};
+use futures_util::future::poll_fn;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+/// More tasks than fit in the run queue.
+const TASKS: u64 = 5000;
+
+/// Lets the other tasks run once.
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+#[test_case]
+fn thousands_of_tasks_run() {
+    let sum = Executor::new().block_on(async {
+        let handles: Vec<_> = (0..TASKS)
+            .map(|i| {
+                task::spawn("stress", async move {
+                    yield_now().await;
+                    i
+                })
+            })
+            .collect();
+        let mut sum = 0;
+        for handle in handles {
+            sum += handle.await.unwrap();
+        }
+        sum
+    });
+    assert_eq!(sum, Ok(TASKS * (TASKS - 1) / 2));
+}
+
+#[test_case]
+fn tasks_woken_together_from_irq_run() {
+    let done = Arc::new(AtomicUsize::new(0));
+    let count = done.clone();
+    Executor::new()
+        .block_on(async move {
+            let handles: Vec<_> = (0..TASKS)
+                .map(|_| {
+                    let count = count.clone();
+                    task::spawn("sleeper", async move {
+                        // due at the same tick, woken by one timer IRQ.
+                        timer::sleep(Duration::from_millis(30)).await;
+                        count.fetch_add(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        })
+        .unwrap();
+    assert_eq!(done.load(Ordering::SeqCst), TASKS as usize);
+}
+
+#[test_case]
+fn repeated_wakes_poll_once() {
+    let polls = Arc::new(AtomicUsize::new(0));
+    let count = polls.clone();
+    Executor::new()
+        .block_on(async move {
+            let mut woken = false;
+            poll_fn(|cx| {
+                count.fetch_add(1, Ordering::SeqCst);
+                if woken {
+                    return Poll::Ready(());
+                }
+                woken = true;
+                for _ in 0..10_000 {
+                    cx.waker().wake_by_ref();
+                }
+                Poll::Pending
+            })
+            .await
+        })
+        .unwrap();
+    assert_eq!(polls.load(Ordering::SeqCst), 2);
+}
+
+#[test_case]
+fn aborting_a_queued_task() {
+    Executor::new()
+        .block_on(async {
+            let handle = task::spawn("pending", futures_util::future::pending::<()>());
+            handle.abort();
+            handle.abort();
+            assert_eq!(handle.await, Err(task::JoinError::Aborted));
+        })
+        .unwrap();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
@@ -0,0 +1,66 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use flario::kernel::mem::stack::{self, KernelStack};
+use flario::{drivers::qemu, vs_print, vs_println};
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    vs_print!("stack_guard...\t");
+    flario::init();
+    flario::mem_init(boot_info);
+    init_test_idt();
+
+    let stack = KernelStack::allocate("guard test", 4096).expect("stack allocation failed");
+    unsafe {
+        (stack.bottom().as_mut_ptr::<u64>()).write_volatile(1);
+        // one word below the stack lands on the guard page.
+        (stack.bottom() - 8u64)
+            .as_mut_ptr::<u64>()
+            .write_volatile(1);
+    }
+
+    vs_println!("[FAILED]");
+    qemu::exit_qemu(qemu::QemuExitCode::Failed);
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: PageFaultErrorCode,
+) {
+    use x86_64::registers::control::Cr2;
+
+    match stack::overflowed(Cr2::read()) {
+        Some("guard test") => {
+            vs_println!("[OK]");
+            qemu::exit_qemu(qemu::QemuExitCode::Success);
+        }
+        other => {
+            vs_println!("[FAILED] overflowed stack {:?}", other);
+            qemu::exit_qemu(qemu::QemuExitCode::Failed);
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
@@ -0,0 +1,206 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use flario::kernel::task::executor::Executor;
+use flario::kernel::task::{self, timer, Mutex, Notify, RwLock, Semaphore};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+#[test_case]
+fn mutex_is_held_across_await_points() {
+    let mutex = Arc::new(Mutex::new(0u64));
+    let shared = mutex.clone();
+    Executor::new()
+        .block_on(async move {
+            let handles: Vec<_> = (0..5)
+                .map(|_| {
+                    let mutex = shared.clone();
+                    task::spawn("adder", async move {
+                        let mut value = mutex.lock().await;
+                        let read = *value;
+                        // the other adders wait instead of reading the same value.
+                        timer::sleep(Duration::from_millis(2)).await;
+                        *value = read + 1;
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        })
+        .unwrap();
+    assert_eq!(*mutex.try_lock().unwrap(), 5);
+}
+
+#[test_case]
+fn mutex_is_taken_in_order() {
+    let order = Executor::new()
+        .block_on(async {
+            let mutex = Arc::new(Mutex::new(Vec::new()));
+            let guard = mutex.lock().await;
+            let handles: Vec<_> = (0..4)
+                .map(|i| {
+                    let mutex = mutex.clone();
+                    task::spawn("waiter", async move { mutex.lock().await.push(i) })
+                })
+                .collect();
+            timer::sleep(Duration::from_millis(5)).await;
+            assert!(mutex.try_lock().is_none());
+            drop(guard);
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            let order = mutex.lock().await.clone();
+            order
+        })
+        .unwrap();
+    assert_eq!(order, [0, 1, 2, 3]);
+}
+
+#[test_case]
+fn dropped_lock_futures_give_up_their_turn() {
+    Executor::new()
+        .block_on(async {
+            let mutex = Mutex::new(());
+            let guard = mutex.lock().await;
+            let waited = timer::timeout(Duration::from_millis(5), mutex.lock()).await;
+            assert!(waited.is_err());
+            drop(guard);
+            assert!(mutex.try_lock().is_some());
+        })
+        .unwrap();
+}
+
+#[test_case]
+fn semaphore_limits_concurrency() {
+    let peak = Arc::new(AtomicUsize::new(0));
+    let highest = peak.clone();
+    Executor::new()
+        .block_on(async move {
+            let semaphore = Arc::new(Semaphore::new(2));
+            let running = Arc::new(AtomicUsize::new(0));
+            let handles: Vec<_> = (0..6)
+                .map(|_| {
+                    let (semaphore, running, peak) =
+                        (semaphore.clone(), running.clone(), highest.clone());
+                    task::spawn("limited", async move {
+                        let _permit = semaphore.acquire().await;
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        timer::sleep(Duration::from_millis(2)).await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            assert_eq!(semaphore.available_permits(), 2);
+        })
+        .unwrap();
+    assert_eq!(peak.load(Ordering::SeqCst), 2);
+}
+
+#[test_case]
+fn semaphore_permits_can_be_forgotten_and_added() {
+    let semaphore = Semaphore::new(3);
+    semaphore.try_acquire_many(2).unwrap().forget();
+    assert_eq!(semaphore.available_permits(), 1);
+    assert!(semaphore.try_acquire_many(2).is_none());
+    semaphore.add_permits(2);
+    let permit = semaphore.try_acquire_many(3).unwrap();
+    assert_eq!(permit.num_permits(), 3);
+    drop(permit);
+    assert_eq!(semaphore.available_permits(), 3);
+}
+
+#[test_case]
+fn rwlock_shares_reads_and_excludes_writes() {
+    Executor::new()
+        .block_on(async {
+            let lock = Arc::new(RwLock::new(1));
+            let first = lock.read().await;
+            let second = lock.read().await;
+            assert_eq!(*first + *second, 2);
+            assert!(lock.try_write().is_none());
+
+            let writer = {
+                let lock = lock.clone();
+                task::spawn("writer", async move { *lock.write().await = 2 })
+            };
+            timer::sleep(Duration::from_millis(5)).await;
+            // the waiting writer comes before new readers.
+            assert!(lock.try_read().is_none());
+            drop((first, second));
+            writer.await.unwrap();
+            assert_eq!(*lock.read().await, 2);
+        })
+        .unwrap();
+}
+
+#[test_case]
+fn notify_one_is_kept_for_the_next_waiter() {
+    Executor::new()
+        .block_on(async {
+            let notify = Notify::new();
+            notify.notify_one();
+            let notified = timer::timeout(Duration::from_millis(5), notify.notified()).await;
+            assert!(notified.is_ok());
+            let notified = timer::timeout(Duration::from_millis(5), notify.notified()).await;
+            assert!(notified.is_err());
+        })
+        .unwrap();
+}
+
+#[test_case]
+fn notify_waiters_wakes_every_waiter() {
+    Executor::new()
+        .block_on(async {
+            let notify = Arc::new(Notify::new());
+            let woken = Arc::new(AtomicUsize::new(0));
+            let handles: Vec<_> = (0..3)
+                .map(|_| {
+                    let (notify, woken) = (notify.clone(), woken.clone());
+                    task::spawn("waiter", async move {
+                        notify.notified().await;
+                        woken.fetch_add(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+            timer::sleep(Duration::from_millis(5)).await;
+            notify.notify_waiters();
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            assert_eq!(woken.load(Ordering::SeqCst), 3);
+            // no permit is left behind.
+            let notified = timer::timeout(Duration::from_millis(5), notify.notified()).await;
+            assert!(notified.is_err());
+        })
+        .unwrap();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
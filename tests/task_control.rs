@@ -0,0 +1,167 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use bootloader::{entry_point, BootInfo};
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+use core::time::Duration;
+use flario::kernel::task::executor::Executor;
+use flario::kernel::task::{self, isolate, timer, CancellationToken, JoinError};
+use futures_util::future::poll_fn;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+/// Sets its flag when dropped.
+struct DropFlag(Arc<AtomicBool>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn aborted_tasks_are_dropped() {
+    let dropped = Arc::new(AtomicBool::new(false));
+    let flag = DropFlag(dropped.clone());
+    let output = Executor::new().block_on(async move {
+        let handle = task::spawn("stuck", async move {
+            let _flag = flag;
+            timer::sleep(Duration::from_secs(60)).await;
+        });
+        timer::sleep(Duration::from_millis(10)).await;
+        handle.abort();
+        handle.await
+    });
+    assert_eq!(output, Ok(Err(JoinError::Aborted)));
+    assert!(dropped.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn abort_handles_work_from_other_tasks() {
+    let output = Executor::new().block_on(async {
+        let handle = task::spawn("stuck", timer::sleep(Duration::from_secs(60)));
+        let abort = handle.abort_handle();
+        task::spawn("killer", async move { abort.abort() });
+        handle.await
+    });
+    assert_eq!(output, Ok(Err(JoinError::Aborted)));
+}
+
+#[test_case]
+fn aborting_completed_tasks_does_nothing() {
+    let output = Executor::new().block_on(async {
+        let handle = task::spawn("quick", async { 3 });
+        timer::sleep(Duration::from_millis(10)).await;
+        handle.abort();
+        handle.await
+    });
+    assert_eq!(output, Ok(Ok(3)));
+}
+
+#[test_case]
+fn cancelled_tasks_wind_down() {
+    let output = Executor::new().block_on(async {
+        let token = CancellationToken::new();
+        let worker = {
+            let token = token.clone();
+            task::spawn("worker", async move {
+                let mut rounds = 0;
+                while !token.is_cancelled() {
+                    timer::sleep(Duration::from_millis(5)).await;
+                    rounds += 1;
+                }
+                rounds
+            })
+        };
+        let waiter = task::spawn("waiter", token.cancelled());
+        timer::sleep(Duration::from_millis(30)).await;
+        token.cancel();
+        (worker.await, waiter.await)
+    });
+    let (worker, waiter) = output.unwrap();
+    assert!(worker.unwrap() > 0);
+    assert_eq!(waiter, Ok(()));
+}
+
+#[test_case]
+fn children_follow_their_parent() {
+    let parent = CancellationToken::new();
+    let child = parent.child_token();
+    let sibling = parent.child_token();
+
+    child.cancel();
+    assert!(child.is_cancelled());
+    assert!(!parent.is_cancelled());
+    assert!(!sibling.is_cancelled());
+
+    parent.cancel();
+    assert!(sibling.is_cancelled());
+    assert!(parent.child_token().is_cancelled());
+}
+
+#[test_case]
+fn panics_fail_only_their_task() {
+    let mut executor = Executor::new();
+    executor.isolate_panics(true);
+    let output = executor.block_on(async {
+        let sibling = task::spawn("sibling", async {
+            timer::sleep(Duration::from_millis(20)).await;
+            9
+        });
+        let faulty = task::spawn("faulty", async {
+            timer::sleep(Duration::from_millis(5)).await;
+            panic!("task failure");
+        });
+        (faulty.await, sibling.await)
+    });
+    assert_eq!(output, Ok((Err(JoinError::Panicked), Ok(9))));
+
+    // the executor keeps going afterwards.
+    assert_eq!(executor.block_on(async { 1 }), Ok(1));
+}
+
+#[test_case]
+fn panicked_tasks_leave_no_timers() {
+    let mut executor = Executor::new();
+    executor.isolate_panics(true);
+    let before = timer::pending();
+    let output = executor.block_on(async {
+        task::spawn("faulty", async {
+            let mut pending = timer::sleep(Duration::from_secs(60));
+            poll_fn(|cx| {
+                assert!(Pin::new(&mut pending).poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+            panic!("task failure");
+        })
+        .await
+    });
+    assert_eq!(output, Ok(Err(JoinError::Panicked)));
+    assert_eq!(timer::pending(), before);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    isolate::recover(info);
+    flario::test_panic_handler(info)
+}
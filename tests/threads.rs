@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use flario::kernel::task::executor::Executor;
+use flario::kernel::thread;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+#[test_case]
+fn join_returns_result() {
+    let handle = thread::spawn("sum", || (1..=100u64).sum::<u64>()).unwrap();
+    assert_eq!(handle.join(), 5050);
+}
+
+#[test_case]
+fn busy_threads_are_preempted() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    // never yields, so the boot thread only gets to run again through preemption.
+    let spinner = thread::spawn("spinner", || {
+        while !DONE.load(Ordering::SeqCst) {
+            core::hint::spin_loop();
+        }
+    })
+    .unwrap();
+
+    let start = thread::ticks();
+    while thread::ticks() < start + 3 {
+        core::hint::spin_loop();
+    }
+    DONE.store(true, Ordering::SeqCst);
+    spinner.join();
+}
+
+#[test_case]
+fn sleep_waits_for_ticks() {
+    let start = thread::ticks();
+    thread::sleep(2);
+    assert!(thread::ticks() >= start + 2);
+}
+
+#[test_case]
+fn threads_take_turns() {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let handles = [
+        thread::spawn("a", || count_with_yields(&COUNTER)).unwrap(),
+        thread::spawn("b", || count_with_yields(&COUNTER)).unwrap(),
+        thread::spawn("c", || count_with_yields(&COUNTER)).unwrap(),
+    ];
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(COUNTER.load(Ordering::SeqCst), 300);
+}
+
+fn count_with_yields(counter: &AtomicU64) {
+    for _ in 0..100 {
+        counter.fetch_add(1, Ordering::SeqCst);
+        thread::yield_now();
+    }
+}
+
+#[test_case]
+fn detached_threads_are_reaped() {
+    for _ in 0..100 {
+        let handle = thread::spawn("detached", || {}).unwrap();
+        drop(handle);
+        thread::yield_now();
+    }
+    // all but a few finished detached threads are gone from the table again.
+    let handle = thread::spawn("last", || (thread::current(), thread::name())).unwrap();
+    let id = handle.id();
+    assert_eq!(handle.join(), (id, "last"));
+}
+
+#[test_case]
+fn tasks_wait_for_threads_to_finish() {
+    let handle = thread::spawn("slow", || {
+        thread::sleep(10);
+        7
+    })
+    .unwrap();
+    let handle = Executor::new()
+        .block_on(async move {
+            handle.finished().await;
+            handle
+        })
+        .unwrap();
+    assert!(handle.is_finished());
+    assert_eq!(handle.join(), 7);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
@@ -0,0 +1,132 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use bootloader::{entry_point, BootInfo};
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use flario::kernel::sc::Instant;
+use flario::kernel::task::executor::Executor;
+use flario::kernel::task::timer::{self, Elapsed};
+use futures_util::task::noop_waker_ref;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn sleep_waits_for_the_duration() {
+    let start = Instant::now();
+    Executor::new()
+        .block_on(timer::sleep(Duration::from_millis(30)))
+        .unwrap();
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(30), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(200), "{:?}", elapsed);
+}
+
+#[test_case]
+fn wheel_wakes_the_task() {
+    let flag = Arc::new(Flag(AtomicBool::new(false)));
+    let waker = Waker::from(flag.clone());
+    let mut context = Context::from_waker(&waker);
+
+    let mut sleep = timer::sleep(Duration::from_millis(20));
+    assert_eq!(Pin::new(&mut sleep).poll(&mut context), Poll::Pending);
+    while !flag.0.load(Ordering::SeqCst) {
+        x86_64::instructions::hlt();
+    }
+    // woken by the tick the deadline falls in, which may be just short of it.
+    Executor::new().block_on(sleep).unwrap();
+}
+
+#[test_case]
+fn timeout_passes_results_through() {
+    let result = Executor::new()
+        .block_on(timer::timeout(Duration::from_secs(1), async { 5 }))
+        .unwrap();
+    assert_eq!(result, Ok(5));
+}
+
+#[test_case]
+fn timeout_elapses() {
+    let start = Instant::now();
+    let slow = timer::sleep(Duration::from_secs(10));
+    let result = Executor::new()
+        .block_on(timer::timeout(Duration::from_millis(20), slow))
+        .unwrap();
+    assert_eq!(result, Err(Elapsed));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test_case]
+fn interval_ticks_periodically() {
+    let period = Duration::from_millis(20);
+    let start = Instant::now();
+    let (first, second) = Executor::new()
+        .block_on(async move {
+            let mut interval = timer::interval(period);
+            (interval.tick().await, interval.tick().await)
+        })
+        .unwrap();
+    assert!(first >= start + period);
+    assert_eq!(second - first, period);
+    assert!(Instant::now() >= second);
+}
+
+#[test_case]
+fn dropped_timers_leave_the_wheel() {
+    let before = timer::pending();
+    let mut context = Context::from_waker(noop_waker_ref());
+    let mut sleeps: alloc::vec::Vec<_> = (1..=10)
+        .map(|i| timer::sleep(Duration::from_secs(i)))
+        .collect();
+    for sleep in sleeps.iter_mut() {
+        assert_eq!(Pin::new(sleep).poll(&mut context), Poll::Pending);
+    }
+    assert_eq!(timer::pending(), before + 10);
+    drop(sleeps);
+    assert_eq!(timer::pending(), before);
+}
+
+#[test_case]
+fn purged_timers_leave_the_wheel() {
+    let waker = Waker::from(Arc::new(Flag(AtomicBool::new(false))));
+    let before = timer::pending();
+    let mut sleep = timer::sleep(Duration::from_secs(1));
+    let mut context = Context::from_waker(&waker);
+    assert_eq!(Pin::new(&mut sleep).poll(&mut context), Poll::Pending);
+    assert_eq!(timer::pending(), before + 1);
+    assert_eq!(timer::purge(&waker), Some(1));
+    assert_eq!(timer::pending(), before);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
@@ -0,0 +1,175 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use flario::kernel::process;
+use flario::kernel::status::Status;
+use flario::kernel::user::{self, programs};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+/// Defines `$name()`, returning the code assembled from `$line`s.
+macro_rules! program {
+    ($name:ident, $start:literal, $end:literal, [$($line:literal),* $(,)?]) => {
+        global_asm!(
+            concat!(".global ", $start),
+            concat!(".global ", $end),
+            concat!($start, ":"),
+            $($line,)*
+            concat!($end, ":"),
+        );
+
+        fn $name() -> &'static [u8] {
+            extern "C" {
+                #[link_name = $start]
+                static START: u8;
+                #[link_name = $end]
+                static END: u8;
+            }
+            unsafe {
+                let start = &START as *const u8;
+                let len = &END as *const u8 as usize - start as usize;
+                core::slice::from_raw_parts(start, len)
+            }
+        }
+    };
+}
+
+program!(
+    exit_5,
+    "test_exit_5_start",
+    "test_exit_5_end",
+    ["xor eax, eax", "mov edi, 5", "int 0x80", "ud2",]
+);
+
+// exits with a code no status has, which fails and falls through to `ud2`.
+program!(
+    exit_42,
+    "test_exit_42_start",
+    "test_exit_42_end",
+    ["xor eax, eax", "mov edi, 42", "int 0x80", "ud2",]
+);
+
+program!(
+    privileged,
+    "test_privileged_start",
+    "test_privileged_end",
+    ["cli", "ud2"]
+);
+
+program!(
+    read_kernel,
+    "test_read_kernel_start",
+    "test_read_kernel_end",
+    ["mov rax, 0x444444440000", "mov rax, [rax]", "ud2",]
+);
+
+// exits with the result of writing from a kernel address.
+program!(
+    bad_pointer,
+    "test_bad_pointer_start",
+    "test_bad_pointer_end",
+    [
+        "mov eax, 1",
+        "mov rdi, 0x444444440000",
+        "mov esi, 8",
+        "int 0x80",
+        "mov rdi, rax",
+        "neg rdi",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+    ]
+);
+
+// exits with the length of the working directory.
+program!(
+    getcwd,
+    "test_getcwd_start",
+    "test_getcwd_end",
+    [
+        "sub rsp, 64",
+        "mov eax, 6",
+        "mov rdi, rsp",
+        "mov esi, 64",
+        "int 0x80",
+        "mov rdi, rax",
+        "xor eax, eax",
+        "int 0x80",
+        "ud2",
+    ]
+);
+
+fn free_frames() -> usize {
+    flario::kernel::mem::stats().frames.unwrap().free
+}
+
+fn run(name: &str, code: &[u8]) -> Status {
+    process::wait(user::spawn(name, code).unwrap()).unwrap()
+}
+
+#[test_case]
+fn hello_exits_cleanly() {
+    let (name, code) = programs::find("hello").unwrap();
+    assert_eq!(run(name, code), Status::Success);
+}
+
+#[test_case]
+fn exit_code_is_returned() {
+    assert_eq!(run("exit 5", exit_5()), Status::AlreadyExists);
+}
+
+#[test_case]
+fn invalid_exit_code_is_rejected() {
+    assert_eq!(run("exit 42", exit_42()), Status::Killed);
+}
+
+#[test_case]
+fn privileged_instruction_kills_program() {
+    assert_eq!(run("cli", privileged()), Status::Killed);
+}
+
+#[test_case]
+fn kernel_memory_is_not_accessible() {
+    assert_eq!(run("read kernel", read_kernel()), Status::Killed);
+}
+
+#[test_case]
+fn syscalls_reject_kernel_pointers() {
+    assert_eq!(run("bad pointer", bad_pointer()), Status::PermissionDenied);
+}
+
+#[test_case]
+fn syscalls_reach_environment() {
+    // the working directory starts out as "/", one byte long.
+    assert_eq!(run("getcwd", getcwd()), Status::NotFound);
+}
+
+#[test_case]
+fn program_memory_is_freed() {
+    let free = free_frames();
+    for _ in 0..10 {
+        run("exit 5", exit_5());
+    }
+    // page tables created for the first program stay around.
+    assert!(free_frames() + 8 >= free);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}
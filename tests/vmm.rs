@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flario::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use flario::kernel::mem::region::Backing;
+use flario::kernel::mem::stack::{self, KernelStack};
+use flario::kernel::mem::vmm::{self, VmmGuard, VMALLOC_END, VMALLOC_START};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageTableFlags};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use flario::*;
+    init();
+    mem_init(boot_info);
+
+    test_main();
+    halt();
+}
+
+fn vmm() -> VmmGuard {
+    vmm::vmm().expect("memory not initialized")
+}
+
+const FLAGS: PageTableFlags = PageTableFlags::PRESENT.union(PageTableFlags::WRITABLE);
+
+#[test_case]
+fn allocate_maps_zeroed_pages() {
+    let region = vmm()
+        .allocate("test", 3 * 4096 - 100, FLAGS, Backing::Allocated)
+        .unwrap();
+    assert_eq!(region.size(), 3 * 4096);
+    assert!(region.start >= VirtAddr::new(VMALLOC_START));
+    assert!(region.end <= VirtAddr::new(VMALLOC_END));
+    assert_eq!(
+        vmm::query(region.start + 4096u64).map(|r| r.name),
+        Some("test")
+    );
+
+    let ptr = region.start.as_mut_ptr::<u64>();
+    unsafe {
+        assert_eq!(ptr.add(1000).read_volatile(), 0);
+        ptr.add(1000).write_volatile(42);
+        assert_eq!(ptr.add(1000).read_volatile(), 42);
+    }
+
+    vmm().unmap(region.start).unwrap();
+}
+
+#[test_case]
+fn unmap_returns_frames() {
+    let free = vmm().frame_allocator().free_frames();
+    let region = vmm()
+        .allocate("frames", 8 * 4096, FLAGS, Backing::Allocated)
+        .unwrap();
+    assert!(vmm().frame_allocator().free_frames() <= free - 8);
+
+    vmm().unmap(region.start).unwrap();
+    assert!(vmm::query(region.start).is_none());
+    assert!(vmm().translate(region.start).is_none());
+    // page tables created for the mapping stay around.
+    assert!(vmm().frame_allocator().free_frames() >= free - 3);
+}
+
+#[test_case]
+fn allocations_do_not_overlap() {
+    let a = vmm().allocate("a", 4096, FLAGS, Backing::Demand).unwrap();
+    let b = vmm().allocate("b", 4096, FLAGS, Backing::Demand).unwrap();
+    assert!(!a.overlaps(&b));
+
+    vmm().unmap(a.start).unwrap();
+    let c = vmm().allocate("c", 4096, FLAGS, Backing::Demand).unwrap();
+    assert_eq!(c.start, a.start);
+
+    vmm().unmap(b.start).unwrap();
+    vmm().unmap(c.start).unwrap();
+}
+
+#[test_case]
+fn protect_changes_flags() {
+    let region = vmm()
+        .allocate("protect", 4096, FLAGS, Backing::Allocated)
+        .unwrap();
+    vmm()
+        .protect(region.start, PageTableFlags::PRESENT)
+        .unwrap();
+    assert_eq!(
+        vmm::query(region.start).map(|r| r.flags),
+        Some(PageTableFlags::PRESENT)
+    );
+    // still readable.
+    unsafe { assert_eq!(region.start.as_ptr::<u64>().read_volatile(), 0) };
+
+    vmm().unmap(region.start).unwrap();
+}
+
+#[test_case]
+fn map_physical_aliases_frame() {
+    let frame = vmm().frame_allocator().allocate_frame().unwrap();
+    let phys = frame.start_address() + 16u64;
+    let virt = vmm().map_physical("alias", phys, 64, FLAGS).unwrap();
+    assert_eq!(vmm().translate(virt), Some(phys));
+
+    vmm().unmap(virt.align_down(4096u64)).unwrap();
+    // the frame belongs to us, not to the mapping.
+    assert!(vmm().frame_allocator().is_used(frame));
+    unsafe { vmm().frame_allocator().deallocate_frame(frame) };
+}
+
+#[test_case]
+fn stack_has_unmapped_guard_page() {
+    let stack = KernelStack::allocate("stack", 2 * 4096).unwrap();
+    assert_eq!(stack.top() - stack.bottom(), 2 * 4096);
+    assert_eq!(stack.bottom() - stack.guard(), 4096);
+    assert!(vmm().translate(stack.guard()).is_none());
+    assert!(vmm().translate(stack.bottom()).is_some());
+    assert_eq!(stack::overflowed(stack.guard() + 8u64), Some("stack"));
+    assert_eq!(stack::overflowed(stack.bottom()), None);
+
+    unsafe { stack.free().unwrap() };
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flario::test_panic_handler(info)
+}